@@ -1,17 +1,21 @@
-use std::thread::JoinHandle;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant, SystemTime};
 use tui::style::{Style, Color};
 use tui::text::{Spans, Span};
 use tui::widgets::ListItem;
-use crossterm::event::{self, Event};
+use crossterm::event::{Event, EventStream, KeyCode, KeyModifiers};
+use futures::StreamExt;
 use tui::{
     backend::Backend,
     Terminal,
 };
-use std::sync::mpsc::{self, Sender, Receiver};
-use tokio::sync::mpsc::{self as async_mpsc, Sender as AsyncSender, Receiver as AsyncReceiver};
+use tokio::sync::mpsc::Receiver;
 
-use crate::test::env_setup::check_env;
+use crate::event::{event_bus, Publisher};
+use crate::hotplug;
+use crate::module_catalog::Catalog;
+use crate::usb::{Sysfs, UsbDevice};
+
+use crate::test::{env_setup::check_env, job::{reap, Job}, release::Release, setup_error::SetupStep, setup_progress::SetupProgress};
 
 use super::ui_selection::*;
 use super::jetson::*;
@@ -22,47 +26,128 @@ pub enum InstallStatus {
     NotInstalled,
     Installing(SystemTime),
     Installed,
+    Failed { step: SetupStep, reason: String },
+    Cancelled { step: SetupStep },
+}
+
+// what the cancel dialog is asking about
+#[derive(Debug, Clone, PartialEq)]
+pub enum CancelTarget {
+    Install,
+    Massflash,
+    // a single device flash, by USB instance
+    Device(String),
 }
 
+// how long running jobs get to clean up when the program quits
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(3);
+
 pub struct App<'a> {
     pub titles: Vec<&'a str>,
     pub index: usize,
     pub selection: UISelectionModel,
     pub devlist: Vec<Jetson>,
-    pub installer: Option<JoinHandle<()>>,
-    pub flash_handle: Option<JoinHandle<()>>,
+    pub installer: Option<Job>,
+    pub flash_job: Option<Job>,
+    pub cancel_target: Option<CancelTarget>,
     pub main_terminal: Logger,
     pub refreshing: bool,
     pub install_status: InstallStatus,
     pub flash_status: FlashStatus,
-    pub async_tx: AsyncSender<Signal>,
-    pub async_rx: AsyncReceiver<Signal>,
-    pub tx: Sender<Signal>,
+    pub dtb_report: Vec<String>,
+    pub dtb_report_scroll: u16,
+    // last progress of the installer, kept after a failure for the checklist
+    pub setup_progress: Option<SetupProgress>,
+    pub releases: Vec<Release>,
+    // the modules refresh_devlist recognises
+    pub catalog: Catalog,
+    // where USB devices are read from
+    pub sysfs: Sysfs,
+    // index into releases of the one F6 installs and flashing uses
+    pub release: usize,
+    // the event bus every job reports on
+    pub tx: Publisher,
     pub rx: Receiver<Signal>,
 }
 
+impl<'a> Default for App<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// run_app shuts the jobs down, this is for when it did not get to, e.g. after a panic: no
+// flash script or download is left running behind the program
+impl<'a> Drop for App<'a> {
+    fn drop(&mut self) {
+        for job in self.take_jobs() {
+            job.kill();
+        }
+    }
+}
+
 impl<'a> App<'a> {
     pub fn new() -> App<'a> {
-        let (async_tx, async_rx) = async_mpsc::channel(4096);
-        let (tx, rx) = mpsc::channel();
+        let (tx, rx) = event_bus();
+        let releases = match Release::load_all(".") {
+            Ok(releases) => releases,
+            Err(e) => {
+                tx.post(Signal::Message(format!("Cannot load release descriptors: {}\n", e)));
+                vec![]
+            }
+        };
+        let catalog = match Catalog::load(".") {
+            Ok(catalog) => catalog,
+            Err(e) => {
+                tx.post(Signal::Message(format!("Cannot load the module catalog: {}\n", e)));
+                Catalog::default()
+            }
+        };
         App {
-            titles: vec!["Main", "ModeSelect", "Quit"],
+            titles: vec!["Main", "ModeSelect", "Quit", "DeviceTreeDiff", "CancelJob"],
             index: 0,
             selection: UISelectionModel { focused: UISelection::DeviceList(None), current: UISelection::DeviceList(None) },
             devlist: vec![],
             installer: None,
-            flash_handle: None,
+            flash_job: None,
+            cancel_target: None,
             main_terminal: Logger::new("Main"),
             refreshing: false,
             install_status: InstallStatus::NotInstalled,
             flash_status: FlashStatus::Wait,
-            async_tx,
-            async_rx,
+            dtb_report: vec![],
+            dtb_report_scroll: 0,
+            setup_progress: None,
+            // the newest release by default
+            release: releases.len().saturating_sub(1),
+            releases,
+            catalog,
+            sysfs: Sysfs::default(),
             tx,
             rx,
         }
     }
 
+    pub fn current_release(&self) -> Option<&Release> {
+        self.releases.get(self.release)
+    }
+
+    // switch to the next release and re-check whether its environment is installed
+    pub fn next_release(&mut self) {
+        if self.releases.is_empty() {
+            return;
+        }
+        // the installer writes into the current release's directory
+        if self.installer.is_some() {
+            self.tx.post(Signal::Message(String::from("Cannot switch release while the environment is being installed\n")));
+            return;
+        }
+        self.release = (self.release + 1) % self.releases.len();
+        self.install_status = InstallStatus::NotInstalled;
+        self.setup_progress = None;
+        check_env(self.tx.clone(), &self.releases[self.release]);
+    }
+
     pub fn selected_device_index(&self) -> Option<usize> {
         if let UISelection::DeviceList(Some(index)) = self.selection.current {
             return Some(index);
@@ -70,11 +155,62 @@ impl<'a> App<'a> {
         None
     }
 
-    pub fn create_new_async_publisher(&self) -> AsyncSender<Signal> {
-        self.async_tx.clone()
+    pub fn has_running_job(&self) -> bool {
+        self.installer.is_some() || self.flash_job.is_some() || self.devlist.iter().any(|jetson| jetson.flash_job.is_some())
+    }
+
+    // The job the cancel key is about: the selected device's own flash, else the massflash,
+    // else the installer
+    pub fn cancel_target(&self) -> Option<CancelTarget> {
+        if let Some(index) = self.selected_device_index() {
+            let jetson = &self.devlist[index];
+            if jetson.flash_job.is_some() {
+                return Some(CancelTarget::Device(jetson.instance_number.clone()));
+            }
+        }
+        if self.flash_job.is_some() {
+            return Some(CancelTarget::Massflash);
+        }
+        if self.installer.is_some() {
+            return Some(CancelTarget::Install);
+        }
+        None
+    }
+
+    pub fn cancel(&mut self, target: &CancelTarget) {
+        let job = match target {
+            CancelTarget::Install => self.installer.as_ref(),
+            CancelTarget::Massflash => self.flash_job.as_ref(),
+            CancelTarget::Device(instance) => {
+                self.devlist.iter().find(|jetson| &jetson.instance_number == instance).and_then(|jetson| jetson.flash_job.as_ref())
+            },
+        };
+        if let Some(job) = job {
+            job.cancel();
+        }
+    }
+
+    // Stop every job and its processes, so that nothing keeps flashing or writing the
+    // workspace once the program is gone
+    pub async fn shutdown(&mut self) {
+        let jobs = self.take_jobs();
+        for job in &jobs {
+            job.cancel();
+        }
+        let deadline = Instant::now() + SHUTDOWN_GRACE;
+        for job in jobs {
+            job.terminate(deadline.saturating_duration_since(Instant::now())).await;
+        }
+    }
+
+    fn take_jobs(&mut self) -> Vec<Job> {
+        self.installer.take().into_iter()
+            .chain(self.flash_job.take())
+            .chain(self.devlist.iter_mut().filter_map(|jetson| jetson.flash_job.take()))
+            .collect()
     }
 
-    pub fn create_new_publisher(&self) -> Sender<Signal> {
+    pub fn create_new_publisher(&self) -> Publisher {
         self.tx.clone()
     }
 
@@ -124,19 +260,63 @@ impl<'a> App<'a> {
         }
     }
 
-    pub fn clear_devlist(&mut self) {
-        let mut filtered = vec![];
+    // Something answering on a port: a board plugged in, or a listed one re-enumerating as it
+    // goes from recovery mode through the flashing initrd to booted L4T. Returns what changed,
+    // None for devices that are not Jetson boards.
+    pub fn add_device(&mut self, device: UsbDevice) -> Option<String> {
+        let stage = self.catalog.stage(&device.product)?;
+        let module = self.catalog.find(&device.product).cloned();
+        let flashing = self.flash_job.is_some();
 
-        while !self.devlist.is_empty() {
-            let item = self.devlist.pop().unwrap();
-            if item.is_flashed() || item.is_flashing() {
-                filtered.push(item);
+        let line = match self.devlist.iter().position(|listed| listed.instance_number == device.port) {
+            Some(index) => {
+                let listed = &mut self.devlist[index];
+                if stage == Stage::Recovery && !flashing && !listed.is_flashing() && listed.flash_job.is_none() {
+                    // back in recovery mode, to be flashed again, or another board in the slot
+                    *listed = Jetson::from_usb(&device, module);
+                } else {
+                    listed.update_usb(&device);
+                    listed.stage = stage;
+                }
+                format!("{} on port {}: {}\n", listed.module_name, device.port, stage)
+            },
+            None => {
+                let mut jetson = Jetson::from_usb(&device, module);
+                jetson.stage = stage;
+                let line = format!("{} connected on port {} ({})\n", jetson.module_name, device.port, stage);
+                self.devlist.push(jetson);
+                line
             }
+        };
+
+        if self.selection.current == UISelection::DeviceList(None) {
+            self.change_current(UISelection::DeviceList(Some(0)));
         }
-        
-        while !filtered.is_empty() {
-            self.devlist.push(filtered.pop().unwrap());
+        Some(line)
+    }
+
+    // Nothing answers on the port any more. A board being flashed or flashed stays listed, it is
+    // only rebooting into its next stage; any other is dropped.
+    pub fn remove_device(&mut self, port: &str) -> Option<String> {
+        let flashing = self.flash_job.is_some();
+        let index = self.devlist.iter().position(|jetson| jetson.instance_number == port)?;
+        let jetson = &mut self.devlist[index];
+        if flashing || jetson.is_busy() {
+            jetson.stage = Stage::Disconnected;
+            return Some(format!("{} on port {}: {}\n", jetson.module_name, port, jetson.stage));
+        }
+        let jetson = self.devlist.remove(index);
+
+        // keep the selection on the same device, or on the last one
+        if let UISelection::DeviceList(Some(selected)) = self.selection.current {
+            let selected = match self.devlist.is_empty() {
+                true => None,
+                false if selected > index || selected == self.devlist.len() => Some(selected - 1),
+                false => Some(selected),
+            };
+            self.change_current(UISelection::DeviceList(selected));
         }
+        Some(format!("{} disconnected from port {}\n", jetson.module_name, port))
     }
 
     pub fn select(&mut self, new: UISelectionModel) {
@@ -151,18 +331,37 @@ impl<'a> App<'a> {
         self.selection.current = current;
     }
 
-    pub fn list(&self) -> Vec<ListItem> {
+    pub fn list(&self) -> Vec<ListItem<'_>> {
 
         let mut ret = vec![];
 
         for (index, jetson) in self.devlist.iter().enumerate() {
 
-            let line = jetson.to_string();
-            
+            let mut line = match jetson.status {
+                FlashStatus::Wait => jetson.to_string(),
+                FlashStatus::Flashing => jetson.to_string() + " [flashing]",
+                FlashStatus::Finished => jetson.to_string() + " [done]",
+                FlashStatus::Failed => jetson.to_string() + " [failed]",
+                FlashStatus::Cancelled => jetson.to_string() + " [cancelled]",
+            };
+            if let Some(identity) = jetson.identity() {
+                line = line + " [" + &identity + "]";
+            }
+            if jetson.stage != Stage::Recovery {
+                line = line + " [" + &jetson.stage.to_string() + "]";
+            }
+            let badge = jetson.link_badge();
+            if let Some(badge) = &badge {
+                line = line + " " + badge;
+            }
+
             let style = match self.selection.current {
                 UISelection::DeviceList(Some(dev_index)) if index == dev_index => {
                     Style::default().fg(Color::Black).bg(Color::White)
                 },
+                _ if badge.is_some() => {
+                    Style::default().fg(Color::Yellow)
+                },
                 _ => {
                     Style::default()
                 }
@@ -177,89 +376,193 @@ impl<'a> App<'a> {
 }
 
 pub async fn run_app<B: Backend>(terminal: &mut Terminal<B>, mut app: App<'static>) -> Result<(), Box<dyn std::error::Error>> {
+    let result = event_loop(terminal, &mut app).await;
+    app.shutdown().await;
+    result
+}
+
+async fn event_loop<B: Backend>(terminal: &mut Terminal<B>, app: &mut App<'static>) -> Result<(), Box<dyn std::error::Error>> {
     // 최초 1회 디바이스 리스트 초기화
     use super::module_detect::refresh_devlist;
     app.main_terminal.init();
 
-    refresh_devlist(&mut app);
-    check_env(app.tx.clone());
+    // watch before listing, a board plugged in in between is then added twice at worst
+    if let Err(e) = hotplug::watch(app.create_new_publisher(), app.sysfs.clone()) {
+        app.tx.post(Signal::Message(format!("USB hotplug monitoring is unavailable ({}), press F5 to refresh the device list\n", e)));
+    }
+    refresh_devlist(app);
+    if let Some(release) = app.current_release() {
+        check_env(app.tx.clone(), release);
+    }
+
+    // a kill or a closed terminal should stop the jobs too, not only the quit dialog
+    let tx = app.create_new_publisher();
+    tokio::spawn(async move {
+        use tokio::signal::unix::{signal, SignalKind};
+        let (mut terminate, mut hangup) = match (signal(SignalKind::terminate()), signal(SignalKind::hangup())) {
+            (Ok(terminate), Ok(hangup)) => (terminate, hangup),
+            _ => return,
+        };
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {},
+            _ = terminate.recv() => {},
+            _ = hangup.recv() => {},
+        }
+        tx.send(Signal::Terminate).await;
+    });
 
+    let mut events = EventStream::new();
     loop {
-        // 플래싱 완료 확인
-        let flash_handle = app.flash_handle.take();
-        if let Some(handle) = flash_handle {
-            if handle.is_finished() {
-                handle.join().unwrap();
-                app.tx.send(Signal::Message(String::from("Flashing complete\n"))).unwrap();
-                app.flash_status = FlashStatus::Finished;
-            } else {
-                app.flash_handle = Some(handle);
-            }
-        } else {
-            app.flash_handle = None;
+        // 플래싱, 설치 완료 확인
+        reap(&mut app.installer).await;
+        reap(&mut app.flash_job).await;
+        for jetson in &mut app.devlist {
+            reap(&mut jetson.flash_job).await;
         }
 
         // 시그널 핸들링
-        loop {
-            if let Ok(sig) = app.rx.try_recv() {
-                match sig {
-                    Signal::Message(msg) => {
-                        app.main_terminal.create_new_publisher().send(msg).unwrap();
-                    },
-                    Signal::EnvironmentInstalled => {
-                        app.install_status = InstallStatus::Installed;
-                    },
-                    Signal::EnvironmentInstalling(timestamp) => {
-                        app.install_status = InstallStatus::Installing(timestamp);
+        while let Ok(sig) = app.rx.try_recv() {
+            match sig {
+                Signal::Message(msg) => {
+                    app.main_terminal.create_new_publisher().send(msg).unwrap();
+                },
+                Signal::Terminate => {
+                    return Ok(());
+                },
+                Signal::EnvironmentInstalled => {
+                    app.setup_progress = None;
+                    app.install_status = InstallStatus::Installed;
+                },
+                Signal::EnvironmentInstalling(timestamp) => {
+                    app.install_status = InstallStatus::Installing(timestamp);
+                },
+                Signal::EnvironmentFailed { step, reason } => {
+                    app.install_status = InstallStatus::Failed { step, reason };
+                },
+                Signal::EnvironmentCancelled { step } => {
+                    app.install_status = InstallStatus::Cancelled { step };
+                },
+                Signal::SetupProgress(progress) => {
+                    app.setup_progress = Some(progress);
+                },
+                Signal::FlashSuccess => {
+                    app.main_terminal.create_new_publisher().send(String::from("Flashing complete\n")).unwrap();
+                    app.flash_status = FlashStatus::Finished;
+                },
+                Signal::FlashCancelled => {
+                    app.main_terminal.create_new_publisher().send(String::from("Flashing cancelled\n")).unwrap();
+                    app.flash_status = FlashStatus::Cancelled;
+                },
+                Signal::FlashFail => {
+                    app.main_terminal.create_new_publisher().send(String::from("Flashing failed, see the device logs\n")).unwrap();
+                    app.flash_status = FlashStatus::Failed;
+                },
+                Signal::DeviceFlashStatus { instance, status } => {
+                    if let Some(jetson) = app.get_device_from_instance_number(&instance) {
+                        jetson.status = status;
+                        let result = match status {
+                            FlashStatus::Finished => "flashed",
+                            FlashStatus::Failed => "flash failed",
+                            FlashStatus::Cancelled => "flash cancelled",
+                            _ => continue,
+                        };
+                        let line = match jetson.identity() {
+                            Some(identity) => format!("{} on port {} ({}): {}\n", jetson.module_name, instance, identity, result),
+                            None => format!("{} on port {}: {}\n", jetson.module_name, instance, result),
+                        };
+                        app.main_terminal.create_new_publisher().send(line).unwrap();
                     }
-                    _ => {}
-                }
-            } else {
-                break;
+                },
+                Signal::DeviceEcid { instance, ecid } => {
+                    if let Some(jetson) = app.get_device_from_instance_number(&instance) {
+                        jetson.ecid = Some(ecid);
+                    }
+                },
+                Signal::DeviceAdded(device) => {
+                    if let Some(line) = app.add_device(device) {
+                        app.main_terminal.create_new_publisher().send(line).unwrap();
+                    }
+                },
+                Signal::DeviceRemoved { port } => {
+                    if let Some(line) = app.remove_device(&port) {
+                        app.main_terminal.create_new_publisher().send(line).unwrap();
+                    }
+                },
+                _ => {}
             }
         }
 
         for dev in &mut app.devlist {
-            if dev.logger.is_some() {
-                dev.logger.as_mut().unwrap().output();
+            if let Some(logger) = dev.logger.as_mut() {
+                logger.output();
             }
         }
 
         // APP 메인 루프 : main_ui 레이아웃에 맞춰 프레임 렌더링
         match app.index {
             0 => {
-                terminal.draw(|f| super::layout::main::main_ui(f, &mut app))?;
+                terminal.draw(|f| super::layout::main::main_ui(f, app))?;
             },
             1 => {
-                terminal.draw(|f| super::layout::select_mode::select_mode_ui(f, &mut app))?;
+                terminal.draw(|f| super::layout::select_mode::select_mode_ui(f, app))?;
             }
             2 => {
-                terminal.draw(|f| super::layout::quit::quit_ui(f, &mut app))?;
+                terminal.draw(|f| super::layout::quit::quit_ui(f, app))?;
+            },
+            3 => {
+                terminal.draw(|f| super::layout::dtb_diff::dtb_diff_ui(f, app))?;
+            },
+            4 => {
+                terminal.draw(|f| super::layout::cancel_job::cancel_job_ui(f, app))?;
             },
             _ => {},
         }
 
-        // 입력값 체크
-        if event::poll(Duration::from_millis(16))? {
-            if let Event::Key(key) = event::read()? {
+        // 입력값 체크, 없으면 작업 출력을 다시 그린다
+        let event = tokio::select! {
+            event = events.next() => event,
+            _ = tokio::time::sleep(Duration::from_millis(16)) => continue,
+        };
+        match event {
+            Some(Ok(Event::Key(key))) => {
+                // the terminal is in raw mode, Ctrl-C arrives as a key
+                if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+                    return Ok(());
+                }
                 match app.index {
                     0 => {
-                        if let Some(()) = super::layout::main::control(&mut app, key) {
+                        if let Some(()) = super::layout::main::control(app, key) {
                             return Ok(());
                         }
                     },
                     1 => {
-                        if let Some(()) = super::layout::select_mode::control(&mut app, key) {
+                        if let Some(()) = super::layout::select_mode::control(app, key) {
                             return Ok(());
                         }
                     }
                     2 => {
-                        if let Some(()) = super::layout::quit::control(&mut app, key) {
+                        if let Some(()) = super::layout::quit::control(app, key) {
                             return Ok(());
                         }
                     },
+                    3 => {
+                        if let Some(()) = super::layout::dtb_diff::control(app, key) {
+                            return Ok(());
+                        }
+                    },
+                    4 => {
+                        super::layout::cancel_job::control(app, key);
+                    },
                     _ => {},
                 }
+            },
+            Some(Ok(_)) => {},
+            Some(Err(e)) => {
+                return Err(e.into());
+            },
+            // the terminal is gone
+            None => {
+                return Ok(());
             }
         }
     }
@@ -1,17 +1,32 @@
-use std::io;
-use std::process::Command;
+use std::fs;
 
-pub fn compile_to_file(dts: &str, dtb: &str) -> io::Result<()> {
-    let _ = Command::new("dtc")
-                        .args([
-                            "-I",
-                            "dts",
-                            "-O",
-                            "dtb",
-                            dts,
-                            "-o",
-                            dtb
-                        ])
-                        .output()?;
+use super::fdt::{Fdt, FdtError};
+
+pub fn compile_to_file(dts: &str, dtb: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let content = fs::read_to_string(dts)?;
+    compile(&Fdt::parse(&content)?, dtb)?;
     Ok(())
-}
\ No newline at end of file
+}
+
+pub fn compile(fdt: &Fdt, dtb: &str) -> Result<(), FdtError> {
+    fdt.write_to_file(dtb)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_memreserve() {
+        let dir = std::env::temp_dir().join(format!("compile-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let dts = dir.join("memreserve.dts");
+        let dtb = dir.join("memreserve.dtb");
+        fs::write(&dts, include_str!("testdata/memreserve.dts")).unwrap();
+
+        compile_to_file(dts.to_str().unwrap(), dtb.to_str().unwrap()).unwrap();
+        let blob = fs::read(&dtb).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(blob, include_bytes!("testdata/memreserve.dtb"));
+    }
+}
@@ -1,38 +1,16 @@
-use std::io;
-use std::process::Command;
-use std::sync::mpsc::Sender;
-use crate::jetson::Signal;
+use std::fs;
 
-pub fn decompile_to_file(dtb: &str, dts: &str) -> io::Result<()> {
-    let _ = Command::new("dtc")
-                        .args([
-                            "-I",
-                            "dtb",
-                            "-O",
-                            "dts",
-                            dtb,
-                            "-o",
-                            dts,
-                        ])
-                        .output()?;
+use super::fdt::{Fdt, FdtError};
 
-    Ok(())
+pub fn decompile(dtb: &str) -> Result<Fdt, FdtError> {
+    Fdt::read_from_file(dtb)
 }
 
-pub fn decompile_to_string(_tx: Sender<Signal>, dtb: &str) -> io::Result<String> {    
-    let output = Command::new("dtc")
-                    .args([
-                        "-q",
-                        "-I",
-                        "dtb",
-                        "-O",
-                        "dts",
-                        dtb,
-                    ])
-                    .output()
-                    .unwrap();
-    
-    let output = String::from_utf8(output.stdout).unwrap();
+pub fn decompile_to_file(dtb: &str, dts: &str) -> Result<(), FdtError> {
+    fs::write(dts, decompile(dtb)?.stringify())?;
+    Ok(())
+}
 
-    Ok(output)
-}
\ No newline at end of file
+pub fn decompile_to_string(dtb: &str) -> Result<String, FdtError> {
+    Ok(decompile(dtb)?.stringify())
+}
@@ -0,0 +1,165 @@
+use std::fmt;
+
+use super::fdt::child_path;
+use super::node::DtbNode;
+use super::property::DtbProperty;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DtbChange {
+    NodeAdded(String),
+    NodeRemoved(String),
+    PropertyAdded { path: String, key: String, value: String },
+    PropertyRemoved { path: String, key: String, value: String },
+    PropertyChanged { path: String, key: String, old: String, new: String },
+}
+
+impl fmt::Display for DtbChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DtbChange::NodeAdded(path) => write!(f, "+ {}", path),
+            DtbChange::NodeRemoved(path) => write!(f, "- {}", path),
+            DtbChange::PropertyAdded { path, key, value } => write!(f, "+ {}: {}", path, assignment(key, value)),
+            DtbChange::PropertyRemoved { path, key, value } => write!(f, "- {}: {}", path, assignment(key, value)),
+            DtbChange::PropertyChanged { path, key, old, new } => write!(f, "~ {}: {}: {} -> {}", path, key, or_empty(old), or_empty(new)),
+        }
+    }
+}
+
+// Compare two trees by node path and property value, ignoring labels, comments and ordering.
+// Values are compared in their canonical DTS form, so `<0>` and `<0x00>` are the same.
+pub fn diff(old: &DtbNode, new: &DtbNode) -> Vec<DtbChange> {
+    let mut changes = vec![];
+    diff_node(old, new, "/", &mut changes);
+    changes
+}
+
+fn diff_node(old: &DtbNode, new: &DtbNode, path: &str, changes: &mut Vec<DtbChange>) {
+    for property in live_properties(old) {
+        match live_properties(new).find(|p| p.key == property.key) {
+            Some(other) => {
+                let (old_value, new_value) = (value(property), value(other));
+                if old_value != new_value {
+                    changes.push(DtbChange::PropertyChanged { path: path.to_string(), key: property.key.clone(), old: old_value, new: new_value });
+                }
+            },
+            None => {
+                changes.push(DtbChange::PropertyRemoved { path: path.to_string(), key: property.key.clone(), value: value(property) });
+            }
+        }
+    }
+    for property in live_properties(new) {
+        if !live_properties(old).any(|p| p.key == property.key) {
+            changes.push(DtbChange::PropertyAdded { path: path.to_string(), key: property.key.clone(), value: value(property) });
+        }
+    }
+
+    for child in live_children(old) {
+        let child_path = child_path(path, &child.node_name);
+        match live_children(new).find(|c| c.node_name == child.node_name) {
+            Some(other) => {
+                diff_node(child, other, &child_path, changes);
+            },
+            None => {
+                changes.push(DtbChange::NodeRemoved(child_path));
+            }
+        }
+    }
+    for child in live_children(new) {
+        if !live_children(old).any(|c| c.node_name == child.node_name) {
+            added_node(child, &child_path(path, &child.node_name), changes);
+        }
+    }
+}
+
+// a new node is listed with everything in it
+fn added_node(node: &DtbNode, path: &str, changes: &mut Vec<DtbChange>) {
+    changes.push(DtbChange::NodeAdded(path.to_string()));
+    for property in live_properties(node) {
+        changes.push(DtbChange::PropertyAdded { path: path.to_string(), key: property.key.clone(), value: value(property) });
+    }
+    for child in live_children(node) {
+        added_node(child, &child_path(path, &child.node_name), changes);
+    }
+}
+
+fn live_properties(node: &DtbNode) -> impl Iterator<Item = &DtbProperty> {
+    node.properties.iter().filter(|p| !p.deleted)
+}
+
+fn live_children(node: &DtbNode) -> impl Iterator<Item = &DtbNode> {
+    node.child_nodes.iter().filter(|c| !c.deleted).map(|c| c.as_ref())
+}
+
+fn value(property: &DtbProperty) -> String {
+    property.value.as_ref().map(|v| v.to_string()).unwrap_or_default()
+}
+
+fn assignment(key: &str, value: &str) -> String {
+    match value {
+        "" => key.to_string(),
+        _ => format!("{} = {}", key, value),
+    }
+}
+
+fn or_empty(value: &str) -> &str {
+    match value {
+        "" => "(empty)",
+        _ => value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STOCK: &str = "/dts-v1/;\n\n/ {\n\tcam_i2cmux {\n\t\ti2c@0 {\n\t\t\tclock-frequency = <400000>;\n\t\t\trbpcv3_imx477_a@1a {\n\t\t\t\tstatus = \"disabled\";\n\t\t\t};\n\t\t};\n\t\ti2c@1 {\n\t\t\tstatus = \"okay\";\n\t\t};\n\t};\n};\n";
+
+    fn changes(patched: &str) -> Vec<DtbChange> {
+        diff(&DtbNode::parse(STOCK).unwrap(), &DtbNode::parse(patched).unwrap())
+    }
+
+    #[test]
+    fn same_tree_written_differently() {
+        // other value literals, a label and a comment do not make a change
+        let patched = STOCK.replace("<400000>", "<0x61a80>").replace("i2c@0 {", "cam_i2c0: i2c@0 { // CSI A");
+        assert_eq!(changes(&patched), []);
+    }
+
+    #[test]
+    fn added_node() {
+        let patched = STOCK.replace("\t\ti2c@1 {\n", "\t\ti2c@1 {\n\t\t\teeprom@50 {\n\t\t\t\treg = <0x50>;\n\t\t\t};\n");
+        assert_eq!(changes(&patched), [
+            DtbChange::NodeAdded(String::from("/cam_i2cmux/i2c@1/eeprom@50")),
+            DtbChange::PropertyAdded { path: String::from("/cam_i2cmux/i2c@1/eeprom@50"), key: String::from("reg"), value: String::from("<0x50>") },
+        ]);
+    }
+
+    #[test]
+    fn removed_node() {
+        let patched = STOCK.replace("\t\ti2c@1 {\n\t\t\tstatus = \"okay\";\n\t\t};\n", "");
+        assert_eq!(changes(&patched), [DtbChange::NodeRemoved(String::from("/cam_i2cmux/i2c@1"))]);
+    }
+
+    #[test]
+    fn changed_property() {
+        let patched = STOCK.replace("\"disabled\"", "\"okay\"");
+        let changes = changes(&patched);
+        assert_eq!(changes, [DtbChange::PropertyChanged {
+            path: String::from("/cam_i2cmux/i2c@0/rbpcv3_imx477_a@1a"),
+            key: String::from("status"),
+            old: String::from("\"disabled\""),
+            new: String::from("\"okay\""),
+        }]);
+        assert_eq!(changes[0].to_string(), "~ /cam_i2cmux/i2c@0/rbpcv3_imx477_a@1a: status: \"disabled\" -> \"okay\"");
+    }
+
+    #[test]
+    fn added_and_removed_properties() {
+        let patched = STOCK.replace("\t\t\tclock-frequency = <400000>;\n", "\t\t\tdma-coherent;\n");
+        let changes = changes(&patched);
+        assert_eq!(changes.iter().map(|c| c.to_string()).collect::<Vec<_>>(), [
+            "- /cam_i2cmux/i2c@0: clock-frequency = <0x61a80>",
+            "+ /cam_i2cmux/i2c@0: dma-coherent",
+        ]);
+    }
+}
@@ -0,0 +1,72 @@
+use super::node::DtbNode;
+use super::parser::{self, ParseError};
+
+// Top level statement of a DTS file
+#[derive(Debug, Clone)]
+pub enum DtsItem {
+    // `/dts-v1/;`, `/plugin/;`, `/include/ "..."`, `#include <...>`, `/memreserve/ ...;`, `/delete-node/ &label;`
+    // `comments` are on the lines above it, `line_comment` on the same line after it
+    Directive { text: String, line_comment: Option<String>, comments: Vec<String> },
+    // `/ { };` or `&label { };`
+    Node(DtbNode),
+}
+
+// A DTS file as written, before `&label` overrides are merged into the root node.
+#[derive(Debug, Clone)]
+pub struct DtsDocument {
+    pub items: Vec<DtsItem>,
+    pub trailing_comments: Vec<String>,
+}
+
+impl DtsDocument {
+    pub fn parse(source: &str) -> Result<DtsDocument, ParseError> {
+        parser::parse_document(source)
+    }
+
+    pub fn root(&self) -> Option<&DtbNode> {
+        self.items.iter().find_map(|item| match item {
+            DtsItem::Node(node) if node.node_name == "/" => Some(node),
+            _ => None,
+        })
+    }
+
+    pub fn root_mut(&mut self) -> Option<&mut DtbNode> {
+        self.items.iter_mut().find_map(|item| match item {
+            DtsItem::Node(node) if node.node_name == "/" => Some(node),
+            _ => None,
+        })
+    }
+
+    pub fn stringify(&self) -> String {
+        let mut ret = String::new();
+        let mut previous_was_node = false;
+
+        for (index, item) in self.items.iter().enumerate() {
+            let is_node = matches!(item, DtsItem::Node(node) if !node.source_layout);
+
+            // nodes built in code are separated from everything around them by a blank line,
+            // parsed ones keep the empty lines of the source
+            if index > 0 && (is_node || previous_was_node) {
+                ret.push('\n');
+            }
+
+            match item {
+                DtsItem::Directive { text, line_comment, comments } => {
+                    ret.push_str(&DtbNode::comment_lines(comments, 0));
+                    ret.push_str(text);
+                    ret.push_str(line_comment.as_deref().unwrap_or(""));
+                    ret.push('\n');
+                },
+                DtsItem::Node(node) => {
+                    ret.push_str(&node.stringify(0));
+                }
+            }
+
+            previous_was_node = is_node;
+        }
+
+        ret.push_str(&DtbNode::comment_lines(&self.trailing_comments, 0));
+
+        ret
+    }
+}
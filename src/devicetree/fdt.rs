@@ -0,0 +1,455 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io;
+
+use super::node::DtbNode;
+use super::parser::{self, ParseError};
+use super::property::DtbProperty;
+use super::value::{Cell, PropertyValue, ValueChunk};
+
+// Flattened device tree (DTB) format, devicetree specification v0.4 chapter 5
+pub const FDT_MAGIC: u32 = 0xd00dfeed;
+const FDT_VERSION: u32 = 17;
+const FDT_LAST_COMP_VERSION: u32 = 16;
+const FDT_HEADER_SIZE: usize = 40;
+
+const FDT_BEGIN_NODE: u32 = 0x1;
+const FDT_END_NODE: u32 = 0x2;
+const FDT_PROP: u32 = 0x3;
+const FDT_NOP: u32 = 0x4;
+const FDT_END: u32 = 0x9;
+
+#[derive(Debug)]
+pub enum FdtError {
+    Io(io::Error),
+    BadMagic(u32),
+    UnsupportedVersion(u32),
+    Truncated(&'static str),
+    BadToken { offset: usize, token: u32 },
+    BadString(usize),
+    BadValue { property: String, reason: String },
+}
+
+impl fmt::Display for FdtError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FdtError::Io(e) => write!(f, "{}", e),
+            FdtError::BadMagic(magic) => write!(f, "bad FDT magic 0x{:08x}", magic),
+            FdtError::UnsupportedVersion(version) => write!(f, "unsupported FDT version {}", version),
+            FdtError::Truncated(section) => write!(f, "FDT truncated in {}", section),
+            FdtError::BadToken { offset, token } => write!(f, "unexpected token 0x{:x} at structure offset 0x{:x}", token, offset),
+            FdtError::BadString(offset) => write!(f, "invalid string at offset 0x{:x}", offset),
+            FdtError::BadValue { property, reason } => write!(f, "cannot encode property \"{}\": {}", property, reason),
+        }
+    }
+}
+
+impl std::error::Error for FdtError {}
+
+impl From<io::Error> for FdtError {
+    fn from(e: io::Error) -> Self {
+        FdtError::Io(e)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FdtReserveEntry {
+    pub address: u64,
+    pub size: u64,
+}
+
+#[derive(Debug)]
+pub struct Fdt {
+    pub boot_cpuid_phys: u32,
+    pub reserved_memory: Vec<FdtReserveEntry>,
+    pub root: DtbNode,
+}
+
+impl Fdt {
+    pub fn new(root: DtbNode) -> Fdt {
+        Fdt {
+            boot_cpuid_phys: 0,
+            reserved_memory: vec![],
+            root,
+        }
+    }
+
+    // DTS source, with its `/memreserve/` entries
+    pub fn parse(source: &str) -> Result<Fdt, ParseError> {
+        let (root, reserved) = parser::parse_tree(source)?;
+        let mut fdt = Fdt::new(root);
+        fdt.reserved_memory = reserved.into_iter().map(|(address, size)| FdtReserveEntry { address, size }).collect();
+        Ok(fdt)
+    }
+
+    pub fn read_from_file(path: &str) -> Result<Fdt, FdtError> {
+        let blob = fs::read(path)?;
+        Self::from_bytes(&blob)
+    }
+
+    pub fn write_to_file(&self, path: &str) -> Result<(), FdtError> {
+        fs::write(path, self.to_bytes()?)?;
+        Ok(())
+    }
+
+    pub fn from_bytes(blob: &[u8]) -> Result<Fdt, FdtError> {
+        if blob.len() < FDT_HEADER_SIZE {
+            return Err(FdtError::Truncated("header"));
+        }
+
+        let magic = be32(blob, 0).unwrap();
+        if magic != FDT_MAGIC {
+            return Err(FdtError::BadMagic(magic));
+        }
+
+        let totalsize = be32(blob, 4).unwrap() as usize;
+        let off_dt_struct = be32(blob, 8).unwrap() as usize;
+        let off_dt_strings = be32(blob, 12).unwrap() as usize;
+        let off_mem_rsvmap = be32(blob, 16).unwrap() as usize;
+        let version = be32(blob, 20).unwrap();
+        let last_comp_version = be32(blob, 24).unwrap();
+        let boot_cpuid_phys = be32(blob, 28).unwrap();
+        let size_dt_strings = be32(blob, 32).unwrap() as usize;
+        let size_dt_struct = be32(blob, 36).unwrap() as usize;
+
+        if last_comp_version > FDT_VERSION || version < FDT_LAST_COMP_VERSION {
+            return Err(FdtError::UnsupportedVersion(version));
+        }
+        if totalsize > blob.len() {
+            return Err(FdtError::Truncated("blob"));
+        }
+
+        let strings = blob.get(off_dt_strings..off_dt_strings + size_dt_strings).ok_or(FdtError::Truncated("strings block"))?;
+        let structure = blob.get(off_dt_struct..off_dt_struct + size_dt_struct).ok_or(FdtError::Truncated("structure block"))?;
+
+        // memory reservation map, terminated by an all-zero entry
+        let mut reserved_memory = vec![];
+        let mut offset = off_mem_rsvmap;
+        loop {
+            let address = be64(blob, offset).ok_or(FdtError::Truncated("memory reservation map"))?;
+            let size = be64(blob, offset + 8).ok_or(FdtError::Truncated("memory reservation map"))?;
+            if address == 0 && size == 0 {
+                break;
+            }
+            reserved_memory.push(FdtReserveEntry { address, size });
+            offset += 16;
+        }
+
+        // structure block
+        let mut stack: Vec<DtbNode> = vec![];
+        let mut root: Option<DtbNode> = None;
+        let mut offset = 0;
+
+        loop {
+            let token = be32(structure, offset).ok_or(FdtError::Truncated("structure block"))?;
+            let token_offset = offset;
+            offset += 4;
+
+            match token {
+                FDT_BEGIN_NODE => {
+                    if root.is_some() {
+                        return Err(FdtError::BadToken { offset: token_offset, token });
+                    }
+                    let name = c_string(structure, offset).ok_or(FdtError::BadString(off_dt_struct + offset))?;
+                    offset = align4(offset + name.len() + 1);
+                    let name = if stack.is_empty() && name.is_empty() { "/" } else { name };
+                    stack.push(DtbNode::with_name(name));
+                },
+                FDT_END_NODE => {
+                    let node = stack.pop().ok_or(FdtError::BadToken { offset: token_offset, token })?;
+                    match stack.last_mut() {
+                        Some(parent) => parent.child_nodes.push(Box::new(node)),
+                        None => root = Some(node),
+                    }
+                },
+                FDT_PROP => {
+                    let len = be32(structure, offset).ok_or(FdtError::Truncated("structure block"))? as usize;
+                    let nameoff = be32(structure, offset + 4).ok_or(FdtError::Truncated("structure block"))? as usize;
+                    offset += 8;
+                    let value = structure.get(offset..offset + len).ok_or(FdtError::Truncated("property value"))?;
+                    offset = align4(offset + len);
+                    let key = c_string(strings, nameoff).ok_or(FdtError::BadString(off_dt_strings + nameoff))?;
+                    let node = stack.last_mut().ok_or(FdtError::BadToken { offset: token_offset, token })?;
+                    node.properties.push(DtbProperty::new(key, PropertyValue::from_bytes(value)));
+                },
+                FDT_NOP => {},
+                FDT_END => {
+                    break;
+                },
+                _ => {
+                    return Err(FdtError::BadToken { offset: token_offset, token });
+                }
+            }
+        }
+
+        if !stack.is_empty() {
+            return Err(FdtError::Truncated("structure block"));
+        }
+
+        Ok(Fdt {
+            boot_cpuid_phys,
+            reserved_memory,
+            root: root.ok_or(FdtError::Truncated("structure block"))?,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, FdtError> {
+        let mut structure: Vec<u8> = vec![];
+        let mut strings: Vec<u8> = vec![];
+
+        let refs = References::new(&self.root);
+        write_node(&self.root, "/", &refs, &mut structure, &mut strings)?;
+        push32(&mut structure, FDT_END);
+
+        let off_mem_rsvmap = FDT_HEADER_SIZE;
+        let off_dt_struct = off_mem_rsvmap + (self.reserved_memory.len() + 1) * 16;
+        let off_dt_strings = off_dt_struct + structure.len();
+        let totalsize = off_dt_strings + strings.len();
+
+        let mut blob: Vec<u8> = Vec::with_capacity(totalsize);
+        push32(&mut blob, FDT_MAGIC);
+        push32(&mut blob, totalsize as u32);
+        push32(&mut blob, off_dt_struct as u32);
+        push32(&mut blob, off_dt_strings as u32);
+        push32(&mut blob, off_mem_rsvmap as u32);
+        push32(&mut blob, FDT_VERSION);
+        push32(&mut blob, FDT_LAST_COMP_VERSION);
+        push32(&mut blob, self.boot_cpuid_phys);
+        push32(&mut blob, strings.len() as u32);
+        push32(&mut blob, structure.len() as u32);
+
+        for entry in &self.reserved_memory {
+            blob.extend_from_slice(&entry.address.to_be_bytes());
+            blob.extend_from_slice(&entry.size.to_be_bytes());
+        }
+        blob.extend_from_slice(&[0u8; 16]);
+
+        blob.extend_from_slice(&structure);
+        blob.extend_from_slice(&strings);
+
+        Ok(blob)
+    }
+
+    pub fn stringify(&self) -> String {
+        let mut ret = String::from("/dts-v1/;\n\n");
+
+        for entry in &self.reserved_memory {
+            ret.push_str(&format!("/memreserve/\t0x{:016x} 0x{:016x};\n", entry.address, entry.size));
+        }
+
+        ret.push_str(&self.root.stringify(0));
+
+        ret
+    }
+}
+
+// Label and phandle tables used to encode `&label` references
+struct References {
+    labels: HashMap<String, String>,
+    phandles: HashMap<String, u32>,
+    // nodes referenced by a phandle that do not have a `phandle` property yet
+    assigned: HashSet<String>,
+}
+
+impl References {
+    fn new(root: &DtbNode) -> References {
+        let mut refs = References {
+            labels: HashMap::new(),
+            phandles: HashMap::new(),
+            assigned: HashSet::new(),
+        };
+        refs.collect(root, "/");
+
+        // like dtc, give every referenced node without a phandle the next free one
+        let mut next = refs.phandles.values().max().copied().unwrap_or(0) + 1;
+        let mut targets = vec![];
+        refs.collect_targets(root, &mut targets);
+        for reference in targets {
+            if let Some(path) = refs.path(&reference) {
+                if !refs.phandles.contains_key(&path) {
+                    refs.phandles.insert(path.clone(), next);
+                    refs.assigned.insert(path);
+                    next += 1;
+                }
+            }
+        }
+
+        refs
+    }
+
+    fn collect(&mut self, node: &DtbNode, path: &str) {
+        for label in &node.labels {
+            self.labels.insert(label.clone(), path.to_string());
+        }
+        for property in node.properties.iter().filter(|p| !p.deleted) {
+            let value = property.value.as_ref();
+            if property.key == "phandle" || property.key == "linux,phandle" {
+                if let Some(phandle) = value.and_then(|v| v.as_u32()) {
+                    self.phandles.insert(path.to_string(), phandle);
+                }
+            }
+            // labels of a compiled tree only survive in /__symbols__
+            if path == "/__symbols__" {
+                if let Some(target) = value.and_then(|v| v.as_str()) {
+                    self.labels.entry(property.key.clone()).or_insert(target.to_string());
+                }
+            }
+        }
+        for child in node.child_nodes.iter().filter(|c| !c.deleted) {
+            self.collect(child, &child_path(path, &child.node_name));
+        }
+    }
+
+    fn collect_targets(&self, node: &DtbNode, targets: &mut Vec<String>) {
+        for value in node.properties.iter().filter(|p| !p.deleted).filter_map(|p| p.value.as_ref()) {
+            for chunk in &value.0 {
+                if let ValueChunk::Cells { cells, .. } = chunk {
+                    for cell in cells {
+                        if let Cell::Ref(reference) = cell {
+                            targets.push(reference.clone());
+                        }
+                    }
+                }
+            }
+        }
+        for child in node.child_nodes.iter().filter(|c| !c.deleted) {
+            self.collect_targets(child, targets);
+        }
+    }
+
+    fn path(&self, reference: &str) -> Option<String> {
+        match reference.strip_prefix("&{").and_then(|r| r.strip_suffix('}')) {
+            Some(path) => Some(path.to_string()),
+            None => self.labels.get(reference.trim_start_matches('&')).cloned(),
+        }
+    }
+
+    fn phandle(&self, reference: &str) -> Option<u32> {
+        self.phandles.get(&self.path(reference)?).copied()
+    }
+}
+
+pub fn child_path(parent: &str, name: &str) -> String {
+    match parent {
+        "/" => String::from("/") + name,
+        _ => String::from(parent) + "/" + name,
+    }
+}
+
+fn write_property(key: &str, value: &[u8], structure: &mut Vec<u8>, strings: &mut Vec<u8>) {
+    push32(structure, FDT_PROP);
+    push32(structure, value.len() as u32);
+    push32(structure, string_offset(strings, key) as u32);
+    structure.extend_from_slice(value);
+    pad4(structure);
+}
+
+fn write_node(node: &DtbNode, path: &str, refs: &References, structure: &mut Vec<u8>, strings: &mut Vec<u8>) -> Result<(), FdtError> {
+    push32(structure, FDT_BEGIN_NODE);
+    if path != "/" {
+        structure.extend_from_slice(node.node_name.as_bytes());
+    }
+    structure.push(0);
+    pad4(structure);
+
+    for property in node.properties.iter().filter(|p| !p.deleted) {
+        let value = match &property.value {
+            Some(value) => value
+                .to_bytes(&mut |reference| refs.phandle(reference), &|reference| refs.path(reference))
+                .map_err(|e| FdtError::BadValue { property: property.key.clone(), reason: e.message })?,
+            None => vec![],
+        };
+        write_property(&property.key, &value, structure, strings);
+    }
+
+    if refs.assigned.contains(path) {
+        write_property("phandle", &refs.phandles[path].to_be_bytes(), structure, strings);
+    }
+
+    for child in node.child_nodes.iter().filter(|c| !c.deleted) {
+        write_node(child, &child_path(path, &child.node_name), refs, structure, strings)?;
+    }
+
+    push32(structure, FDT_END_NODE);
+    Ok(())
+}
+
+fn string_offset(strings: &mut Vec<u8>, key: &str) -> usize {
+    let mut offset = 0;
+    for s in strings.split(|b| *b == 0) {
+        if s == key.as_bytes() && offset + s.len() < strings.len() {
+            return offset;
+        }
+        offset += s.len() + 1;
+    }
+
+    let offset = strings.len();
+    strings.extend_from_slice(key.as_bytes());
+    strings.push(0);
+    offset
+}
+
+fn be32(buf: &[u8], offset: usize) -> Option<u32> {
+    buf.get(offset..offset + 4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn be64(buf: &[u8], offset: usize) -> Option<u64> {
+    let hi = be32(buf, offset)? as u64;
+    let lo = be32(buf, offset + 4)? as u64;
+    Some((hi << 32) | lo)
+}
+
+fn c_string(buf: &[u8], offset: usize) -> Option<&str> {
+    let rest = buf.get(offset..)?;
+    let end = rest.iter().position(|b| *b == 0)?;
+    std::str::from_utf8(&rest[..end]).ok()
+}
+
+fn align4(offset: usize) -> usize {
+    (offset + 3) & !3
+}
+
+fn push32(buf: &mut Vec<u8>, value: u32) {
+    buf.extend_from_slice(&value.to_be_bytes());
+}
+
+fn pad4(buf: &mut Vec<u8>) {
+    while !buf.len().is_multiple_of(4) {
+        buf.push(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // testdata/memreserve.dts in the layout dtc writes: header, reservation map, structure, strings
+    const BLOB: &[u8] = include_bytes!("testdata/memreserve.dtb");
+    const SOURCE: &str = include_str!("testdata/memreserve.dts");
+
+    const RESERVED: [FdtReserveEntry; 2] = [
+        FdtReserveEntry { address: 0x80000000, size: 0x10000 },
+        FdtReserveEntry { address: 0xa0000000, size: 0x200000 },
+    ];
+
+    #[test]
+    fn writes_back_the_blob_it_read() {
+        let fdt = Fdt::from_bytes(BLOB).unwrap();
+        assert_eq!(fdt.reserved_memory, RESERVED);
+        assert_eq!(fdt.to_bytes().unwrap(), BLOB);
+    }
+
+    #[test]
+    fn compiles_source_with_memreserve() {
+        let fdt = Fdt::parse(SOURCE).unwrap();
+        assert_eq!(fdt.reserved_memory, RESERVED);
+        assert_eq!(fdt.to_bytes().unwrap(), BLOB);
+    }
+
+    #[test]
+    fn decompiled_source_compiles_to_the_same_blob() {
+        let source = Fdt::from_bytes(BLOB).unwrap().stringify();
+        assert_eq!(Fdt::parse(&source).unwrap().to_bytes().unwrap(), BLOB);
+    }
+}
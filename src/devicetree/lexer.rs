@@ -0,0 +1,321 @@
+use super::parser::ParseError;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    // `// ...` or `/* ... */`, kept verbatim
+    Comment,
+    // C preprocessor line (`#include <...>`), kept verbatim
+    Preprocessor,
+    // `/dts-v1/`, `/plugin/`, `/delete-node/`, ...
+    Directive,
+    // `label:`, text without the colon
+    Label,
+    // `&label` or `&{/path}`
+    Reference,
+    // node name, property name, `/`, number or identifier
+    Word,
+    String,
+    LBrace,
+    RBrace,
+    Semicolon,
+    Equals,
+    Eof,
+}
+
+#[derive(Debug, Clone)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub text: String,
+    pub offset: usize,
+    // empty lines between the previous token and this one
+    pub blank_lines: usize,
+    // on the same line as the end of the previous token
+    pub same_line: bool,
+}
+
+const PREPROCESSOR_DIRECTIVES: [&str; 12] = [
+    "include", "define", "undef", "ifdef", "ifndef", "if", "else", "elif", "endif", "error", "pragma", "line",
+];
+
+pub fn is_name_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || b",._+*#?@-".contains(&c)
+}
+
+fn is_label_start(c: u8) -> bool {
+    c.is_ascii_alphabetic() || c == b'_'
+}
+
+pub struct Lexer<'a> {
+    source: &'a str,
+    bytes: &'a [u8],
+    pos: usize,
+    // line breaks in the whitespace before the current token
+    newlines: usize,
+    // the whitespace before the current token starts the file
+    at_start: bool,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(source: &'a str) -> Lexer<'a> {
+        Lexer {
+            source,
+            bytes: source.as_bytes(),
+            pos: 0,
+            newlines: 0,
+            at_start: true,
+        }
+    }
+
+    pub fn source(&self) -> &'a str {
+        self.source
+    }
+
+    pub fn error(&self, offset: usize, message: &str) -> ParseError {
+        ParseError::at(self.source, offset, message)
+    }
+
+    fn peek_byte(&self, n: usize) -> Option<u8> {
+        self.bytes.get(self.pos + n).copied()
+    }
+
+    fn at_line_start(&self, offset: usize) -> bool {
+        self.bytes[..offset].iter().rev().take_while(|b| **b != b'\n').all(|b| b.is_ascii_whitespace())
+    }
+
+    fn skip_whitespace(&mut self) {
+        self.at_start = self.pos == 0;
+        self.newlines = 0;
+        while self.pos < self.bytes.len() && self.bytes[self.pos].is_ascii_whitespace() {
+            if self.bytes[self.pos] == b'\n' {
+                self.newlines += 1;
+            }
+            self.pos += 1;
+        }
+    }
+
+    fn token(&self, kind: TokenKind, start: usize, end: usize) -> Token {
+        Token {
+            kind,
+            text: self.source[start..end].to_string(),
+            offset: start,
+            blank_lines: match self.at_start {
+                true => self.newlines,
+                false => self.newlines.saturating_sub(1),
+            },
+            same_line: !self.at_start && self.newlines == 0,
+        }
+    }
+
+    pub fn peek(&mut self) -> Result<Token, ParseError> {
+        let pos = self.pos;
+        let token = self.next_token();
+        self.pos = pos;
+        token
+    }
+
+    pub fn next_token(&mut self) -> Result<Token, ParseError> {
+        self.skip_whitespace();
+
+        let start = self.pos;
+        let c = match self.peek_byte(0) {
+            Some(c) => c,
+            None => return Ok(self.token(TokenKind::Eof, start, start)),
+        };
+
+        match c {
+            b'/' if self.peek_byte(1) == Some(b'/') => {
+                while self.pos < self.bytes.len() && self.bytes[self.pos] != b'\n' {
+                    self.pos += 1;
+                }
+                Ok(self.token(TokenKind::Comment, start, self.pos))
+            },
+            b'/' if self.peek_byte(1) == Some(b'*') => {
+                match self.source[start + 2..].find("*/") {
+                    Some(end) => {
+                        self.pos = start + 2 + end + 2;
+                        Ok(self.token(TokenKind::Comment, start, self.pos))
+                    },
+                    None => Err(self.error(start, "unterminated comment")),
+                }
+            },
+            b'/' => {
+                // `/name/` directive, otherwise the root node name
+                let mut end = start + 1;
+                while end < self.bytes.len() && (self.bytes[end].is_ascii_alphanumeric() || self.bytes[end] == b'-') {
+                    end += 1;
+                }
+                if end > start + 1 && self.bytes.get(end) == Some(&b'/') {
+                    self.pos = end + 1;
+                    Ok(self.token(TokenKind::Directive, start, self.pos))
+                } else {
+                    self.pos = start + 1;
+                    Ok(self.token(TokenKind::Word, start, self.pos))
+                }
+            },
+            b'#' if self.at_line_start(start) && self.is_preprocessor_line(start) => {
+                // preprocessor lines continue over trailing backslashes
+                loop {
+                    while self.pos < self.bytes.len() && self.bytes[self.pos] != b'\n' {
+                        self.pos += 1;
+                    }
+                    if self.source[start..self.pos].trim_end().ends_with('\\') && self.pos < self.bytes.len() {
+                        self.pos += 1;
+                        continue;
+                    }
+                    break;
+                }
+                Ok(self.token(TokenKind::Preprocessor, start, self.pos))
+            },
+            b'"' => {
+                self.pos = self.scan_quoted(start, b'"')?;
+                Ok(self.token(TokenKind::String, start, self.pos))
+            },
+            b'&' => {
+                if self.peek_byte(1) == Some(b'{') {
+                    match self.source[start..].find('}') {
+                        Some(end) => {
+                            self.pos = start + end + 1;
+                            Ok(self.token(TokenKind::Reference, start, self.pos))
+                        },
+                        None => Err(self.error(start, "unterminated path reference")),
+                    }
+                } else {
+                    self.pos += 1;
+                    while self.pos < self.bytes.len() && (self.bytes[self.pos].is_ascii_alphanumeric() || self.bytes[self.pos] == b'_') {
+                        self.pos += 1;
+                    }
+                    if self.pos == start + 1 {
+                        return Err(self.error(start, "expected label after '&'"));
+                    }
+                    Ok(self.token(TokenKind::Reference, start, self.pos))
+                }
+            },
+            b'{' => {
+                self.pos += 1;
+                Ok(self.token(TokenKind::LBrace, start, self.pos))
+            },
+            b'}' => {
+                self.pos += 1;
+                Ok(self.token(TokenKind::RBrace, start, self.pos))
+            },
+            b';' => {
+                self.pos += 1;
+                Ok(self.token(TokenKind::Semicolon, start, self.pos))
+            },
+            b'=' => {
+                self.pos += 1;
+                Ok(self.token(TokenKind::Equals, start, self.pos))
+            },
+            c if is_name_char(c) => {
+                while self.pos < self.bytes.len() && is_name_char(self.bytes[self.pos]) {
+                    self.pos += 1;
+                }
+                let word_end = self.pos;
+                if self.peek_byte(0) == Some(b':') && is_label_start(c) && self.source[start..word_end].bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_') {
+                    self.pos += 1;
+                    return Ok(self.token(TokenKind::Label, start, word_end));
+                }
+                Ok(self.token(TokenKind::Word, start, word_end))
+            },
+            _ => {
+                let c = self.source[start..].chars().next().unwrap();
+                Err(self.error(start, &format!("unexpected character '{}'", c)))
+            }
+        }
+    }
+
+    fn is_preprocessor_line(&self, start: usize) -> bool {
+        let rest = &self.bytes[start + 1..];
+        let word_len = rest.iter().take_while(|b| b.is_ascii_alphabetic()).count();
+        let word = &self.source[start + 1..start + 1 + word_len];
+        let follows_name_char = rest.get(word_len).map(|b| is_name_char(*b)).unwrap_or(false);
+        PREPROCESSOR_DIRECTIVES.contains(&word) && !follows_name_char
+    }
+
+    fn scan_quoted(&self, start: usize, quote: u8) -> Result<usize, ParseError> {
+        let mut i = start + 1;
+        while i < self.bytes.len() {
+            match self.bytes[i] {
+                b'\\' => i += 2,
+                b'\n' => break,
+                c if c == quote => return Ok(i + 1),
+                _ => i += 1,
+            }
+        }
+        Err(self.error(start, "unterminated string"))
+    }
+
+    // Raw text of a property value up to (not including) the terminating ';'.
+    // Strings, character literals, comments and brackets are skipped as a unit so
+    // that a ';', '{' or '=' inside them does not end the value.
+    pub fn scan_value(&mut self) -> Result<Token, ParseError> {
+        self.skip_whitespace();
+        let start = self.pos;
+        let mut depth: Vec<u8> = vec![];
+        let mut i = start;
+
+        while i < self.bytes.len() {
+            match self.bytes[i] {
+                b'"' => i = self.scan_quoted(i, b'"')?,
+                b'\'' => i = self.scan_quoted(i, b'\'')?,
+                b'/' if self.bytes.get(i + 1) == Some(&b'/') => {
+                    while i < self.bytes.len() && self.bytes[i] != b'\n' {
+                        i += 1;
+                    }
+                },
+                b'/' if self.bytes.get(i + 1) == Some(&b'*') => {
+                    match self.source[i + 2..].find("*/") {
+                        Some(end) => i = i + 2 + end + 2,
+                        None => return Err(self.error(i, "unterminated comment")),
+                    }
+                },
+                b'&' if self.bytes.get(i + 1) == Some(&b'{') => {
+                    // path reference
+                    match self.source[i..].find('}') {
+                        Some(end) => i += end + 1,
+                        None => return Err(self.error(i, "unterminated path reference")),
+                    }
+                },
+                b'<' => {
+                    // inside a parenthesised expression '<' is an operator
+                    if depth.last() != Some(&b')') {
+                        depth.push(b'>');
+                    }
+                    i += 1;
+                },
+                b'[' => {
+                    depth.push(b']');
+                    i += 1;
+                },
+                b'(' => {
+                    depth.push(b')');
+                    i += 1;
+                },
+                c @ (b'>' | b']' | b')') => {
+                    if c == b'>' && depth.last() == Some(&b')') {
+                        i += 1;
+                        continue;
+                    }
+                    if depth.pop() != Some(c) {
+                        return Err(self.error(i, &format!("unbalanced '{}' in property value", c as char)));
+                    }
+                    i += 1;
+                },
+                b';' if depth.is_empty() => {
+                    self.pos = i;
+                    let end = self.source[..i].trim_end().len().max(start);
+                    if end == start {
+                        return Err(self.error(start, "missing property value"));
+                    }
+                    return Ok(self.token(TokenKind::Word, start, end));
+                },
+                b'{' | b'}' if depth.is_empty() => {
+                    return Err(self.error(i, "expected ';' after property value"));
+                },
+                _ => i += 1,
+            }
+        }
+
+        Err(self.error(start, "unterminated property value, expected ';'"))
+    }
+}
@@ -1,4 +1,14 @@
 pub mod compile;
 pub mod decompile;
+pub mod diff;
+pub mod document;
+pub mod fdt;
+pub mod lexer;
 pub mod node;
-pub mod property;
\ No newline at end of file
+pub mod overlay;
+pub mod parser;
+pub mod patch;
+pub mod property;
+pub mod query;
+pub mod validate;
+pub mod value;
@@ -1,19 +1,37 @@
 use super::property::*;
+use super::parser::{self, ParseError};
+use super::value::{PropertyValue, Status};
 
 use std::fmt;
 
+#[derive(Clone)]
 pub struct DtbNode {
     pub node_name: String,
+    pub labels: Vec<String>,
+    // comments on the lines above the node, "" for an empty line
+    pub comments: Vec<String>,
+    // comments on the same line after the '{' and after the closing "};", with the whitespace in front of them
+    pub open_comment: Option<String>,
+    pub close_comment: Option<String>,
     pub properties: Vec<DtbProperty>,
     pub child_nodes: Vec<Box<DtbNode>>,
+    // comments between the last child and the closing brace
+    pub trailing_comments: Vec<String>,
+    // `/delete-node/ name;`
+    pub deleted: bool,
+    pub omit_if_no_ref: bool,
+    // parsed from source: the empty lines in front of it are in `comments`, none are added
+    pub source_layout: bool,
 }
 
 impl fmt::Debug for DtbNode {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("DtbNode")
             .field("node_name", &self.node_name)
+            .field("labels", &self.labels)
             .field("properties", &self.properties)
             .field("child_nodes", &self.child_nodes)
+            .field("deleted", &self.deleted)
             .finish()
     }
 }
@@ -23,143 +41,177 @@ impl DtbNode {
         "\t".repeat(n)
     }
 
-    pub fn stringify(&self, indent: usize) -> String {
+    // one comment per line, "" as an empty line; preprocessor lines stay in the first column
+    pub fn comment_lines(comments: &[String], indent: usize) -> String {
         let mut ret = String::new();
-
-        if self.node_name == "/" && indent == 0 {
-            ret.push_str("/dts-v1/;");
-            ret.push('\n');
+        for comment in comments {
+            if !comment.is_empty() && !comment.starts_with('#') {
+                ret.push_str(&Self::indent(indent));
+            }
+            ret.push_str(comment);
             ret.push('\n');
         }
+        ret
+    }
 
-        ret.push_str(&(Self::indent(indent) + &self.node_name + " {\n")[..]);
+    pub fn stringify(&self, indent: usize) -> String {
+        let mut ret = Self::comment_lines(&self.comments, indent);
+        let close_comment = self.close_comment.as_deref().unwrap_or("");
 
-        // properties
-        for property in &self.properties {
-            let mut property_string = Self::indent(indent+1);
-            property_string.push_str(&property.key);
-            match &property.value {
-                Some(value) => {
-                    property_string.push_str(" = ");
-                    property_string.push_str(&value);
-                },
-                _ => {}
-            }
-            property_string.push_str(";\n");
+        if self.deleted {
+            ret.push_str(&(Self::indent(indent) + "/delete-node/ " + &self.node_name + ";" + close_comment + "\n"));
+            return ret;
+        }
 
-            ret.push_str(&property_string);
+        ret.push_str(&Self::indent(indent));
+        if self.omit_if_no_ref {
+            ret.push_str("/omit-if-no-ref/ ");
+        }
+        for label in &self.labels {
+            ret.push_str(label);
+            ret.push_str(": ");
         }
+        ret.push_str(&self.node_name);
+        ret.push_str(" {");
+        ret.push_str(self.open_comment.as_deref().unwrap_or(""));
+        ret.push('\n');
 
-        // add new line between properties and child_nodes
-        // if there are no child nodes, just close node.
-        if self.child_nodes.len() > 0 {
-            ret.push_str("\n");
+        // properties
+        for property in &self.properties {
+            ret.push_str(&property.stringify(indent + 1));
         }
 
-        // child_nodes
-        for (idx, node) in self.child_nodes.iter().enumerate() {
-            ret.push_str(&node.stringify(indent+1));
-            if idx < self.child_nodes.len() - 1 {
-                ret.push_str("\n");
+        // child_nodes, each one built in code preceded by a blank line
+        for node in &self.child_nodes {
+            if !node.source_layout {
+                ret.push('\n');
             }
+            ret.push_str(&node.stringify(indent + 1));
         }
 
+        ret.push_str(&Self::comment_lines(&self.trailing_comments, indent + 1));
+
         // close node
         ret.push_str(&Self::indent(indent));
-        ret.push_str("};\n");
+        ret.push_str("};");
+        ret.push_str(close_comment);
+        ret.push('\n');
 
         ret
     }
 
-    pub fn parse(content_vec: &Vec<&str>, brackets: &Vec<(usize, usize)>, index: usize, start: (usize, usize)) -> (usize, DtbNode) {
-        let mut ret = DtbNode {
-            node_name: String::new(),
-            properties: Vec::<DtbProperty>::new(),
-            child_nodes: Vec::<Box<DtbNode>>::new(),
-        };
-
-        ret.node_name = content_vec[start.0].trim().strip_suffix("{").unwrap().trim().to_string();
-
-        let mut idx = start.0;
-        let mut bracket_idx = index;
-
-        loop {
-            idx += 1;
-            
-            if brackets[bracket_idx + 1].0 == idx && brackets[bracket_idx + 1].1 == 0 {
-                // open new child node
-                let child: DtbNode;
-                let result = Self::parse(content_vec, brackets, bracket_idx + 1, brackets[bracket_idx + 1].clone());
-                bracket_idx = result.0;
-                idx = brackets[bracket_idx].0;
-                child = result.1;
-
-                ret.child_nodes.push(Box::new(child));
-
-            } else if brackets[bracket_idx + 1].0 == idx && brackets[bracket_idx + 1].1 == 1 {
-                // close
-                return (bracket_idx + 1, ret);
-            } else if content_vec[idx].contains(" = ") {
-                // property with (key, value)
-                let v: Vec<&str> = content_vec[idx].trim().split(" = ").map(|s| s.trim()).collect();
-                let property = DtbProperty {
-                    key: String::from(v[0]),
-                    value: Some(String::from(v[1].trim().strip_suffix(";").unwrap())),
-                };
-
-                ret.properties.push(property);
-            } else if !content_vec[idx].contains("=") && content_vec[idx].contains(";") {
-                // property with no value
-                let property = DtbProperty {
-                    key: String::from(content_vec[idx].trim().strip_suffix(";").unwrap()),
-                    value: None,
-                };
-                ret.properties.push(property);
+    pub fn find_property(&mut self, key: &str) -> Option<&mut DtbProperty> {
+        self.properties.iter_mut().find(|property| property.key == key && !property.deleted)
+    }
+
+    pub fn find_childnode(&mut self, name: &str) -> Option<&mut Box<DtbNode>> {
+        self.child_nodes.iter_mut().find(|node| node.node_name == name && !node.deleted)
+    }
+
+    // Replace the value of `key`, adding the property if the node does not have it
+    pub fn set_property(&mut self, key: &str, value: Option<PropertyValue>) -> &mut DtbProperty {
+        match self.properties.iter().position(|p| p.key == key && !p.deleted) {
+            Some(index) => {
+                self.properties[index].value = value;
+                &mut self.properties[index]
+            },
+            None => {
+                self.properties.push(DtbProperty::new(key, value));
+                self.properties.last_mut().unwrap()
             }
-            
         }
-
     }
 
-    pub fn find_property(&mut self, key: &str) -> Option<&mut DtbProperty> {
-        self.properties.iter_mut().find(|property| property.key == key)
+    pub fn status(&self) -> Option<Status> {
+        self.properties
+            .iter()
+            .find(|p| p.key == "status" && !p.deleted)
+            .and_then(|p| p.as_str())
+            .and_then(|s| s.parse().ok())
     }
 
-    pub fn find_childnode(&mut self, name: &str) -> Option<&mut Box<DtbNode>> {
-        self.child_nodes.iter_mut().find(|node| node.node_name == name)
+    pub fn set_status(&mut self, status: Status) {
+        self.set_property("status", Some(PropertyValue::string(status.as_str())));
     }
 
-    pub fn new(content: String) -> DtbNode {
-        let mut node = DtbNode {
-            node_name: String::new(),
+    pub fn with_name(name: &str) -> DtbNode {
+        DtbNode {
+            node_name: name.to_string(),
+            labels: vec![],
+            comments: vec![],
+            open_comment: None,
+            close_comment: None,
             properties: Vec::<DtbProperty>::new(),
             child_nodes: Vec::<Box<DtbNode>>::new(),
-        };
-
-        node._init(content);
+            trailing_comments: vec![],
+            deleted: false,
+            omit_if_no_ref: false,
+            source_layout: false,
+        }
+    }
 
+    // `other` without its properties and children: name, comments and layout
+    pub fn empty_like(other: &DtbNode) -> DtbNode {
+        let mut node = DtbNode::with_name(&other.node_name);
+        node.comments = other.comments.clone();
+        node.open_comment = other.open_comment.clone();
+        node.close_comment = other.close_comment.clone();
+        node.trailing_comments = other.trailing_comments.clone();
+        node.omit_if_no_ref = other.omit_if_no_ref;
+        node.source_layout = other.source_layout;
         node
     }
 
-    fn _init(&mut self, content: String) {
-
-        // find open, close brackets
-        let content_vec: Vec<&str> = content.lines().collect::<Vec<&str>>();
+    // Parse DTS source into a single tree, merging `&label { }` overrides and
+    // applying `/delete-node/` and `/delete-property/` the way dtc does.
+    pub fn parse(content: &str) -> Result<DtbNode, ParseError> {
+        parser::parse_tree(content).map(|(root, _)| root)
+    }
 
-        let mut opens: Vec<(usize, usize)> = content_vec.iter()
-                                                    .enumerate()
-                                                    .filter(|(_idx, line)| line.contains("{") && !line.trim().starts_with("//"))
-                                                    .map(|(idx, _line)| (idx, 0))
-                                                    .collect();
-        let mut closes: Vec<(usize, usize)> = content_vec.iter()
-                                                    .enumerate()
-                                                    .filter(|(_idx, line)| line.contains("};") && !line.trim().starts_with("//"))
-                                                    .map(|(idx, _line)| (idx, 1))
-                                                    .collect();
+    // Merge `other` on top of this node: properties are replaced, children merged by name.
+    pub fn merge(&mut self, other: &DtbNode) {
+        for label in &other.labels {
+            if !self.labels.contains(label) {
+                self.labels.push(label.clone());
+            }
+        }
 
-        opens.append(&mut closes);
-        opens.sort_unstable_by(|(idx1, _), (idx2, _)| idx1.cmp(idx2));
+        for property in &other.properties {
+            if property.deleted {
+                self.properties.retain(|p| p.key != property.key);
+                continue;
+            }
+            match self.properties.iter_mut().find(|p| p.key == property.key) {
+                Some(existing) => {
+                    existing.value = property.value.clone();
+                    existing.literal = property.literal.clone();
+                    for label in &property.labels {
+                        if !existing.labels.contains(label) {
+                            existing.labels.push(label.clone());
+                        }
+                    }
+                },
+                None => {
+                    self.properties.push(property.clone());
+                }
+            }
+        }
 
-        *self = Self::parse(&content_vec, &opens, 0, opens[0].clone()).1;
+        for child in &other.child_nodes {
+            if child.deleted {
+                self.child_nodes.retain(|c| c.node_name != child.node_name);
+                continue;
+            }
+            match self.child_nodes.iter_mut().find(|c| c.node_name == child.node_name) {
+                Some(existing) => {
+                    existing.merge(child);
+                },
+                None => {
+                    let mut node = DtbNode::empty_like(child);
+                    node.merge(child);
+                    self.child_nodes.push(Box::new(node));
+                }
+            }
+        }
     }
-}
\ No newline at end of file
+}
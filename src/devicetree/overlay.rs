@@ -0,0 +1,381 @@
+use std::fmt;
+
+use super::fdt::child_path;
+use super::node::DtbNode;
+use super::patch::{DtbPatch, PatchError};
+use super::query::LookupError;
+use super::value::{Cell, PropertyValue, ValueChunk};
+
+// placeholder cell for a phandle resolved through __fixups__
+const UNRESOLVED_PHANDLE: u64 = 0xffffffff;
+
+#[derive(Debug)]
+pub enum OverlayError {
+    Patch(PatchError),
+    Lookup(LookupError),
+    // patch operation an overlay cannot express
+    Unsupported(String),
+    // fragment, __fixups__ or __local_fixups__ entry that cannot be applied
+    Malformed(String),
+}
+
+impl fmt::Display for OverlayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OverlayError::Patch(e) => write!(f, "{}", e),
+            OverlayError::Lookup(e) => write!(f, "{}", e),
+            OverlayError::Unsupported(reason) => write!(f, "not supported in an overlay: {}", reason),
+            OverlayError::Malformed(reason) => write!(f, "malformed overlay: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for OverlayError {}
+
+impl From<PatchError> for OverlayError {
+    fn from(e: PatchError) -> Self {
+        OverlayError::Patch(e)
+    }
+}
+
+impl From<LookupError> for OverlayError {
+    fn from(e: LookupError) -> Self {
+        OverlayError::Lookup(e)
+    }
+}
+
+impl DtbPatch {
+    // Build a `/plugin/` overlay with one fragment@N per [[node]] entry.
+    // `base` is the tree the overlay is built for: aliases and labels are resolved against it,
+    // and nodes a `create = true` entry adds become children of the closest existing node.
+    // Label references in values are left to the bootloader through __fixups__.
+    pub fn to_overlay(&self, base: &DtbNode) -> Result<DtbNode, OverlayError> {
+        let mut root = DtbNode::with_name("/");
+        let mut fixups: Vec<(String, Vec<String>)> = vec![];
+
+        for (n, node_patch) in self.nodes.iter().enumerate() {
+            if node_patch.delete || !node_patch.delete_properties.is_empty() {
+                return Err(OverlayError::Unsupported(format!("{}: overlays cannot delete nodes or properties", node_patch.path)));
+            }
+
+            let (target, missing) = match base.resolve(&node_patch.path) {
+                Ok(path) => (path, vec![]),
+                Err(LookupError::PathNotFound { path, .. }) if node_patch.create => existing_ancestor(base, &path),
+                Err(e) => return Err(e.into()),
+            };
+            if missing.is_empty() {
+                node_patch.check_properties(base.lookup(&target)?)?;
+            }
+
+            let fragment_name = format!("fragment@{}", n);
+            let mut overlay_path = format!("/{}/__overlay__", fragment_name);
+            let mut overlay = DtbNode::with_name("__overlay__");
+
+            let mut node = &mut overlay;
+            for name in &missing {
+                node.child_nodes.push(Box::new(DtbNode::with_name(name)));
+                node = node.child_nodes.last_mut().unwrap();
+                overlay_path = child_path(&overlay_path, name);
+            }
+            for (key, value) in node_patch.properties()? {
+                let value = match value {
+                    Some(value) => Some(resolve_references(base, value, &format!("{}:{}", overlay_path, key), &mut fixups)?),
+                    None => None,
+                };
+                node.set_property(&key, value);
+            }
+
+            let mut fragment = DtbNode::with_name(&fragment_name);
+            fragment.set_property("target-path", Some(PropertyValue::string(&target)));
+            fragment.child_nodes.push(Box::new(overlay));
+            root.child_nodes.push(Box::new(fragment));
+        }
+
+        if !fixups.is_empty() {
+            let mut node = DtbNode::with_name("__fixups__");
+            for (label, locations) in &fixups {
+                let locations: Vec<&str> = locations.iter().map(|l| &l[..]).collect();
+                node.set_property(label, Some(PropertyValue::strings(&locations)));
+            }
+            root.child_nodes.push(Box::new(node));
+        }
+
+        Ok(root)
+    }
+}
+
+impl DtbNode {
+    // Apply a compiled overlay on top of this tree, the way fdtoverlay and the bootloader do
+    pub fn apply_overlay(&mut self, overlay: &DtbNode) -> Result<(), OverlayError> {
+        let mut overlay = overlay.clone();
+
+        // move the overlay's own phandles above the ones already in use
+        let delta = self.walk().iter().filter_map(|(_, node)| node.phandle()).max().unwrap_or(0);
+        renumber_phandles(&mut overlay, delta);
+        if let Ok(local_fixups) = overlay.lookup("/__local_fixups__") {
+            let local_fixups = local_fixups.clone();
+            apply_local_fixups(&mut overlay, &local_fixups, "/", delta)?;
+        }
+
+        // everything is resolved before this tree is touched, so that an error leaves it as it was
+
+        // label = "path:property:offset", one entry per cell referring to the label
+        let mut new_phandles: Vec<(String, u32)> = vec![];
+        if let Ok(fixups) = overlay.lookup("/__fixups__") {
+            let fixups = fixups.clone();
+            let mut next = self.walk().iter().chain(overlay.walk().iter()).filter_map(|(_, node)| node.phandle()).max().unwrap_or(0) + 1;
+            for property in fixups.properties.iter().filter(|p| !p.deleted) {
+                // a referenced node without a phandle gets the next free one
+                let path = self.resolve(&format!("&{}", property.key))?;
+                let existing = self.lookup(&path)?.phandle()
+                                .or_else(|| new_phandles.iter().find(|(p, _)| *p == path).map(|(_, phandle)| *phandle));
+                let phandle = match existing {
+                    Some(phandle) => phandle,
+                    None => {
+                        new_phandles.push((path, next));
+                        next += 1;
+                        next - 1
+                    }
+                };
+                for location in property.as_str_list().unwrap_or_default() {
+                    let mut parts = location.rsplitn(3, ':');
+                    let (offset, key, path) = match (parts.next(), parts.next(), parts.next()) {
+                        (Some(offset), Some(key), Some(path)) => (offset, key, path),
+                        _ => return Err(OverlayError::Malformed(format!("fixup {}", location))),
+                    };
+                    let offset = offset.parse::<usize>().map_err(|_| OverlayError::Malformed(format!("fixup {}", location)))?;
+                    let value = overlay.lookup_mut(path)?.find_property(key).and_then(|p| p.value.as_mut());
+                    if !value.map(|v| patch_cell(v, offset, |_| phandle)).unwrap_or(false) {
+                        return Err(OverlayError::Malformed(format!("fixup {} does not point at a cell", location)));
+                    }
+                }
+            }
+        }
+
+        let mut merges: Vec<(&DtbNode, String)> = vec![];
+        let mut targets: Vec<(String, String)> = vec![];
+        for fragment in overlay.child_nodes.iter().filter(|c| !c.deleted) {
+            let content = match fragment.child_nodes.iter().find(|c| c.node_name == "__overlay__" && !c.deleted) {
+                Some(content) => content,
+                None => continue,
+            };
+
+            let target_path = fragment.properties.iter().find(|p| p.key == "target-path" && !p.deleted).and_then(|p| p.as_str());
+            let target = fragment.properties.iter().find(|p| p.key == "target" && !p.deleted).and_then(|p| p.as_u32());
+            let target = match (target_path, target) {
+                (Some(path), _) => self.resolve(path)?,
+                (None, Some(phandle)) => self.phandle_path(phandle)?,
+                (None, None) => return Err(OverlayError::Malformed(format!("{} has no target", fragment.node_name))),
+            };
+
+            merges.push((content, target.clone()));
+            targets.push((format!("/{}/__overlay__", fragment.node_name), target));
+        }
+
+        // labels defined by the overlay, so later overlays can refer to them
+        let mut entries = vec![];
+        if let Ok(symbols) = overlay.lookup("/__symbols__") {
+            for property in symbols.properties.iter().filter(|p| !p.deleted) {
+                let path = property.as_str().unwrap_or_default();
+                for (prefix, target) in &targets {
+                    if let Some(rest) = path.strip_prefix(prefix.as_str()) {
+                        entries.push((property.key.clone(), target.clone() + rest));
+                    }
+                }
+            }
+        }
+
+        for (path, phandle) in new_phandles {
+            self.lookup_mut(&path)?.set_property("phandle", Some(PropertyValue::u32(phandle)));
+        }
+        for (content, target) in merges {
+            self.lookup_mut(&target)?.merge(content);
+        }
+
+        if entries.is_empty() {
+            return Ok(());
+        }
+        if self.lookup("/__symbols__").is_err() {
+            self.child_nodes.push(Box::new(DtbNode::with_name("__symbols__")));
+        }
+        let node = self.lookup_mut("/__symbols__")?;
+        for (label, path) in entries {
+            node.set_property(&label, Some(PropertyValue::string(&path)));
+        }
+
+        Ok(())
+    }
+}
+
+// deepest existing node of `path`, and the names below it that do not exist yet
+fn existing_ancestor(base: &DtbNode, path: &str) -> (String, Vec<String>) {
+    let names: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    let mut target = String::from("/");
+    for (i, name) in names.iter().enumerate() {
+        let next = child_path(&target, name);
+        if base.lookup(&next).is_err() {
+            return (target, names[i..].iter().map(|s| s.to_string()).collect());
+        }
+        target = next;
+    }
+    (target, vec![])
+}
+
+// Replace references in a patch value with what an overlay can carry:
+// `&label` cells become a placeholder recorded in __fixups__, `&{/path}` cells
+// the phandle the base tree already has, and bare references the target path.
+fn resolve_references(base: &DtbNode, value: PropertyValue, location: &str, fixups: &mut Vec<(String, Vec<String>)>) -> Result<PropertyValue, OverlayError> {
+    let mut chunks = vec![];
+    let mut offset = 0;
+
+    for chunk in value.0 {
+        let chunk = match chunk {
+            ValueChunk::Ref(reference) => ValueChunk::Str(base.resolve(&reference)?),
+            ValueChunk::Cells { bits, cells } => {
+                let mut resolved = vec![];
+                for (i, cell) in cells.into_iter().enumerate() {
+                    let cell = match cell {
+                        Cell::Ref(reference) if reference.starts_with("&{") => {
+                            match base.lookup(&reference)?.phandle() {
+                                Some(phandle) => Cell::Num(phandle as u64),
+                                None => return Err(OverlayError::Unsupported(format!("{} has no phandle in the base tree", reference))),
+                            }
+                        },
+                        Cell::Ref(reference) => {
+                            let label = reference.trim_start_matches('&').to_string();
+                            base.label_path(&label)?;
+                            let entry = format!("{}:{}", location, offset + i * (bits as usize / 8));
+                            match fixups.iter_mut().find(|(l, _)| *l == label) {
+                                Some((_, locations)) => locations.push(entry),
+                                None => fixups.push((label, vec![entry])),
+                            }
+                            Cell::Num(UNRESOLVED_PHANDLE)
+                        },
+                        cell => cell,
+                    };
+                    resolved.push(cell);
+                }
+                ValueChunk::Cells { bits, cells: resolved }
+            },
+            chunk => chunk,
+        };
+        offset += chunk_len(&chunk);
+        chunks.push(chunk);
+    }
+
+    Ok(PropertyValue(chunks))
+}
+
+fn chunk_len(chunk: &ValueChunk) -> usize {
+    match chunk {
+        ValueChunk::Str(s) => s.len() + 1,
+        ValueChunk::Cells { bits, cells } => cells.len() * (*bits as usize / 8),
+        ValueChunk::Bytes(bytes) => bytes.len(),
+        ValueChunk::Ref(reference) => reference.len() + 1,
+        // unknown until preprocessed
+        ValueChunk::Expr(_) => 0,
+    }
+}
+
+// Rewrite the 32-bit cell starting at byte `offset` of the encoded value.
+// Values decompiled from a blob may hold it in a byte string rather than in `< >`.
+fn patch_cell<F>(value: &mut PropertyValue, offset: usize, patch: F) -> bool
+where
+    F: Fn(u32) -> u32,
+{
+    let mut start = 0;
+    for chunk in value.0.iter_mut() {
+        let len = chunk_len(chunk);
+        if offset < start + len {
+            let at = offset - start;
+            match chunk {
+                ValueChunk::Cells { bits: 32, cells } if at.is_multiple_of(4) => {
+                    match cells.get_mut(at / 4) {
+                        Some(Cell::Num(cell)) => {
+                            *cell = patch(*cell as u32) as u64;
+                            return true;
+                        },
+                        Some(cell @ Cell::Ref(_)) => {
+                            *cell = Cell::Num(patch(UNRESOLVED_PHANDLE as u32) as u64);
+                            return true;
+                        },
+                        _ => return false,
+                    }
+                },
+                ValueChunk::Bytes(bytes) if at + 4 <= bytes.len() => {
+                    let cell = u32::from_be_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]]);
+                    bytes[at..at + 4].copy_from_slice(&patch(cell).to_be_bytes());
+                    return true;
+                },
+                _ => return false,
+            }
+        }
+        start += len;
+    }
+    false
+}
+
+fn renumber_phandles(node: &mut DtbNode, delta: u32) {
+    for property in node.properties.iter_mut().filter(|p| !p.deleted) {
+        if property.key == "phandle" || property.key == "linux,phandle" {
+            if let Some(phandle) = property.as_u32() {
+                property.set_value(PropertyValue::u32(phandle + delta));
+            }
+        }
+    }
+    for child in node.child_nodes.iter_mut() {
+        renumber_phandles(child, delta);
+    }
+}
+
+// __local_fixups__ mirrors the overlay tree, listing the offsets of cells that hold overlay phandles
+fn apply_local_fixups(overlay: &mut DtbNode, local_fixups: &DtbNode, path: &str, delta: u32) -> Result<(), OverlayError> {
+    for property in local_fixups.properties.iter().filter(|p| !p.deleted) {
+        let offsets = property.as_u32_cells().unwrap_or_default();
+        let value = overlay.lookup_mut(path)?.find_property(&property.key).and_then(|p| p.value.as_mut());
+        let value = match value {
+            Some(value) => value,
+            None => return Err(OverlayError::Malformed(format!("local fixup for missing property {}:{}", path, property.key))),
+        };
+        for offset in offsets {
+            if !patch_cell(value, offset as usize, |phandle| phandle + delta) {
+                return Err(OverlayError::Malformed(format!("local fixup {}:{}:{} does not point at a cell", path, property.key, offset)));
+            }
+        }
+    }
+    for child in local_fixups.child_nodes.iter().filter(|c| !c.deleted) {
+        apply_local_fixups(overlay, child, &child_path(path, &child.node_name), delta)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devicetree::value::Status;
+
+    const BASE: &str = "/dts-v1/;\n\n/ {\n\tsdhci@3440000 {\n\t\tstatus = \"disabled\";\n\t};\n};\n";
+
+    fn overlay(second_target: &str) -> DtbNode {
+        DtbNode::parse(&format!(
+            "/dts-v1/;\n/plugin/;\n\n/ {{\n\tfragment@0 {{\n\t\ttarget-path = \"/sdhci@3440000\";\n\t\t__overlay__ {{\n\t\t\tstatus = \"okay\";\n\t\t}};\n\t}};\n\n\tfragment@1 {{\n\t\ttarget-path = \"{}\";\n\t\t__overlay__ {{\n\t\t\tstatus = \"okay\";\n\t\t}};\n\t}};\n}};\n",
+            second_target
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn applies_without_symbols() {
+        let mut base = DtbNode::parse(BASE).unwrap();
+        base.apply_overlay(&overlay("/sdhci@3440000")).unwrap();
+        assert_eq!(base.lookup("/sdhci@3440000").unwrap().status(), Some(Status::Okay));
+        assert!(base.lookup("/__symbols__").is_err());
+    }
+
+    #[test]
+    fn leaves_the_tree_alone_on_error() {
+        let mut base = DtbNode::parse(BASE).unwrap();
+        assert!(base.apply_overlay(&overlay("/cam_i2cmux")).is_err());
+        assert_eq!(base.stringify(0), DtbNode::parse(BASE).unwrap().stringify(0));
+    }
+}
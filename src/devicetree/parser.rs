@@ -0,0 +1,512 @@
+use std::fmt;
+
+use super::document::{DtsDocument, DtsItem};
+use super::lexer::{Lexer, Token, TokenKind};
+use super::node::DtbNode;
+use super::property::DtbProperty;
+use super::value::{self, PropertyValue};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl ParseError {
+    pub fn at(source: &str, offset: usize, message: &str) -> ParseError {
+        let before = &source[..offset.min(source.len())];
+        let line = before.matches('\n').count() + 1;
+        let column = before.len() - before.rfind('\n').map(|i| i + 1).unwrap_or(0) + 1;
+
+        ParseError {
+            line,
+            column,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+struct Parser<'a> {
+    lexer: Lexer<'a>,
+    comments: Vec<String>,
+}
+
+impl<'a> Parser<'a> {
+    fn expect(&mut self, kind: TokenKind, what: &str) -> Result<Token, ParseError> {
+        let token = self.next_significant()?;
+        if token.kind != kind {
+            return Err(self.unexpected(&token, what));
+        }
+        Ok(token)
+    }
+
+    // next token, collecting any comments in front of it
+    fn next_significant(&mut self) -> Result<Token, ParseError> {
+        loop {
+            let token = self.next_token()?;
+            match token.kind {
+                TokenKind::Comment => self.comments.push(token.text),
+                _ => return Ok(token),
+            }
+        }
+    }
+
+    // next token, recording the empty lines in front of it as "" comments
+    fn next_token(&mut self) -> Result<Token, ParseError> {
+        let token = self.lexer.next_token()?;
+        for _ in 0..token.blank_lines {
+            self.comments.push(String::new());
+        }
+        Ok(token)
+    }
+
+    // a comment on the same line after `end`, the whitespace in front of it included
+    fn line_comment(&mut self, end: usize) -> Result<Option<String>, ParseError> {
+        let token = self.lexer.peek()?;
+        if token.kind != TokenKind::Comment || !token.same_line {
+            return Ok(None);
+        }
+        self.lexer.next_token()?;
+        Ok(Some(self.lexer.source()[end..token.offset + token.text.len()].to_string()))
+    }
+
+    fn unexpected(&self, token: &Token, what: &str) -> ParseError {
+        let found = match token.kind {
+            TokenKind::Eof => String::from("end of file"),
+            _ => format!("\"{}\"", token.text),
+        };
+        self.lexer.error(token.offset, &format!("expected {}, found {}", what, found))
+    }
+
+    // verbatim source from `start` through the next ';'
+    fn directive_statement(&mut self, start: usize) -> Result<String, ParseError> {
+        self.lexer.scan_value()?;
+        let end = self.expect(TokenKind::Semicolon, "';'")?.offset + 1;
+        Ok(self.lexer.source()[start..end].to_string())
+    }
+
+    fn parse_document(&mut self) -> Result<Vec<(DtsItem, usize)>, ParseError> {
+        let mut items = vec![];
+        let mut labels: Vec<String> = vec![];
+        let mut omit_if_no_ref = false;
+
+        loop {
+            let token = self.next_token()?;
+            match token.kind {
+                TokenKind::Eof => {
+                    if !labels.is_empty() || omit_if_no_ref {
+                        return Err(self.unexpected(&token, "node"));
+                    }
+                    break;
+                },
+                TokenKind::Comment => {
+                    self.comments.push(token.text);
+                },
+                TokenKind::Preprocessor => {
+                    let comments = std::mem::take(&mut self.comments);
+                    items.push((DtsItem::Directive { text: token.text, line_comment: None, comments }, token.offset));
+                },
+                TokenKind::Directive if token.text == "/omit-if-no-ref/" => {
+                    omit_if_no_ref = true;
+                },
+                TokenKind::Directive => {
+                    let text = match &token.text[..] {
+                        "/dts-v1/" | "/plugin/" => {
+                            let end = self.expect(TokenKind::Semicolon, "';'")?.offset + 1;
+                            self.lexer.source()[token.offset..end].to_string()
+                        },
+                        "/include/" => {
+                            let file = self.expect(TokenKind::String, "file name")?;
+                            self.lexer.source()[token.offset..file.offset + file.text.len()].to_string()
+                        },
+                        "/memreserve/" | "/delete-node/" => {
+                            self.directive_statement(token.offset)?
+                        },
+                        _ => {
+                            return Err(self.lexer.error(token.offset, &format!("unknown directive {}", token.text)));
+                        }
+                    };
+                    let line_comment = self.line_comment(token.offset + text.len())?;
+                    let comments = std::mem::take(&mut self.comments);
+                    items.push((DtsItem::Directive { text, line_comment, comments }, token.offset));
+                },
+                TokenKind::Label => {
+                    labels.push(token.text);
+                },
+                TokenKind::Word if token.text == "/" => {
+                    let node = self.parse_node(token.text.clone(), std::mem::take(&mut labels), omit_if_no_ref)?;
+                    omit_if_no_ref = false;
+                    items.push((DtsItem::Node(node), token.offset));
+                },
+                TokenKind::Reference => {
+                    let node = self.parse_node(token.text.clone(), std::mem::take(&mut labels), omit_if_no_ref)?;
+                    omit_if_no_ref = false;
+                    items.push((DtsItem::Node(node), token.offset));
+                },
+                _ => {
+                    return Err(self.unexpected(&token, "'/', '&label' or a directive"));
+                }
+            }
+        }
+
+        Ok(items)
+    }
+
+    // node body starting at '{', through the closing "};"
+    fn parse_node(&mut self, name: String, labels: Vec<String>, omit_if_no_ref: bool) -> Result<DtbNode, ParseError> {
+        let mut node = DtbNode::with_name(&name);
+        node.labels = labels;
+        node.omit_if_no_ref = omit_if_no_ref;
+        node.comments = std::mem::take(&mut self.comments);
+        node.source_layout = true;
+
+        let brace = self.expect(TokenKind::LBrace, "'{'")?;
+        node.open_comment = self.line_comment(brace.offset + 1)?;
+
+        let mut labels: Vec<String> = vec![];
+        let mut omit_if_no_ref = false;
+
+        loop {
+            let token = self.next_token()?;
+            match token.kind {
+                TokenKind::Comment | TokenKind::Preprocessor => {
+                    self.comments.push(token.text);
+                },
+                TokenKind::RBrace => {
+                    if !labels.is_empty() || omit_if_no_ref {
+                        return Err(self.unexpected(&token, "property or node name"));
+                    }
+                    node.trailing_comments = std::mem::take(&mut self.comments);
+                    let semicolon = self.expect(TokenKind::Semicolon, "';' after '}'")?;
+                    node.close_comment = self.line_comment(semicolon.offset + 1)?;
+                    return Ok(node);
+                },
+                TokenKind::Directive if token.text == "/delete-property/" || token.text == "/delete-node/" => {
+                    let target = self.next_significant()?;
+                    if target.kind != TokenKind::Word {
+                        return Err(self.unexpected(&target, "name"));
+                    }
+                    let semicolon = self.expect(TokenKind::Semicolon, "';'")?;
+                    let line_comment = self.line_comment(semicolon.offset + 1)?;
+                    let comments = std::mem::take(&mut self.comments);
+                    if token.text == "/delete-property/" {
+                        let mut property = DtbProperty::new(&target.text, None);
+                        property.comments = comments;
+                        property.line_comment = line_comment;
+                        property.deleted = true;
+                        node.properties.push(property);
+                    } else {
+                        let mut child = DtbNode::with_name(&target.text);
+                        child.comments = comments;
+                        child.close_comment = line_comment;
+                        child.source_layout = true;
+                        child.deleted = true;
+                        node.child_nodes.push(Box::new(child));
+                    }
+                },
+                TokenKind::Directive if token.text == "/omit-if-no-ref/" => {
+                    omit_if_no_ref = true;
+                },
+                TokenKind::Label => {
+                    labels.push(token.text);
+                },
+                TokenKind::Word => {
+                    let next = self.lexer.peek()?;
+                    match next.kind {
+                        TokenKind::LBrace => {
+                            let child = self.parse_node(token.text, std::mem::take(&mut labels), omit_if_no_ref)?;
+                            omit_if_no_ref = false;
+                            node.child_nodes.push(Box::new(child));
+                        },
+                        TokenKind::Equals | TokenKind::Semicolon => {
+                            if omit_if_no_ref {
+                                return Err(self.lexer.error(token.offset, "/omit-if-no-ref/ only applies to nodes"));
+                            }
+                            self.lexer.next_token()?;
+                            let (value, literal, end) = match next.kind {
+                                TokenKind::Equals => {
+                                    let raw = self.lexer.scan_value()?;
+                                    let semicolon = self.expect(TokenKind::Semicolon, "';'")?;
+                                    let value = PropertyValue::parse(&raw.text)
+                                        .map_err(|e| self.lexer.error(raw.offset + e.offset, &e.message))?;
+                                    (Some(value), Some(raw.text), semicolon.offset + 1)
+                                },
+                                _ => (None, None, next.offset + 1),
+                            };
+                            let mut property = DtbProperty::new(&token.text, value);
+                            property.literal = literal;
+                            property.line_comment = self.line_comment(end)?;
+                            property.labels = std::mem::take(&mut labels);
+                            property.comments = std::mem::take(&mut self.comments);
+                            node.properties.push(property);
+                        },
+                        _ => {
+                            return Err(self.unexpected(&next, "'=', ';' or '{'"));
+                        }
+                    }
+                },
+                _ => {
+                    return Err(self.unexpected(&token, "property, node or '}'"));
+                }
+            }
+        }
+    }
+}
+
+// items with the source offset they start at, and comments after the last item
+type ParsedItems = (Vec<(DtsItem, usize)>, Vec<String>);
+
+fn parse_items(source: &str) -> Result<ParsedItems, ParseError> {
+    let mut parser = Parser {
+        lexer: Lexer::new(source),
+        comments: vec![],
+    };
+
+    let items = parser.parse_document()?;
+    Ok((items, parser.comments))
+}
+
+pub fn parse_document(source: &str) -> Result<DtsDocument, ParseError> {
+    let (items, trailing_comments) = parse_items(source)?;
+
+    Ok(DtsDocument {
+        items: items.into_iter().map(|(item, _)| item).collect(),
+        trailing_comments,
+    })
+}
+
+// The tree, and the `/memreserve/ <address> <size>;` entries as (address, size)
+pub fn parse_tree(source: &str) -> Result<(DtbNode, Vec<(u64, u64)>), ParseError> {
+    let (items, _) = parse_items(source)?;
+    let mut root: Option<DtbNode> = None;
+    let mut reserved = vec![];
+
+    for (item, offset) in items {
+        match item {
+            DtsItem::Node(node) if node.node_name == "/" => {
+                let target = root.get_or_insert_with(|| {
+                    let mut root = DtbNode::empty_like(&node);
+                    // the empty lines in front of it separate it from directives, which are not part of the tree
+                    let blank = root.comments.iter().take_while(|comment| comment.is_empty()).count();
+                    root.comments.drain(..blank);
+                    root
+                });
+                target.merge(&node);
+            },
+            DtsItem::Node(node) => {
+                let target = match root.as_mut() {
+                    Some(root) => root.lookup_mut(&node.node_name).ok(),
+                    None => None,
+                };
+                match target {
+                    Some(target) => target.merge(&node),
+                    None => return Err(ParseError::at(source, offset, &format!("reference {} not found", node.node_name))),
+                }
+            },
+            DtsItem::Directive { text, .. } if text.starts_with("/memreserve/") => {
+                let numbers: Vec<Option<u64>> = text["/memreserve/".len()..].trim_end_matches(';').split_whitespace().map(value::parse_integer).collect();
+                match numbers[..] {
+                    [Some(address), Some(size)] => reserved.push((address, size)),
+                    _ => return Err(ParseError::at(source, offset, "expected /memreserve/ <address> <size>;")),
+                }
+            },
+            DtsItem::Directive { text, .. } if text.starts_with("/delete-node/") => {
+                let reference = text.trim_start_matches("/delete-node/").trim().trim_end_matches(';').trim();
+                let removed = match root.as_mut() {
+                    Some(root) => remove_reference(root, reference),
+                    None => false,
+                };
+                if !removed {
+                    return Err(ParseError::at(source, offset, &format!("node {} not found", reference)));
+                }
+            },
+            _ => {}
+        }
+    }
+
+    match root {
+        Some(root) => Ok((root, reserved)),
+        None => Err(ParseError::at(source, source.len(), "missing root node")),
+    }
+}
+
+fn remove_reference(root: &mut DtbNode, reference: &str) -> bool {
+    let path = match root.resolve(reference) {
+        Ok(path) => path,
+        Err(_) => return false,
+    };
+    let (parent, name) = match path.rsplit_once('/') {
+        Some(split) => split,
+        None => return false,
+    };
+    match root.lookup_mut(&format!("&{{{}}}", parent)) {
+        Ok(parent) => {
+            let count = parent.child_nodes.len();
+            parent.child_nodes.retain(|c| c.node_name != name);
+            parent.child_nodes.len() != count
+        },
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // laid out the way the L4T r35 sources are: tabs, C preprocessor, macros in cells
+    const BOARD: &str = r#"// SPDX-License-Identifier: GPL-2.0-only
+/*
+ * Copyright (c) 2022-2023, NVIDIA CORPORATION.  All rights reserved.
+ */
+/dts-v1/;
+
+#include "tegra234-p3767-0000-p3768-0000-a0.dts"
+#include <dt-bindings/gpio/tegra234-gpio.h>
+#include <dt-bindings/input/input.h>
+
+/memreserve/ 0x80000000 0x00010000;
+
+/ {
+	nvidia,dtsfilename = __FILE__;
+	nvidia,dtbbuildtime = __DATE__, __TIME__;
+
+	compatible = "nvidia,p3768-0000+p3767-0005", "nvidia,p3767-0005", "nvidia,tegra234";
+	model = "NVIDIA Orin Nano Developer Kit";
+
+	bus@0 {
+		/* UARTA, debug console on the 40-pin header */
+		serial@3100000 {
+			status = "okay";
+		};
+
+		i2c@3160000 {
+			status = "okay";
+			clock-frequency = <400000>; /* 400 kHz */
+			#address-cells = <1>;
+			#size-cells = <0>;
+
+			eeprom@50 {
+				compatible = "atmel,24c02";
+				reg = <0x50>;
+				pagesize = <8>;
+				read-only;
+			};
+		};
+	};
+
+	gpio-keys {
+		compatible = "gpio-keys";
+		status = "okay";
+
+		key-power {
+			label = "Power";
+			gpios = <&gpio_aon TEGRA234_AON_GPIO(EE, 4) GPIO_ACTIVE_LOW>;
+			linux,input-type = <EV_KEY>;
+			linux,code = <KEY_POWER>;
+			wakeup-event-action = <EV_ACT_ASSERTED>;
+			wakeup-source;
+		};
+	};
+
+	reserved-memory {
+		#address-cells = <2>;
+		#size-cells = <2>;
+		ranges;
+
+		linux,cma { // CMA for the display
+			compatible = "shared-dma-pool";
+			reusable;
+			size = <0x0 0x10000000>;	// 256 MiB
+			alignment = <0x0 0x10000>;
+			status = "disabled";
+		};
+	};
+};
+"#;
+
+    // a pinmux/override file: values over several lines, labels and comments inside values,
+    // `&label` overrides and deletions
+    const OVERRIDES: &str = r#"/dts-v1/;
+/plugin/;
+
+#include <dt-bindings/pinctrl/pinctrl-tegra.h>
+
+&pinmux {
+	pinctrl-names = "default", "drive";
+	pinctrl-0 = <&pinmux_default>;
+
+	pinmux_default: common {
+		/* SFIO Pin Configuration */
+		soc_gpio59_pac6 {
+			nvidia,pins = "soc_gpio59_pac6";
+			nvidia,function = "rsvd1";
+			nvidia,pull = <TEGRA_PIN_PULL_NONE>;
+			nvidia,tristate = <TEGRA_PIN_DISABLE>;
+			nvidia,enable-input = <TEGRA_PIN_ENABLE>;
+			nvidia,io-high-voltage = <TEGRA_PIN_DISABLE>;
+			nvidia,lpdr = <TEGRA_PIN_DISABLE>;
+		};
+	};
+};
+
+&{/bus@0/pcie@14160000} {
+	interrupts = <0 51 0x04>, /* controller */
+		     <0 52 0x04>; /* MSI */
+	num-lanes = <4>;
+	max-link-speed = <0x4>;
+	reg = start: <0x00 0x14160000 0x0 0x00020000
+		      0x00 0x36000000 0x0 0x00040000>;
+	/delete-property/ nvidia,enable-power-down; // keep the link up
+
+	/delete-node/ pci@0,0;
+};
+
+/delete-node/ &cma;
+
+
+/* end of overrides */
+"#;
+
+    #[test]
+    fn round_trips_r35_sources() {
+        for source in [BOARD, OVERRIDES] {
+            let document = DtsDocument::parse(source).unwrap();
+            assert_eq!(document.stringify(), source);
+        }
+    }
+
+    #[test]
+    fn keeps_value_literals() {
+        let document = DtsDocument::parse(BOARD).unwrap();
+        let root = document.root().unwrap();
+        let i2c = root.child_nodes[0].child_nodes[1].as_ref();
+        let frequency = &i2c.properties[1];
+        assert_eq!(frequency.as_u32(), Some(400000));
+        assert_eq!(frequency.literal.as_deref(), Some("<400000>"));
+        assert_eq!(frequency.line_comment.as_deref(), Some(" /* 400 kHz */"));
+    }
+
+    #[test]
+    fn prints_changed_values_in_canonical_form() {
+        let mut document = DtsDocument::parse(BOARD).unwrap();
+        let i2c = document.root_mut().unwrap().lookup_mut("/bus@0/i2c@3160000").unwrap();
+        i2c.set_property("clock-frequency", Some(PropertyValue::u32(100000)));
+        assert!(document.stringify().contains("\t\t\tclock-frequency = <0x186a0>;\n"));
+    }
+
+    #[test]
+    fn keeps_root_comments() {
+        let source = "/dts-v1/;\n\n// board root\n/ {\n\tmodel = \"a\";\n\t/* last */\n};\n\n/ {\n\tmodel = \"b\";\n};\n";
+        let root = DtbNode::parse(source).unwrap();
+        assert_eq!(root.stringify(0), "// board root\n/ {\n\tmodel = \"b\";\n\t/* last */\n};\n");
+    }
+}
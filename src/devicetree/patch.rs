@@ -0,0 +1,250 @@
+use std::fmt;
+use std::fs;
+use std::io;
+
+use serde::Deserialize;
+
+use super::node::DtbNode;
+use super::query::LookupError;
+use super::value::PropertyValue;
+
+// A device tree patch file, e.g.
+//
+//     [[node]]
+//     path = "/cam_i2cmux/i2c@0/rbpcv3_imx477_a@1a"
+//     set = { status = "okay" }
+//
+// `path` is anything DtbNode::lookup accepts: an absolute path, an alias or a &label.
+// `set` changes properties the node already has; with `create = true` it also adds them.
+// Property values in `set` are typed by their TOML type: a string is a DTS string,
+// an integer a single cell, an array a string list or cell list, `true` an empty
+// property, and `{ dts = '<&gpio 1 0>' }` any value written in DTS syntax.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DtbPatch {
+    #[serde(default)]
+    pub overlay: Option<OverlayConfig>,
+    #[serde(default, rename = "node")]
+    pub nodes: Vec<NodePatch>,
+}
+
+// Ship the patch as an overlay instead of rewriting the stock DTB:
+//
+//     [overlay]
+//     name = "sg-orin-nx.dtbo"
+//     board-configs = ["jetson-orin-nano-devkit.conf"]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct OverlayConfig {
+    // file written next to the DTB in kernel/dtb
+    pub name: String,
+    // board configurations whose OVERLAY_DTB_FILE gets the overlay appended
+    #[serde(default)]
+    pub board_configs: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct NodePatch {
+    pub path: String,
+    // create the node (and missing parents) and the properties in `set` instead of failing
+    // when they do not exist
+    #[serde(default)]
+    pub create: bool,
+    #[serde(default)]
+    pub delete: bool,
+    #[serde(default)]
+    pub set: toml::Table,
+    #[serde(default)]
+    pub delete_properties: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum PatchError {
+    Io(io::Error),
+    Syntax(toml::de::Error),
+    NodeNotFound(LookupError),
+    PropertyNotFound { path: String, key: String },
+    InvalidValue { path: String, key: String, reason: String },
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PatchError::Io(e) => write!(f, "{}", e),
+            PatchError::Syntax(e) => write!(f, "{}", e),
+            PatchError::NodeNotFound(e) => write!(f, "{}", e),
+            PatchError::PropertyNotFound { path, key } => write!(f, "{} has no property {} to set, add create = true to add it", path, key),
+            PatchError::InvalidValue { path, key, reason } => write!(f, "invalid value for {}:{}: {}", path, key, reason),
+        }
+    }
+}
+
+impl std::error::Error for PatchError {}
+
+impl From<io::Error> for PatchError {
+    fn from(e: io::Error) -> Self {
+        PatchError::Io(e)
+    }
+}
+
+impl From<LookupError> for PatchError {
+    fn from(e: LookupError) -> Self {
+        PatchError::NodeNotFound(e)
+    }
+}
+
+impl DtbPatch {
+    pub fn load(path: &str) -> Result<DtbPatch, PatchError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(content: &str) -> Result<DtbPatch, PatchError> {
+        let patch: DtbPatch = toml::from_str(content).map_err(PatchError::Syntax)?;
+
+        // check every value before anything is applied
+        for node in &patch.nodes {
+            node.properties()?;
+        }
+
+        Ok(patch)
+    }
+
+    pub fn apply(&self, root: &mut DtbNode) -> Result<(), PatchError> {
+        for node_patch in &self.nodes {
+            node_patch.apply(root)?;
+        }
+        Ok(())
+    }
+}
+
+impl NodePatch {
+    pub fn properties(&self) -> Result<Vec<(String, Option<PropertyValue>)>, PatchError> {
+        self.set
+            .iter()
+            .map(|(key, value)| {
+                to_property_value(value)
+                    .map(|value| (key.clone(), value))
+                    .map_err(|reason| PatchError::InvalidValue { path: self.path.clone(), key: key.clone(), reason })
+            })
+            .collect()
+    }
+
+    // without `create`, `set` only changes properties `node` already has
+    pub fn check_properties(&self, node: &DtbNode) -> Result<(), PatchError> {
+        if self.create {
+            return Ok(());
+        }
+        for key in self.set.keys() {
+            if !node.properties.iter().any(|p| &p.key == key && !p.deleted) {
+                return Err(PatchError::PropertyNotFound { path: self.path.clone(), key: key.clone() });
+            }
+        }
+        Ok(())
+    }
+
+    fn apply(&self, root: &mut DtbNode) -> Result<(), PatchError> {
+        if self.delete {
+            let path = root.resolve(&self.path)?;
+            let (parent, name) = path.rsplit_once('/').unwrap_or(("", &path));
+            let parent = root.lookup_mut(&format!("&{{{}}}", parent))?;
+            parent.child_nodes.retain(|c| c.node_name != name);
+            return Ok(());
+        }
+
+        let properties = self.properties()?;
+        let node = match root.lookup(&self.path) {
+            Err(LookupError::PathNotFound { path, .. }) if self.create => create(root, &path),
+            _ => root.lookup_mut(&self.path)?,
+        };
+        self.check_properties(node)?;
+
+        for key in &self.delete_properties {
+            node.properties.retain(|p| &p.key != key);
+        }
+        for (key, value) in properties {
+            node.set_property(&key, value);
+        }
+
+        Ok(())
+    }
+}
+
+// walk an absolute path, adding the nodes that do not exist yet
+fn create<'a>(root: &'a mut DtbNode, path: &str) -> &'a mut DtbNode {
+    let mut node = root;
+    for name in path.split('/').filter(|s| !s.is_empty()) {
+        let index = match node.child_nodes.iter().position(|c| c.node_name == name && !c.deleted) {
+            Some(index) => index,
+            None => {
+                node.child_nodes.push(Box::new(DtbNode::with_name(name)));
+                node.child_nodes.len() - 1
+            }
+        };
+        node = &mut node.child_nodes[index];
+    }
+    node
+}
+
+fn to_property_value(value: &toml::Value) -> Result<Option<PropertyValue>, String> {
+    match value {
+        toml::Value::String(s) => Ok(Some(PropertyValue::string(s))),
+        toml::Value::Integer(i) => Ok(Some(PropertyValue::u32(to_cell(*i)?))),
+        toml::Value::Boolean(true) => Ok(None),
+        toml::Value::Array(items) if items.iter().all(|v| v.is_str()) => {
+            let list: Vec<&str> = items.iter().filter_map(|v| v.as_str()).collect();
+            Ok(Some(PropertyValue::strings(&list)))
+        },
+        toml::Value::Array(items) if items.iter().all(|v| v.is_integer()) => {
+            let cells = items.iter().map(|v| to_cell(v.as_integer().unwrap())).collect::<Result<Vec<u32>, String>>()?;
+            Ok(Some(PropertyValue::cells(&cells)))
+        },
+        toml::Value::Table(table) => match (table.len(), table.get("dts")) {
+            (1, Some(toml::Value::String(dts))) => PropertyValue::parse(dts).map(Some).map_err(|e| e.message),
+            _ => Err(String::from("expected { dts = '...' }")),
+        },
+        _ => Err(String::from("expected a string, integer, array of strings or integers, true or { dts = '...' }")),
+    }
+}
+
+fn to_cell(value: i64) -> Result<u32, String> {
+    u32::try_from(value).map_err(|_| format!("{} does not fit in a 32-bit cell", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devicetree::value::Status;
+
+    const BASE: &str = "/dts-v1/;\n\n/ {\n\tsdhci@3440000 {\n\t\tstatus = \"disabled\";\n\t};\n};\n";
+
+    #[test]
+    fn sets_existing_properties() {
+        let mut root = DtbNode::parse(BASE).unwrap();
+        let patch = DtbPatch::parse("[[node]]\npath = \"/sdhci@3440000\"\nset = { status = \"okay\" }\n").unwrap();
+        patch.apply(&mut root).unwrap();
+        assert_eq!(root.lookup("/sdhci@3440000").unwrap().status(), Some(Status::Okay));
+    }
+
+    #[test]
+    fn refuses_to_add_properties_without_create() {
+        let mut root = DtbNode::parse(BASE).unwrap();
+        let patch = DtbPatch::parse("[[node]]\npath = \"/sdhci@3440000\"\nset = { stauts = \"okay\" }\n").unwrap();
+        match patch.apply(&mut root) {
+            Err(PatchError::PropertyNotFound { path, key }) => {
+                assert_eq!(path, "/sdhci@3440000");
+                assert_eq!(key, "stauts");
+            },
+            result => panic!("unexpected {:?}", result),
+        }
+    }
+
+    #[test]
+    fn adds_properties_with_create() {
+        let mut root = DtbNode::parse(BASE).unwrap();
+        let patch = DtbPatch::parse("[[node]]\npath = \"/sdhci@3440000\"\ncreate = true\nset = { bus-width = 4 }\n").unwrap();
+        patch.apply(&mut root).unwrap();
+        let sdhci = root.lookup("/sdhci@3440000").unwrap();
+        assert_eq!(sdhci.properties.iter().find(|p| p.key == "bus-width").and_then(|p| p.as_u32()), Some(4));
+    }
+}
@@ -1,8 +1,20 @@
 use std::fmt;
 
+use super::node::DtbNode;
+use super::value::PropertyValue;
+
+#[derive(Clone)]
 pub struct DtbProperty {
     pub key: String,
-    pub value: Option<String>,
+    pub value: Option<PropertyValue>,
+    // `value` as written in the source, printed instead of it for as long as the two agree
+    pub literal: Option<String>,
+    pub labels: Vec<String>,
+    pub comments: Vec<String>,
+    // comment on the same line after the ';', with the whitespace in front of it
+    pub line_comment: Option<String>,
+    // `/delete-property/ key;`
+    pub deleted: bool,
 }
 
 impl fmt::Debug for DtbProperty {
@@ -10,6 +22,86 @@ impl fmt::Debug for DtbProperty {
         f.debug_struct("DtbProperty")
             .field("key", &self.key)
             .field("value", &self.value)
+            .field("labels", &self.labels)
+            .field("deleted", &self.deleted)
             .finish()
     }
 }
+
+impl DtbProperty {
+    pub fn new(key: &str, value: Option<PropertyValue>) -> DtbProperty {
+        DtbProperty {
+            key: key.to_string(),
+            value,
+            literal: None,
+            labels: vec![],
+            comments: vec![],
+            line_comment: None,
+            deleted: false,
+        }
+    }
+
+    pub fn set_value(&mut self, value: PropertyValue) {
+        self.value = Some(value);
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        self.value.as_ref()?.as_str()
+    }
+
+    pub fn as_str_list(&self) -> Option<Vec<&str>> {
+        self.value.as_ref()?.as_str_list()
+    }
+
+    pub fn as_u32(&self) -> Option<u32> {
+        self.value.as_ref()?.as_u32()
+    }
+
+    pub fn as_u32_cells(&self) -> Option<Vec<u32>> {
+        self.value.as_ref()?.as_u32_cells()
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        self.value.as_ref()?.as_u64()
+    }
+
+    pub fn stringify(&self, indent: usize) -> String {
+        let mut ret = DtbNode::comment_lines(&self.comments, indent);
+
+        ret.push_str(&"\t".repeat(indent));
+
+        if self.deleted {
+            ret.push_str("/delete-property/ ");
+            ret.push_str(&self.key);
+            ret.push(';');
+            ret.push_str(self.line_comment.as_deref().unwrap_or(""));
+            ret.push('\n');
+            return ret;
+        }
+
+        for label in &self.labels {
+            ret.push_str(label);
+            ret.push_str(": ");
+        }
+        ret.push_str(&self.key);
+        // a changed value is printed in canonical form, without the comment that was about the old one
+        let written = match (&self.value, &self.literal) {
+            (Some(value), Some(literal)) => PropertyValue::parse(literal).as_ref() == Ok(value),
+            (value, literal) => value.is_none() && literal.is_none(),
+        };
+        if let Some(value) = &self.value {
+            ret.push_str(" = ");
+            match &self.literal {
+                Some(literal) if written => ret.push_str(literal),
+                _ => ret.push_str(&value.to_string()),
+            }
+        }
+        ret.push(';');
+        if written {
+            ret.push_str(self.line_comment.as_deref().unwrap_or(""));
+        }
+        ret.push('\n');
+
+        ret
+    }
+}
@@ -0,0 +1,343 @@
+use std::fmt;
+
+use super::fdt::child_path;
+use super::node::DtbNode;
+use super::value::Status;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LookupError {
+    // `missing` is the first path component that does not exist
+    PathNotFound { path: String, missing: String },
+    AliasNotFound(String),
+    LabelNotFound(String),
+    PhandleNotFound(u32),
+}
+
+impl fmt::Display for LookupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LookupError::PathNotFound { path, missing } => write!(f, "path not found: {} (no node {})", path, missing),
+            LookupError::AliasNotFound(alias) => write!(f, "alias {} not found in /aliases", alias),
+            LookupError::LabelNotFound(label) => write!(f, "label {} not found", label),
+            LookupError::PhandleNotFound(phandle) => write!(f, "no node with phandle 0x{:x}", phandle),
+        }
+    }
+}
+
+impl std::error::Error for LookupError {}
+
+// Lookups take the same node references dtc and the kernel accept:
+//
+//     /cam_i2cmux/i2c@0/rbpcv3_imx477_a@1a    absolute path
+//     serial0, i2c1/eeprom@50                 alias from /aliases, optionally followed by a path
+//     &cam_i2c0                               label, or a symbol from /__symbols__
+//     &{/cam_i2cmux/i2c@0}                    path reference
+impl DtbNode {
+    pub fn lookup(&self, path: &str) -> Result<&DtbNode, LookupError> {
+        let indices = self.locate(path)?;
+        Ok(indices.iter().fold(self, |node, &i| &node.child_nodes[i]))
+    }
+
+    pub fn lookup_mut(&mut self, path: &str) -> Result<&mut DtbNode, LookupError> {
+        let indices = self.locate(path)?;
+        Ok(indices.iter().fold(self, |node, &i| &mut node.child_nodes[i]))
+    }
+
+    pub fn lookup_phandle(&self, phandle: u32) -> Result<&DtbNode, LookupError> {
+        let path = self.phandle_path(phandle)?;
+        self.lookup(&path)
+    }
+
+    pub fn lookup_phandle_mut(&mut self, phandle: u32) -> Result<&mut DtbNode, LookupError> {
+        let path = self.phandle_path(phandle)?;
+        self.lookup_mut(&path)
+    }
+
+    // Absolute path of an alias in /aliases
+    pub fn alias(&self, alias: &str) -> Result<String, LookupError> {
+        self.locate_path("/aliases")
+            .ok()
+            .map(|indices| indices.iter().fold(self, |node, &i| &node.child_nodes[i]))
+            .and_then(|aliases| aliases.properties.iter().find(|p| p.key == alias && !p.deleted))
+            .and_then(|p| p.as_str())
+            .map(|path| path.to_string())
+            .ok_or(LookupError::AliasNotFound(alias.to_string()))
+    }
+
+    // Absolute path of a label, from the node labels of a parsed source or /__symbols__ of a compiled tree
+    pub fn label_path(&self, label: &str) -> Result<String, LookupError> {
+        if let Some((path, _)) = self.walk().into_iter().find(|(_, node)| node.labels.iter().any(|l| l == label)) {
+            return Ok(path);
+        }
+
+        self.locate_path("/__symbols__")
+            .ok()
+            .map(|indices| indices.iter().fold(self, |node, &i| &node.child_nodes[i]))
+            .and_then(|symbols| symbols.properties.iter().find(|p| p.key == label && !p.deleted))
+            .and_then(|p| p.as_str())
+            .map(|path| path.to_string())
+            .ok_or(LookupError::LabelNotFound(label.to_string()))
+    }
+
+    pub fn phandle(&self) -> Option<u32> {
+        self.properties
+            .iter()
+            .filter(|p| !p.deleted)
+            .find(|p| p.key == "phandle" || p.key == "linux,phandle")
+            .and_then(|p| p.as_u32())
+    }
+
+    pub fn is_compatible(&self, compatible: &str) -> bool {
+        self.properties
+            .iter()
+            .find(|p| p.key == "compatible" && !p.deleted)
+            .and_then(|p| p.as_str_list())
+            .map(|list| list.contains(&compatible))
+            .unwrap_or(false)
+    }
+
+    // Every node of the tree with its absolute path, depth first
+    pub fn walk(&self) -> Vec<(String, &DtbNode)> {
+        let mut nodes = vec![];
+        walk_into(self, String::from("/"), &mut nodes);
+        nodes
+    }
+
+    pub fn find_all<F>(&self, predicate: F) -> Vec<(String, &DtbNode)>
+    where
+        F: Fn(&DtbNode) -> bool,
+    {
+        self.walk().into_iter().filter(|(_, node)| predicate(node)).collect()
+    }
+
+    pub fn find_compatible(&self, compatible: &str) -> Vec<(String, &DtbNode)> {
+        self.find_all(|node| node.is_compatible(compatible))
+    }
+
+    pub fn find_with_status(&self, status: Status) -> Vec<(String, &DtbNode)> {
+        self.find_all(|node| node.status() == Some(status))
+    }
+
+    // Nodes whose path matches a glob: `*` and `?` match within one component,
+    // `**` matches any number of components, e.g. "/cam_i2cmux/*/rbpcv3_imx477_*" or "/**/endpoint"
+    pub fn select(&self, pattern: &str) -> Vec<(String, &DtbNode)> {
+        let pattern: Vec<&str> = pattern.split('/').filter(|s| !s.is_empty()).collect();
+        self.walk()
+            .into_iter()
+            .filter(|(path, _)| {
+                let names: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+                glob_path(&pattern, &names)
+            })
+            .collect()
+    }
+
+    // Absolute path of the referenced node
+    pub fn resolve(&self, path: &str) -> Result<String, LookupError> {
+        let path = self.absolute_path(path)?;
+        self.locate_path(&path)?;
+        Ok(path)
+    }
+
+    // aliases and labels replaced by the path they stand for, without checking the node exists
+    fn absolute_path(&self, path: &str) -> Result<String, LookupError> {
+        if let Some(path) = path.strip_prefix("&{").and_then(|p| p.strip_suffix('}')) {
+            return Ok(path.to_string());
+        }
+        if let Some(label) = path.strip_prefix('&') {
+            return self.label_path(label);
+        }
+        if path.starts_with('/') {
+            return Ok(path.to_string());
+        }
+
+        let (alias, rest) = path.split_once('/').unwrap_or((path, ""));
+        let target = self.alias(alias)?;
+        match rest {
+            "" => Ok(target),
+            _ => Ok(child_path(&target, rest)),
+        }
+    }
+
+    // child indices from this node down to the referenced node
+    fn locate(&self, path: &str) -> Result<Vec<usize>, LookupError> {
+        self.locate_path(&self.absolute_path(path)?)
+    }
+
+    fn locate_path(&self, path: &str) -> Result<Vec<usize>, LookupError> {
+        let mut indices = vec![];
+        let mut node = self;
+        let mut current = String::from("/");
+
+        for name in path.split('/').filter(|s| !s.is_empty()) {
+            current = child_path(&current, name);
+            match node.child_nodes.iter().position(|c| c.node_name == name && !c.deleted) {
+                Some(index) => {
+                    indices.push(index);
+                    node = &node.child_nodes[index];
+                },
+                None => {
+                    return Err(LookupError::PathNotFound { path: path.to_string(), missing: current });
+                }
+            }
+        }
+
+        Ok(indices)
+    }
+
+    // Absolute path of the node with this phandle
+    pub fn phandle_path(&self, phandle: u32) -> Result<String, LookupError> {
+        self.walk()
+            .into_iter()
+            .find(|(_, node)| node.phandle() == Some(phandle))
+            .map(|(path, _)| path)
+            .ok_or(LookupError::PhandleNotFound(phandle))
+    }
+}
+
+fn walk_into<'a>(node: &'a DtbNode, path: String, nodes: &mut Vec<(String, &'a DtbNode)>) {
+    nodes.push((path.clone(), node));
+    for child in node.child_nodes.iter().filter(|c| !c.deleted) {
+        walk_into(child, child_path(&path, &child.node_name), nodes);
+    }
+}
+
+fn glob_path(pattern: &[&str], names: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => names.is_empty(),
+        Some((&"**", rest)) => (0..=names.len()).any(|skip| glob_path(rest, &names[skip..])),
+        Some((first, rest)) => match names.split_first() {
+            Some((name, names)) => glob(first.as_bytes(), name.as_bytes()) && glob_path(rest, names),
+            None => false,
+        },
+    }
+}
+
+fn glob(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => (0..=name.len()).any(|skip| glob(rest, &name[skip..])),
+        Some((b'?', rest)) => !name.is_empty() && glob(rest, &name[1..]),
+        Some((c, rest)) => name.first() == Some(c) && glob(rest, &name[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DTS: &str = r#"/dts-v1/;
+
+/ {
+	aliases {
+		i2c1 = "/cam_i2cmux/i2c@0";
+		serial0 = "/serial@3100000";
+	};
+
+	serial@3100000 {
+		compatible = "nvidia,tegra194-hsuart";
+		status = "okay";
+	};
+
+	gpio: gpio@2200000 {
+		compatible = "nvidia,tegra234-gpio", "nvidia,tegra194-gpio";
+		phandle = <0x9c>;
+	};
+
+	cam_i2cmux {
+		cam_i2c0: i2c@0 {
+			rbpcv3_imx477_a@1a {
+				status = "disabled";
+			};
+			rbpcv2_imx219_a@10 {
+				status = "disabled";
+				ports {
+					port@0 {
+						endpoint {
+						};
+					};
+				};
+			};
+		};
+		i2c@1 {
+			rbpcv3_imx477_c@1a {
+				compatible = "sony,imx477";
+			};
+		};
+	};
+
+	__symbols__ {
+		uartc = "/serial@3100000";
+	};
+};
+"#;
+
+    fn tree() -> DtbNode {
+        DtbNode::parse(DTS).unwrap()
+    }
+
+    fn paths(nodes: Vec<(String, &DtbNode)>) -> Vec<String> {
+        nodes.into_iter().map(|(path, _)| path).collect()
+    }
+
+    #[test]
+    fn resolves_aliases() {
+        let root = tree();
+        assert_eq!(root.resolve("serial0").unwrap(), "/serial@3100000");
+        assert_eq!(root.resolve("i2c1/rbpcv3_imx477_a@1a").unwrap(), "/cam_i2cmux/i2c@0/rbpcv3_imx477_a@1a");
+        assert_eq!(root.lookup("i2c1").unwrap().node_name, "i2c@0");
+
+        assert_eq!(root.resolve("i2c7"), Err(LookupError::AliasNotFound(String::from("i2c7"))));
+        assert_eq!(root.resolve("i2c1/eeprom@50"), Err(LookupError::PathNotFound {
+            path: String::from("/cam_i2cmux/i2c@0/eeprom@50"),
+            missing: String::from("/cam_i2cmux/i2c@0/eeprom@50"),
+        }));
+    }
+
+    #[test]
+    fn resolves_labels_symbols_and_path_references() {
+        let root = tree();
+        assert_eq!(root.label_path("cam_i2c0").unwrap(), "/cam_i2cmux/i2c@0");
+        assert_eq!(root.resolve("&gpio").unwrap(), "/gpio@2200000");
+        // a compiled tree only has them in /__symbols__
+        assert_eq!(root.resolve("&uartc").unwrap(), "/serial@3100000");
+        assert_eq!(root.resolve("&{/cam_i2cmux/i2c@1}").unwrap(), "/cam_i2cmux/i2c@1");
+
+        assert_eq!(root.resolve("&cam_i2c9"), Err(LookupError::LabelNotFound(String::from("cam_i2c9"))));
+        assert!(root.resolve("&{/cam_i2cmux/i2c@2}").is_err());
+    }
+
+    #[test]
+    fn finds_nodes_by_phandle() {
+        let mut root = tree();
+        assert_eq!(root.lookup_phandle(0x9c).unwrap().node_name, "gpio@2200000");
+        root.lookup_phandle_mut(0x9c).unwrap().set_status(Status::Disabled);
+        assert_eq!(root.lookup("&gpio").unwrap().status(), Some(Status::Disabled));
+
+        assert_eq!(root.lookup_phandle(0x9d).err(), Some(LookupError::PhandleNotFound(0x9d)));
+    }
+
+    #[test]
+    fn selects_by_glob() {
+        let root = tree();
+        assert_eq!(paths(root.select("/cam_i2cmux/*/rbpcv3_imx477_*")), [
+            "/cam_i2cmux/i2c@0/rbpcv3_imx477_a@1a",
+            "/cam_i2cmux/i2c@1/rbpcv3_imx477_c@1a",
+        ]);
+        assert_eq!(paths(root.select("/**/endpoint")), ["/cam_i2cmux/i2c@0/rbpcv2_imx219_a@10/ports/port@0/endpoint"]);
+        assert_eq!(paths(root.select("/cam_i2cmux/i2c@?")), ["/cam_i2cmux/i2c@0", "/cam_i2cmux/i2c@1"]);
+        // `*` stays within one component
+        assert!(root.select("/cam_i2cmux/*").iter().all(|(path, _)| path.matches('/').count() == 2));
+        assert!(root.select("/cam_i2cmux/i2c@2/**").is_empty());
+    }
+
+    #[test]
+    fn finds_compatible_nodes() {
+        let root = tree();
+        // any entry of the list
+        assert_eq!(paths(root.find_compatible("nvidia,tegra194-gpio")), ["/gpio@2200000"]);
+        assert_eq!(paths(root.find_compatible("sony,imx477")), ["/cam_i2cmux/i2c@1/rbpcv3_imx477_c@1a"]);
+        assert!(root.find_compatible("nvidia,tegra194").is_empty());
+
+        assert_eq!(paths(root.find_with_status(Status::Disabled)).len(), 2);
+    }
+}
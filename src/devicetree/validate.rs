@@ -0,0 +1,449 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+
+use serde::Deserialize;
+
+use super::fdt::child_path;
+use super::node::DtbNode;
+use super::patch::PatchError;
+use super::value::{Cell, Status, ValueChunk};
+
+// properties holding phandle + arguments, with the provider property giving the argument count
+const PHANDLE_ARGS: [(&str, &str); 9] = [
+    ("clocks", "#clock-cells"),
+    ("resets", "#reset-cells"),
+    ("power-domains", "#power-domain-cells"),
+    ("dmas", "#dma-cells"),
+    ("iommus", "#iommu-cells"),
+    ("phys", "#phy-cells"),
+    ("mboxes", "#mbox-cells"),
+    ("interconnects", "#interconnect-cells"),
+    ("thermal-sensors", "#thermal-sensor-cells"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, "{}: {}: {}", severity, self.path, self.message)
+    }
+}
+
+// Extra rules for the nodes we patch, e.g.
+//
+//     [[schema]]
+//     path = "/cam_i2cmux/*/rbpcv*_imx*@*/mode*"
+//     required = ["tegra_sinterface"]
+//     allowed = { tegra_sinterface = ["serial_a", "serial_b"] }
+//
+// `path` is a glob as in DtbNode::select; `compatible` matches nodes by compatible string instead.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SchemaSet {
+    #[serde(default, rename = "schema")]
+    pub schemas: Vec<NodeSchema>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NodeSchema {
+    pub path: Option<String>,
+    pub compatible: Option<String>,
+    #[serde(default)]
+    pub required: Vec<String>,
+    // string properties and the values they may take
+    #[serde(default)]
+    pub allowed: HashMap<String, Vec<String>>,
+}
+
+impl SchemaSet {
+    pub fn load(path: &str) -> Result<SchemaSet, PatchError> {
+        toml::from_str(&fs::read_to_string(path)?).map_err(PatchError::Syntax)
+    }
+
+    pub fn check(&self, root: &DtbNode) -> Vec<Diagnostic> {
+        let mut diagnostics = vec![];
+
+        for schema in &self.schemas {
+            let nodes = match (&schema.path, &schema.compatible) {
+                (Some(path), _) => root.select(path),
+                (None, Some(compatible)) => root.find_compatible(compatible),
+                (None, None) => vec![],
+            };
+            for (path, node) in nodes {
+                // disabled nodes are not probed, their content does not matter
+                if node.status() == Some(Status::Disabled) {
+                    continue;
+                }
+                for key in &schema.required {
+                    if !node.properties.iter().any(|p| &p.key == key && !p.deleted) {
+                        diagnostics.push(error(&path, &format!("missing required property {}", key)));
+                    }
+                }
+                for (key, allowed) in &schema.allowed {
+                    let value = node.properties.iter().find(|p| &p.key == key && !p.deleted).and_then(|p| p.as_str());
+                    if let Some(value) = value {
+                        if !allowed.iter().any(|a| a == value) {
+                            diagnostics.push(error(&path, &format!("{} = \"{}\" is not one of {}", key, value, allowed.join(", "))));
+                        }
+                    }
+                }
+            }
+        }
+
+        diagnostics
+    }
+}
+
+// Structural checks in the spirit of dtc's: what would make the kernel misread the tree is
+// an error, what is only unusual (and common in vendor trees) is a warning.
+pub fn validate(root: &DtbNode) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+
+    let mut phandles: HashMap<u32, String> = HashMap::new();
+    let mut labels: HashMap<String, String> = HashMap::new();
+    for (path, node) in root.walk() {
+        if let Some(phandle) = node.phandle() {
+            if let Some(other) = phandles.insert(phandle, path.clone()) {
+                diagnostics.push(error(&path, &format!("phandle 0x{:x} is also used by {}", phandle, other)));
+            }
+        }
+        for label in &node.labels {
+            if let Some(other) = labels.insert(label.clone(), path.clone()) {
+                diagnostics.push(error(&path, &format!("label {} is also defined on {}", label, other)));
+            }
+        }
+    }
+
+    check_node(root, root, "/", 2, 1, &phandles, &mut diagnostics);
+    diagnostics
+}
+
+fn check_node(root: &DtbNode, node: &DtbNode, path: &str, address_cells: u32, size_cells: u32, phandles: &HashMap<u32, String>, diagnostics: &mut Vec<Diagnostic>) {
+    let mut names: Vec<&str> = vec![];
+    for child in node.child_nodes.iter().filter(|c| !c.deleted) {
+        if names.contains(&&child.node_name[..]) {
+            diagnostics.push(error(&child_path(path, &child.node_name), "duplicate node name"));
+        }
+        names.push(&child.node_name);
+    }
+
+    if path != "/" {
+        check_reg(node, path, address_cells, size_cells, diagnostics);
+    }
+    check_references(root, node, path, phandles, diagnostics);
+
+    if node.status() == Some(Status::Okay) && node.properties.iter().any(|p| p.key == "reg" && !p.deleted) && !node.properties.iter().any(|p| p.key == "compatible" && !p.deleted) {
+        diagnostics.push(warning(path, "enabled device has no compatible"));
+    }
+
+    let child_address_cells = cell_count(node, "#address-cells", path, diagnostics);
+    let child_size_cells = cell_count(node, "#size-cells", path, diagnostics);
+    let children_have_reg = node.child_nodes.iter().filter(|c| !c.deleted).any(|c| c.properties.iter().any(|p| p.key == "reg" && !p.deleted));
+    if children_have_reg && path != "/" && (child_address_cells.is_none() || child_size_cells.is_none()) {
+        diagnostics.push(warning(path, "children have reg but #address-cells/#size-cells are not set, defaults 2/1 apply"));
+    }
+
+    for child in node.child_nodes.iter().filter(|c| !c.deleted) {
+        check_node(
+            root,
+            child,
+            &child_path(path, &child.node_name),
+            child_address_cells.unwrap_or(2),
+            child_size_cells.unwrap_or(1),
+            phandles,
+            diagnostics,
+        );
+    }
+}
+
+fn cell_count(node: &DtbNode, key: &str, path: &str, diagnostics: &mut Vec<Diagnostic>) -> Option<u32> {
+    let property = node.properties.iter().find(|p| p.key == key && !p.deleted)?;
+    match property.as_u32() {
+        Some(count) => Some(count),
+        None => {
+            diagnostics.push(error(path, &format!("{} must be a single cell", key)));
+            None
+        }
+    }
+}
+
+fn check_reg(node: &DtbNode, path: &str, address_cells: u32, size_cells: u32, diagnostics: &mut Vec<Diagnostic>) {
+    let unit_address = node.node_name.split_once('@').map(|(_, address)| address);
+    let reg = node.properties.iter().find(|p| p.key == "reg" && !p.deleted);
+
+    let reg = match (unit_address, reg) {
+        (Some(_), None) => {
+            if !node.properties.iter().any(|p| p.key == "ranges" && !p.deleted) {
+                diagnostics.push(warning(path, "node has a unit address but no reg"));
+            }
+            return;
+        },
+        (None, Some(_)) => {
+            diagnostics.push(warning(path, "node has reg but no unit address"));
+            return;
+        },
+        (None, None) => return,
+        (Some(_), Some(reg)) => reg,
+    };
+
+    let cells = match reg.as_u32_cells() {
+        Some(cells) => cells,
+        None => {
+            diagnostics.push(error(path, "reg is not a list of 32-bit cells"));
+            return;
+        }
+    };
+
+    let entry = (address_cells + size_cells) as usize;
+    if entry == 0 || cells.is_empty() || !cells.len().is_multiple_of(entry) {
+        diagnostics.push(error(path, &format!("reg has {} cells, not a multiple of #address-cells {} + #size-cells {}", cells.len(), address_cells, size_cells)));
+        return;
+    }
+
+    if address_cells == 0 || address_cells > 2 {
+        return;
+    }
+    let address = cells[..address_cells as usize].iter().fold(0u64, |acc, c| (acc << 32) | *c as u64);
+    let first = unit_address.unwrap_or_default().split(',').next().unwrap_or_default();
+    match u64::from_str_radix(first, 16) {
+        Ok(unit) if unit == address => {},
+        Ok(_) => {
+            diagnostics.push(warning(path, &format!("unit address does not match the first reg address 0x{:x}", address)));
+        },
+        Err(_) => {
+            diagnostics.push(warning(path, "unit address is not a hexadecimal number"));
+        }
+    }
+}
+
+fn check_references(root: &DtbNode, node: &DtbNode, path: &str, phandles: &HashMap<u32, String>, diagnostics: &mut Vec<Diagnostic>) {
+    for property in node.properties.iter().filter(|p| !p.deleted) {
+        let value = match &property.value {
+            Some(value) => value,
+            None => continue,
+        };
+
+        // references written in source form have to resolve
+        for chunk in &value.0 {
+            let references: Vec<&String> = match chunk {
+                ValueChunk::Ref(reference) => vec![reference],
+                ValueChunk::Cells { cells, .. } => cells.iter().filter_map(|c| match c { Cell::Ref(r) => Some(r), _ => None }).collect(),
+                _ => vec![],
+            };
+            for reference in references {
+                if root.resolve(reference).is_err() {
+                    diagnostics.push(error(path, &format!("{} refers to {}, which does not exist", property.key, reference)));
+                }
+            }
+        }
+
+        // compiled phandles: only the properties whose layout is known
+        let key = &property.key[..];
+        let cells = match value.as_u32_cells() {
+            Some(cells) => cells,
+            None => continue,
+        };
+        if key == "interrupt-parent" || (key.starts_with("pinctrl-") && key[8..].bytes().all(|b| b.is_ascii_digit())) {
+            for phandle in cells {
+                check_phandle(phandle, &property.key, path, phandles, diagnostics);
+            }
+        } else if let Some((_, cells_key)) = PHANDLE_ARGS.iter().find(|(k, _)| *k == key) {
+            check_phandle_args(root, &cells, key, cells_key, path, phandles, diagnostics);
+        } else if (key == "gpios" || key.ends_with("-gpios")) && key != "nr-gpios" {
+            check_phandle_args(root, &cells, key, "#gpio-cells", path, phandles, diagnostics);
+        }
+    }
+}
+
+fn check_phandle_args(root: &DtbNode, cells: &[u32], key: &str, cells_key: &str, path: &str, phandles: &HashMap<u32, String>, diagnostics: &mut Vec<Diagnostic>) {
+    let mut i = 0;
+    while i < cells.len() {
+        let phandle = cells[i];
+        if !check_phandle(phandle, key, path, phandles, diagnostics) {
+            return;
+        }
+        // 0 is an empty entry
+        if phandle == 0 {
+            i += 1;
+            continue;
+        }
+        let provider = root.lookup(&phandles[&phandle]).ok();
+        let count = provider.and_then(|p| p.properties.iter().find(|p| p.key == cells_key && !p.deleted)).and_then(|p| p.as_u32());
+        match count {
+            Some(count) => i += 1 + count as usize,
+            None => {
+                diagnostics.push(warning(path, &format!("{} points at {}, which has no {}", key, phandles[&phandle], cells_key)));
+                return;
+            }
+        }
+    }
+    if i > cells.len() {
+        diagnostics.push(error(path, &format!("{} is shorter than its providers' {} require", key, cells_key)));
+    }
+}
+
+fn check_phandle(phandle: u32, key: &str, path: &str, phandles: &HashMap<u32, String>, diagnostics: &mut Vec<Diagnostic>) -> bool {
+    if phandle == 0 || phandles.contains_key(&phandle) {
+        return true;
+    }
+    diagnostics.push(error(path, &format!("{} refers to phandle 0x{:x}, which no node has", key, phandle)));
+    false
+}
+
+fn error(path: &str, message: &str) -> Diagnostic {
+    Diagnostic {
+        severity: Severity::Error,
+        path: path.to_string(),
+        message: message.to_string(),
+    }
+}
+
+fn warning(path: &str, message: &str) -> Diagnostic {
+    Diagnostic {
+        severity: Severity::Warning,
+        path: path.to_string(),
+        message: message.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `nodes` under a root with 1 address and 1 size cell
+    fn tree(nodes: &str) -> DtbNode {
+        DtbNode::parse(&format!("/dts-v1/;\n\n/ {{\n\t#address-cells = <1>;\n\t#size-cells = <1>;\n{}\n}};\n", nodes)).unwrap()
+    }
+
+    fn check(nodes: &str) -> Vec<String> {
+        validate(&tree(nodes)).iter().map(|d| d.to_string()).collect()
+    }
+
+    const GPIO: &str = "gpio: gpio@2200000 { compatible = \"nvidia,tegra234-gpio\"; reg = <0x2200000 0x10000>; #gpio-cells = <2>; phandle = <0x9c>; };";
+
+    #[test]
+    fn duplicate_phandles_labels_and_names() {
+        assert_eq!(check(GPIO), Vec::<String>::new());
+
+        assert_eq!(check(&format!("{} uart@3100000 {{ reg = <0x3100000 0x100>; phandle = <0x9c>; }};", GPIO)), [
+            "error: /uart@3100000: phandle 0x9c is also used by /gpio@2200000",
+        ]);
+        assert_eq!(check(&format!("{} gpio: uart@3100000 {{ reg = <0x3100000 0x100>; }};", GPIO)), [
+            "error: /uart@3100000: label gpio is also defined on /gpio@2200000",
+        ]);
+        // a source naming a node twice means one node, only a patched tree can hold both
+        let mut root = tree("bus { a { }; };");
+        root.lookup_mut("/bus").unwrap().child_nodes.push(Box::new(DtbNode::with_name("a")));
+        assert_eq!(validate(&root).iter().map(|d| d.to_string()).collect::<Vec<_>>(), ["error: /bus/a: duplicate node name"]);
+    }
+
+    #[test]
+    fn reg_and_unit_address() {
+        assert_eq!(check("uart@3100000 { reg = <0x3100000 0x100>; };"), Vec::<String>::new());
+        // a bus may only translate addresses
+        assert_eq!(check("bus@0 { ranges; };"), Vec::<String>::new());
+
+        assert_eq!(check("uart@3100000 { };"), ["warning: /uart@3100000: node has a unit address but no reg"]);
+        assert_eq!(check("uart { reg = <0x3100000 0x100>; };"), ["warning: /uart: node has reg but no unit address"]);
+        assert_eq!(check("uart@3100000 { reg = <0x3100000 0x100 0x0>; };"), [
+            "error: /uart@3100000: reg has 3 cells, not a multiple of #address-cells 1 + #size-cells 1",
+        ]);
+        assert_eq!(check("uart@3100000 { reg = \"0x3100000\"; };"), ["error: /uart@3100000: reg is not a list of 32-bit cells"]);
+        assert_eq!(check("uart@3110000 { reg = <0x3100000 0x100>; };"), [
+            "warning: /uart@3110000: unit address does not match the first reg address 0x3100000",
+        ]);
+        assert_eq!(check("uart@serial { reg = <0x3100000 0x100>; };"), ["warning: /uart@serial: unit address is not a hexadecimal number"]);
+    }
+
+    #[test]
+    fn cell_counts() {
+        assert_eq!(check("i2c@3160000 { reg = <0x3160000 0x100>; #address-cells = <1>; #size-cells = <0>; eeprom@50 { reg = <0x50>; }; };"), Vec::<String>::new());
+
+        assert_eq!(check("bus { #address-cells = <1 1>; };"), ["error: /bus: #address-cells must be a single cell"]);
+        assert_eq!(check("i2c@3160000 { reg = <0x3160000 0x100>; eeprom@50 { reg = <0x0 0x50 0x0>; }; };"), [
+            "warning: /i2c@3160000: children have reg but #address-cells/#size-cells are not set, defaults 2/1 apply",
+        ]);
+    }
+
+    #[test]
+    fn enabled_devices_need_a_compatible() {
+        assert_eq!(check("uart@3100000 { compatible = \"nvidia,tegra194-hsuart\"; reg = <0x3100000 0x100>; status = \"okay\"; };"), Vec::<String>::new());
+        assert_eq!(check("uart@3100000 { reg = <0x3100000 0x100>; status = \"disabled\"; };"), Vec::<String>::new());
+
+        assert_eq!(check("uart@3100000 { reg = <0x3100000 0x100>; status = \"okay\"; };"), ["warning: /uart@3100000: enabled device has no compatible"]);
+    }
+
+    #[test]
+    fn references() {
+        assert_eq!(check(&format!("{} cam {{ reset-gpios = <&gpio 0x3e 0>; }};", GPIO)), Vec::<String>::new());
+
+        assert_eq!(check("cam { reset-gpios = <&gpio 0x3e 0>; };"), ["error: /cam: reset-gpios refers to &gpio, which does not exist"]);
+        assert_eq!(check("cam { interrupt-parent = <0x9d>; };"), ["error: /cam: interrupt-parent refers to phandle 0x9d, which no node has"]);
+    }
+
+    #[test]
+    fn phandle_arguments() {
+        // two entries of 1 + #gpio-cells
+        assert_eq!(check(&format!("{} cam {{ gpios = <0x9c 0x3e 0x0 0x9c 0x3f 0x0>; }};", GPIO)), Vec::<String>::new());
+
+        assert_eq!(check(&format!("{} cam {{ gpios = <0x9c 0x3e>; }};", GPIO)), ["error: /cam: gpios is shorter than its providers' #gpio-cells require"]);
+        assert_eq!(check(&format!("{} cam {{ clocks = <0x9c 0x1>; }};", GPIO)), ["warning: /cam: clocks points at /gpio@2200000, which has no #clock-cells"]);
+    }
+
+    fn schemas() -> SchemaSet {
+        SchemaSet::load(concat!(env!("CARGO_MANIFEST_DIR"), "/patches/schema.toml")).unwrap()
+    }
+
+    fn check_schemas(nodes: &str) -> Vec<String> {
+        schemas().check(&tree(nodes)).iter().map(|d| d.to_string()).collect()
+    }
+
+    const SENSOR: &str = "compatible = \"sony,imx477\"; reg = <0x1a>; devnode = \"video0\";";
+
+    #[test]
+    fn schema_required_properties() {
+        assert_eq!(check_schemas(&format!("cam_i2cmux {{ i2c@0 {{ rbpcv3_imx477_a@1a {{ {} }}; }}; }};", SENSOR)), Vec::<String>::new());
+        // disabled sensors are not probed
+        assert_eq!(check_schemas("cam_i2cmux { i2c@0 { rbpcv3_imx477_a@1a { status = \"disabled\"; }; }; };"), Vec::<String>::new());
+
+        assert_eq!(check_schemas("cam_i2cmux { i2c@0 { rbpcv3_imx477_a@1a { compatible = \"sony,imx477\"; reg = <0x1a>; }; }; };"), [
+            "error: /cam_i2cmux/i2c@0/rbpcv3_imx477_a@1a: missing required property devnode",
+        ]);
+    }
+
+    #[test]
+    fn schema_allowed_values() {
+        let mode = |sinterface: &str| format!("cam_i2cmux {{ i2c@0 {{ rbpcv3_imx477_a@1a {{ {} mode0 {{ tegra_sinterface = \"{}\"; }}; }}; }}; }};", SENSOR, sinterface);
+        assert_eq!(check_schemas(&mode("serial_a")), Vec::<String>::new());
+
+        let diagnostics = check_schemas(&mode("serial_z"));
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].starts_with("error: /cam_i2cmux/i2c@0/rbpcv3_imx477_a@1a/mode0: tegra_sinterface = \"serial_z\" is not one of serial_a, "));
+    }
+
+    #[test]
+    fn schema_by_compatible() {
+        let schemas: SchemaSet = toml::from_str("[[schema]]\ncompatible = \"sony,imx477\"\nrequired = [\"devnode\"]\n").unwrap();
+        let check = |nodes: &str| schemas.check(&tree(nodes)).iter().map(|d| d.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(check(&format!("imx477@1a {{ {} }};", SENSOR)), Vec::<String>::new());
+        assert_eq!(check("imx477@1a { compatible = \"sony,imx477\"; };"), ["error: /imx477@1a: missing required property devnode"]);
+    }
+}
@@ -0,0 +1,726 @@
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValueError {
+    // byte offset into the value text
+    pub offset: usize,
+    pub message: String,
+}
+
+impl ValueError {
+    fn new(offset: usize, message: &str) -> ValueError {
+        ValueError {
+            offset,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ValueError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ValueError {}
+
+// One cell inside `< >`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Cell {
+    Num(u64),
+    // `&label` or `&{/path}`, encoded as the target's phandle
+    Ref(String),
+    // parenthesised expression or preprocessor macro, kept as written
+    Expr(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValueChunk {
+    // one quoted string; string lists decompiled from a DTB are kept as a single `"a\0b"`
+    Str(String),
+    Cells { bits: u32, cells: Vec<Cell> },
+    Bytes(Vec<u8>),
+    // bare `&label`, encoded as the target's full path
+    Ref(String),
+    // preprocessor macro standing for the whole value, e.g. `__FILE__`, kept as written
+    Expr(String),
+}
+
+// Value of a property, the comma separated parts of `key = <..>, "..", [..];`
+#[derive(Debug, Clone, PartialEq)]
+pub struct PropertyValue(pub Vec<ValueChunk>);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+    Okay,
+    Disabled,
+    Reserved,
+    Fail,
+}
+
+impl Status {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Status::Okay => "okay",
+            Status::Disabled => "disabled",
+            Status::Reserved => "reserved",
+            Status::Fail => "fail",
+        }
+    }
+}
+
+impl FromStr for Status {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Status, String> {
+        match s {
+            "okay" | "ok" => Ok(Status::Okay),
+            "disabled" => Ok(Status::Disabled),
+            "reserved" => Ok(Status::Reserved),
+            _ if s.starts_with("fail") => Ok(Status::Fail),
+            _ => Err(format!("invalid status \"{}\"", s)),
+        }
+    }
+}
+
+impl PropertyValue {
+    pub fn string(s: &str) -> PropertyValue {
+        PropertyValue(vec![ValueChunk::Str(s.to_string())])
+    }
+
+    pub fn strings(list: &[&str]) -> PropertyValue {
+        PropertyValue(list.iter().map(|s| ValueChunk::Str(s.to_string())).collect())
+    }
+
+    pub fn u32(value: u32) -> PropertyValue {
+        Self::cells(&[value])
+    }
+
+    pub fn cells(values: &[u32]) -> PropertyValue {
+        PropertyValue(vec![ValueChunk::Cells {
+            bits: 32,
+            cells: values.iter().map(|v| Cell::Num(*v as u64)).collect(),
+        }])
+    }
+
+    pub fn u64(value: u64) -> PropertyValue {
+        PropertyValue(vec![ValueChunk::Cells { bits: 64, cells: vec![Cell::Num(value)] }])
+    }
+
+    pub fn bytes(bytes: &[u8]) -> PropertyValue {
+        PropertyValue(vec![ValueChunk::Bytes(bytes.to_vec())])
+    }
+
+    pub fn phandle(label: &str) -> PropertyValue {
+        let reference = match label.starts_with('&') {
+            true => label.to_string(),
+            false => String::from("&") + label,
+        };
+        PropertyValue(vec![ValueChunk::Cells { bits: 32, cells: vec![Cell::Ref(reference)] }])
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match &self.0[..] {
+            [ValueChunk::Str(s)] if !s.contains('\0') => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_str_list(&self) -> Option<Vec<&str>> {
+        let mut ret = vec![];
+        for chunk in &self.0 {
+            match chunk {
+                ValueChunk::Str(s) => ret.extend(s.split('\0')),
+                _ => return None,
+            }
+        }
+        Some(ret)
+    }
+
+    pub fn as_u32_cells(&self) -> Option<Vec<u32>> {
+        let mut ret = vec![];
+        for chunk in &self.0 {
+            match chunk {
+                ValueChunk::Cells { bits: 32, cells } => {
+                    for cell in cells {
+                        match cell {
+                            Cell::Num(value) => ret.push(*value as u32),
+                            _ => return None,
+                        }
+                    }
+                },
+                _ => return None,
+            }
+        }
+        Some(ret)
+    }
+
+    pub fn as_u32(&self) -> Option<u32> {
+        match self.as_u32_cells()?[..] {
+            [value] => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match &self.0[..] {
+            [ValueChunk::Cells { bits: 64, cells }] => match cells[..] {
+                [Cell::Num(value)] => Some(value),
+                _ => None,
+            },
+            _ => match self.as_u32_cells()?[..] {
+                [hi, lo] => Some(((hi as u64) << 32) | lo as u64),
+                [value] => Some(value as u64),
+                _ => None,
+            },
+        }
+    }
+
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match &self.0[..] {
+            [ValueChunk::Bytes(bytes)] => Some(bytes),
+            _ => None,
+        }
+    }
+
+    // label or path of a single-phandle value like `<&gpio>`
+    pub fn as_reference(&self) -> Option<&str> {
+        match &self.0[..] {
+            [ValueChunk::Cells { cells, .. }] => match &cells[..] {
+                [Cell::Ref(reference)] => Some(reference),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<PropertyValue, ValueError> {
+        ValueParser { text, bytes: text.as_bytes(), pos: 0 }.parse()
+    }
+
+    // Binary encoding. `phandle` resolves a `&label`/`&{/path}` cell, `path` a bare reference.
+    pub fn to_bytes(&self, phandle: &mut dyn FnMut(&str) -> Option<u32>, path: &dyn Fn(&str) -> Option<String>) -> Result<Vec<u8>, ValueError> {
+        let mut ret = vec![];
+
+        for chunk in &self.0 {
+            match chunk {
+                ValueChunk::Str(s) => {
+                    ret.extend_from_slice(s.as_bytes());
+                    ret.push(0);
+                },
+                ValueChunk::Cells { bits, cells } => {
+                    for cell in cells {
+                        let value = match cell {
+                            Cell::Num(value) => *value,
+                            Cell::Ref(reference) => {
+                                phandle(reference).ok_or(ValueError::new(0, &format!("reference {} not found", reference)))? as u64
+                            },
+                            Cell::Expr(expr) => {
+                                return Err(ValueError::new(0, &format!("cannot evaluate {}, run the source through the C preprocessor first", expr)));
+                            }
+                        };
+                        match bits {
+                            8 => ret.push(value as u8),
+                            16 => ret.extend_from_slice(&(value as u16).to_be_bytes()),
+                            64 => ret.extend_from_slice(&value.to_be_bytes()),
+                            _ => ret.extend_from_slice(&(value as u32).to_be_bytes()),
+                        }
+                    }
+                },
+                ValueChunk::Bytes(bytes) => {
+                    ret.extend_from_slice(bytes);
+                },
+                ValueChunk::Ref(reference) => {
+                    let target = path(reference).ok_or(ValueError::new(0, &format!("reference {} not found", reference)))?;
+                    ret.extend_from_slice(target.as_bytes());
+                    ret.push(0);
+                },
+                ValueChunk::Expr(expr) => {
+                    return Err(ValueError::new(0, &format!("cannot evaluate {}, run the source through the C preprocessor first", expr)));
+                }
+            }
+        }
+
+        Ok(ret)
+    }
+
+    // Decode a binary value the same way `dtc -I dtb -O dts` does:
+    // string lists as one quoted string, 32-bit cells, otherwise a byte string.
+    pub fn from_bytes(value: &[u8]) -> Option<PropertyValue> {
+        if value.is_empty() {
+            return None;
+        }
+
+        if is_string_list(value) {
+            let s = String::from_utf8_lossy(&value[..value.len() - 1]).to_string();
+            return Some(PropertyValue(vec![ValueChunk::Str(s)]));
+        }
+
+        if value.len().is_multiple_of(4) {
+            let cells = value
+                .chunks(4)
+                .map(|c| Cell::Num(u32::from_be_bytes([c[0], c[1], c[2], c[3]]) as u64))
+                .collect();
+            return Some(PropertyValue(vec![ValueChunk::Cells { bits: 32, cells }]));
+        }
+
+        Some(Self::bytes(value))
+    }
+}
+
+fn is_string_list(value: &[u8]) -> bool {
+    if value[value.len() - 1] != 0 || value[0] == 0 {
+        return false;
+    }
+
+    let nul_count = value.iter().filter(|b| **b == 0).count();
+    if nul_count > value.len() - nul_count {
+        return false;
+    }
+
+    // empty strings in the middle of the list mean this is binary data
+    if value.windows(2).any(|w| w[0] == 0 && w[1] == 0) {
+        return false;
+    }
+
+    std::str::from_utf8(value).is_ok() && value.iter().all(|b| *b == 0 || *b >= 0x20 || *b == b'\t' || *b == b'\n' || *b == b'\r') && !value.contains(&0x7f)
+}
+
+impl fmt::Display for PropertyValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, chunk) in self.0.iter().enumerate() {
+            if index > 0 {
+                write!(f, ", ")?;
+            }
+            match chunk {
+                ValueChunk::Str(s) => {
+                    write!(f, "\"")?;
+                    for c in s.chars() {
+                        match c {
+                            '\0' => write!(f, "\\0")?,
+                            '"' => write!(f, "\\\"")?,
+                            '\\' => write!(f, "\\\\")?,
+                            '\t' => write!(f, "\\t")?,
+                            '\n' => write!(f, "\\n")?,
+                            '\r' => write!(f, "\\r")?,
+                            c if (c as u32) < 0x20 || c as u32 == 0x7f => write!(f, "\\x{:02x}", c as u32)?,
+                            c => write!(f, "{}", c)?,
+                        }
+                    }
+                    write!(f, "\"")?;
+                },
+                ValueChunk::Cells { bits, cells } => {
+                    if *bits != 32 {
+                        write!(f, "/bits/ {} ", bits)?;
+                    }
+                    let cells: Vec<String> = cells
+                        .iter()
+                        .map(|cell| match cell {
+                            Cell::Num(value) => format!("0x{:02x}", value),
+                            Cell::Ref(reference) => reference.clone(),
+                            Cell::Expr(expr) => expr.clone(),
+                        })
+                        .collect();
+                    write!(f, "<{}>", cells.join(" "))?;
+                },
+                ValueChunk::Bytes(bytes) => {
+                    let bytes: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+                    write!(f, "[{}]", bytes.join(" "))?;
+                },
+                ValueChunk::Ref(reference) => {
+                    write!(f, "{}", reference)?;
+                },
+                ValueChunk::Expr(expr) => {
+                    write!(f, "{}", expr)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+struct ValueParser<'a> {
+    text: &'a str,
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ValueParser<'a> {
+    fn error(&self, message: &str) -> ValueError {
+        ValueError::new(self.pos, message)
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.pos).copied()
+    }
+
+    // whitespace, comments and `label:` markers carry no value
+    fn skip_trivia(&mut self) -> Result<(), ValueError> {
+        loop {
+            match self.peek() {
+                Some(c) if c.is_ascii_whitespace() => self.pos += 1,
+                Some(b'/') if self.text[self.pos..].starts_with("//") => {
+                    while self.peek().is_some_and(|c| c != b'\n') {
+                        self.pos += 1;
+                    }
+                },
+                Some(b'/') if self.text[self.pos..].starts_with("/*") => {
+                    match self.text[self.pos + 2..].find("*/") {
+                        Some(end) => self.pos += 2 + end + 2,
+                        None => return Err(self.error("unterminated comment")),
+                    }
+                },
+                Some(c) if c.is_ascii_alphabetic() || c == b'_' => {
+                    let len = self.identifier_len();
+                    if self.bytes.get(self.pos + len) == Some(&b':') {
+                        self.pos += len + 1;
+                    } else {
+                        return Ok(());
+                    }
+                },
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn identifier_len(&self) -> usize {
+        self.bytes[self.pos..].iter().take_while(|c| c.is_ascii_alphanumeric() || **c == b'_').count()
+    }
+
+    fn parse(mut self) -> Result<PropertyValue, ValueError> {
+        let mut chunks = vec![];
+
+        loop {
+            self.skip_trivia()?;
+            let chunk = match self.peek() {
+                Some(b'"') => ValueChunk::Str(self.parse_string()?),
+                Some(b'<') => ValueChunk::Cells { bits: 32, cells: self.parse_cells(32)? },
+                Some(b'/') if self.text[self.pos..].starts_with("/bits/") => {
+                    self.pos += "/bits/".len();
+                    self.skip_trivia()?;
+                    let digits = self.bytes[self.pos..].iter().take_while(|c| c.is_ascii_digit()).count();
+                    let bits = self.text[self.pos..self.pos + digits].parse::<u32>().unwrap_or(0);
+                    if ![8, 16, 32, 64].contains(&bits) {
+                        return Err(self.error("/bits/ must be 8, 16, 32 or 64"));
+                    }
+                    self.pos += digits;
+                    self.skip_trivia()?;
+                    if self.peek() != Some(b'<') {
+                        return Err(self.error("expected '<' after /bits/"));
+                    }
+                    ValueChunk::Cells { bits, cells: self.parse_cells(bits)? }
+                },
+                Some(b'[') => ValueChunk::Bytes(self.parse_bytes()?),
+                Some(b'&') => ValueChunk::Ref(self.parse_reference()?),
+                Some(c) if c.is_ascii_alphabetic() || c == b'_' => {
+                    let start = self.pos;
+                    self.pos += self.identifier_len();
+                    if self.peek() == Some(b'(') {
+                        self.skip_balanced()?;
+                    }
+                    ValueChunk::Expr(self.text[start..self.pos].to_string())
+                },
+                Some(_) => return Err(self.error("expected '\"', '<', '[', '&' or a macro")),
+                None => return Err(self.error("missing value")),
+            };
+            chunks.push(chunk);
+
+            self.skip_trivia()?;
+            match self.peek() {
+                None => break,
+                Some(b',') => self.pos += 1,
+                Some(_) => return Err(self.error("expected ',' between values")),
+            }
+        }
+
+        Ok(PropertyValue(chunks))
+    }
+
+    fn parse_string(&mut self) -> Result<String, ValueError> {
+        let start = self.pos;
+        self.pos += 1;
+        let mut ret: Vec<u8> = vec![];
+
+        loop {
+            match self.peek() {
+                None | Some(b'\n') => {
+                    self.pos = start;
+                    return Err(self.error("unterminated string"));
+                },
+                Some(b'"') => {
+                    self.pos += 1;
+                    break;
+                },
+                Some(b'\\') => {
+                    self.pos += 1;
+                    ret.push(self.parse_escape()?);
+                },
+                Some(c) => {
+                    ret.push(c);
+                    self.pos += 1;
+                }
+            }
+        }
+
+        String::from_utf8(ret).map_err(|_| ValueError::new(start, "string is not valid UTF-8"))
+    }
+
+    fn parse_escape(&mut self) -> Result<u8, ValueError> {
+        let c = self.peek().ok_or(self.error("invalid escape sequence"))?;
+        self.pos += 1;
+
+        match c {
+            b'0'..=b'7' => {
+                let start = self.pos - 1;
+                while self.pos < start + 3 && self.peek().is_some_and(|c| (b'0'..=b'7').contains(&c)) {
+                    self.pos += 1;
+                }
+                u8::from_str_radix(&self.text[start..self.pos], 8).map_err(|_| self.error("invalid octal escape"))
+            },
+            b'x' => {
+                let start = self.pos;
+                while self.pos < start + 2 && self.peek().is_some_and(|c| c.is_ascii_hexdigit()) {
+                    self.pos += 1;
+                }
+                u8::from_str_radix(&self.text[start..self.pos], 16).map_err(|_| self.error("invalid \\x escape"))
+            },
+            b'a' => Ok(0x07),
+            b'b' => Ok(0x08),
+            b't' => Ok(b'\t'),
+            b'n' => Ok(b'\n'),
+            b'v' => Ok(0x0b),
+            b'f' => Ok(0x0c),
+            b'r' => Ok(b'\r'),
+            c => Ok(c),
+        }
+    }
+
+    fn parse_cells(&mut self, bits: u32) -> Result<Vec<Cell>, ValueError> {
+        self.pos += 1;
+        let mut cells = vec![];
+        let max = match bits {
+            64 => u64::MAX,
+            _ => (1u64 << bits) - 1,
+        };
+
+        loop {
+            self.skip_trivia()?;
+            let start = self.pos;
+            let cell = match self.peek() {
+                None => return Err(self.error("unterminated cell list, expected '>'")),
+                Some(b'>') => {
+                    self.pos += 1;
+                    return Ok(cells);
+                },
+                Some(b'&') => Cell::Ref(self.parse_reference()?),
+                Some(b'(') => {
+                    self.skip_balanced()?;
+                    Cell::Expr(self.text[start..self.pos].to_string())
+                },
+                Some(b'\'') => {
+                    self.pos += 1;
+                    let value = match self.peek() {
+                        Some(b'\\') => {
+                            self.pos += 1;
+                            self.parse_escape()?
+                        },
+                        Some(c) => {
+                            self.pos += 1;
+                            c
+                        },
+                        None => return Err(self.error("unterminated character literal")),
+                    };
+                    if self.peek() != Some(b'\'') {
+                        return Err(self.error("unterminated character literal"));
+                    }
+                    self.pos += 1;
+                    Cell::Num(value as u64)
+                },
+                Some(c) if c.is_ascii_digit() => {
+                    let len = self.bytes[self.pos..].iter().take_while(|c| c.is_ascii_alphanumeric()).count();
+                    let literal = &self.text[self.pos..self.pos + len];
+                    let value = parse_integer(literal).ok_or(self.error(&format!("invalid number \"{}\"", literal)))?;
+                    if value > max {
+                        return Err(self.error(&format!("{} does not fit in {} bits", literal, bits)));
+                    }
+                    self.pos += len;
+                    Cell::Num(value)
+                },
+                Some(c) if c.is_ascii_alphabetic() || c == b'_' => {
+                    // preprocessor macro, possibly with arguments
+                    self.pos += self.identifier_len();
+                    if self.peek() == Some(b'(') {
+                        self.skip_balanced()?;
+                    }
+                    Cell::Expr(self.text[start..self.pos].to_string())
+                },
+                Some(_) => return Err(self.error("expected number, reference or expression")),
+            };
+            cells.push(cell);
+        }
+    }
+
+    fn skip_balanced(&mut self) -> Result<(), ValueError> {
+        let start = self.pos;
+        let mut depth = 0;
+        while let Some(c) = self.peek() {
+            self.pos += 1;
+            match c {
+                b'(' => depth += 1,
+                b')' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(());
+                    }
+                },
+                _ => {}
+            }
+        }
+        self.pos = start;
+        Err(self.error("unbalanced '('"))
+    }
+
+    fn parse_bytes(&mut self) -> Result<Vec<u8>, ValueError> {
+        self.pos += 1;
+        let mut ret = vec![];
+
+        loop {
+            self.skip_trivia()?;
+            match self.peek() {
+                None => return Err(self.error("unterminated byte string, expected ']'")),
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(ret);
+                },
+                Some(_) => {
+                    let pair = self.text.get(self.pos..self.pos + 2).ok_or(self.error("expected two hex digits"))?;
+                    let byte = u8::from_str_radix(pair, 16).map_err(|_| self.error(&format!("invalid byte \"{}\"", pair)))?;
+                    ret.push(byte);
+                    self.pos += 2;
+                }
+            }
+        }
+    }
+
+    fn parse_reference(&mut self) -> Result<String, ValueError> {
+        let start = self.pos;
+        self.pos += 1;
+        if self.peek() == Some(b'{') {
+            match self.text[self.pos..].find('}') {
+                Some(end) => self.pos += end + 1,
+                None => return Err(self.error("unterminated path reference")),
+            }
+        } else {
+            let len = self.identifier_len();
+            if len == 0 {
+                return Err(self.error("expected label after '&'"));
+            }
+            self.pos += len;
+        }
+        Ok(self.text[start..self.pos].to_string())
+    }
+}
+
+pub fn parse_integer(literal: &str) -> Option<u64> {
+    let digits = literal.trim_end_matches(['U', 'L', 'u', 'l']);
+    if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        u64::from_str_radix(hex, 16).ok()
+    } else if digits.len() > 1 && digits.starts_with('0') {
+        u64::from_str_radix(&digits[1..], 8).ok()
+    } else {
+        digits.parse::<u64>().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn no_phandle(_: &str) -> Option<u32> {
+        None
+    }
+
+    fn no_path(_: &str) -> Option<String> {
+        None
+    }
+
+    // parse, print, parse again and encode: the printed form means the same value
+    fn round_trip(text: &str) -> (PropertyValue, Vec<u8>) {
+        let value = PropertyValue::parse(text).unwrap();
+        assert_eq!(PropertyValue::parse(&value.to_string()).unwrap(), value, "{} printed as {}", text, value);
+        let bytes = value.to_bytes(&mut no_phandle, &no_path).unwrap();
+        (value, bytes)
+    }
+
+    #[test]
+    fn cells() {
+        let (value, bytes) = round_trip("<0x1 017 400000>");
+        assert_eq!(value, PropertyValue::cells(&[1, 0o17, 400000]));
+        assert_eq!(value.to_string(), "<0x01 0x0f 0x61a80>");
+        assert_eq!(bytes, [0, 0, 0, 1, 0, 0, 0, 0x0f, 0, 0x06, 0x1a, 0x80]);
+        assert_eq!(PropertyValue::from_bytes(&bytes), Some(value));
+
+        assert!(PropertyValue::parse("<0x100000000>").is_err());
+        assert!(PropertyValue::parse("<1 2").is_err());
+    }
+
+    #[test]
+    fn cells_of_64_and_8_bits() {
+        let (value, bytes) = round_trip("/bits/ 64 <0x123456789abcdef0>");
+        assert_eq!(value.as_u64(), Some(0x1234_5678_9abc_def0));
+        assert_eq!(bytes, 0x1234_5678_9abc_def0u64.to_be_bytes());
+        // a DTB does not say the cells were 64-bit, they read back as two 32-bit ones
+        assert_eq!(PropertyValue::from_bytes(&bytes).unwrap().as_u64(), Some(0x1234_5678_9abc_def0));
+
+        let (value, bytes) = round_trip("/bits/ 8 <0x12 0xff>");
+        assert_eq!(value.to_string(), "/bits/ 8 <0x12 0xff>");
+        assert_eq!(bytes, [0x12, 0xff]);
+        assert!(PropertyValue::parse("/bits/ 8 <0x100>").is_err());
+        assert!(PropertyValue::parse("/bits/ 12 <1>").is_err());
+    }
+
+    #[test]
+    fn bytes() {
+        let (value, bytes) = round_trip("[00 1a ff]");
+        assert_eq!(value.as_bytes(), Some(&[0x00, 0x1a, 0xff][..]));
+        assert_eq!(value.to_string(), "[00 1a ff]");
+        assert_eq!(PropertyValue::from_bytes(&bytes), Some(value));
+
+        assert!(PropertyValue::parse("[0g]").is_err());
+    }
+
+    #[test]
+    fn mixed_strings_and_cells() {
+        let (value, bytes) = round_trip("\"nvidia,tegra234\", <0x2>, [ab]");
+        assert_eq!(value.0.len(), 3);
+        assert_eq!(bytes, b"nvidia,tegra234\0\0\0\0\x02\xab");
+
+        let (value, bytes) = round_trip("\"nvidia,p3768-0000\", \"nvidia,tegra234\"");
+        assert_eq!(value.as_str_list(), Some(vec!["nvidia,p3768-0000", "nvidia,tegra234"]));
+        // a string list comes back from a DTB as one string
+        let decoded = PropertyValue::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.to_string(), "\"nvidia,p3768-0000\\0nvidia,tegra234\"");
+        assert_eq!(decoded.as_str_list(), value.as_str_list());
+    }
+
+    #[test]
+    fn string_escapes() {
+        let (value, bytes) = round_trip(r#""tab\there \"quoted\" back\\slash \x01\101\n""#);
+        assert_eq!(value.as_str(), Some("tab\there \"quoted\" back\\slash \x01A\n"));
+        assert_eq!(value.to_string(), r#""tab\there \"quoted\" back\\slash \x01A\n""#);
+        assert_eq!(bytes, b"tab\there \"quoted\" back\\slash \x01A\n\0");
+
+        assert!(PropertyValue::parse("\"unterminated").is_err());
+    }
+
+    #[test]
+    fn references_need_their_target() {
+        let value = PropertyValue::parse("<&gpio 0x3c 0>, &uartb").unwrap();
+        assert_eq!(value.to_string(), "<&gpio 0x3c 0x00>, &uartb");
+        assert!(value.to_bytes(&mut no_phandle, &no_path).is_err());
+
+        let bytes = value.to_bytes(&mut |label| (label == "&gpio").then_some(0x9c), &|label| (label == "&uartb").then(|| String::from("/serial@3110000"))).unwrap();
+        assert_eq!(bytes, b"\0\0\0\x9c\0\0\0\x3c\0\0\0\0/serial@3110000\0");
+    }
+}
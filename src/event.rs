@@ -0,0 +1,58 @@
+use tokio::sync::mpsc::{self, Receiver, Sender};
+
+use crate::jetson::Signal;
+
+// Events run_app has not handled yet; past this the jobs wait for the UI to catch up and
+// what the UI posts itself is dropped
+pub const EVENT_CAPACITY: usize = 1024;
+
+pub fn event_bus() -> (Publisher, Receiver<Signal>) {
+    let (tx, rx) = mpsc::channel(EVENT_CAPACITY);
+    (Publisher { tx }, rx)
+}
+
+// The sending end of the one queue every job and the UI report to run_app through.
+// Once run_app has returned nobody listens any more, so a closed bus is not an error.
+#[derive(Clone)]
+pub struct Publisher {
+    tx: Sender<Signal>,
+}
+
+impl Publisher {
+    // from jobs, waiting while the queue is full
+    pub async fn send(&self, signal: Signal) {
+        let _ = self.tx.send(signal).await;
+    }
+
+    // from blocking work running under spawn_blocking
+    pub fn blocking_send(&self, signal: Signal) {
+        let _ = self.tx.blocking_send(signal);
+    }
+
+    // From the UI loop. It is the one emptying the queue, so it must not wait on it: with
+    // EVENT_CAPACITY events still unhandled the event is dropped.
+    pub fn post(&self, signal: Signal) {
+        let _ = self.tx.try_send(signal);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn post_drops_events_past_capacity() {
+        let (tx, mut rx) = event_bus();
+        for n in 0..=EVENT_CAPACITY {
+            tx.post(Signal::Message(n.to_string()));
+        }
+        drop(tx);
+
+        let mut received = 0;
+        while let Some(signal) = rx.recv().await {
+            assert!(matches!(signal, Signal::Message(n) if n == received.to_string()));
+            received += 1;
+        }
+        assert_eq!(received, EVENT_CAPACITY);
+    }
+}
@@ -0,0 +1,192 @@
+use std::{
+    collections::HashMap,
+    io, mem,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+};
+
+use tokio::io::{unix::AsyncFd, Interest};
+
+use crate::event::Publisher;
+use crate::jetson::Signal;
+use crate::usb::{Sysfs, NVIDIA};
+
+// the multicast group the kernel sends uevents on, udevd re-sends them on group 2 after its rules ran
+const KERNEL_GROUP: u32 = 1;
+// a uevent is at most 2048 bytes (UEVENT_BUFFER_SIZE)
+const UEVENT_MAX: usize = 8192;
+
+// An NVIDIA USB device coming or going, by port path
+#[derive(Debug, Clone, PartialEq)]
+pub enum Uevent {
+    Added(String),
+    Removed(String),
+}
+
+// Listen to the kernel uevents and report NVIDIA USB devices coming and going as
+// DeviceAdded/DeviceRemoved, reading the added ones from `sysfs`. The socket is opened before
+// returning, so that nothing plugged in after the call is missed.
+pub fn watch(tx: Publisher, sysfs: Sysfs) -> io::Result<()> {
+    // the OwnedFd keeps the socket open for as long as the AsyncFd holds it
+    let socket = unsafe { AsyncFd::register_with_interest(open()?, Interest::READABLE)? };
+    tokio::spawn(async move {
+        let mut buf = vec![0u8; UEVENT_MAX];
+        loop {
+            let mut guard = match socket.readable().await {
+                Ok(guard) => guard,
+                Err(_) => return,
+            };
+            let received = match guard.try_io(|socket| receive(socket.get_ref(), &mut buf)) {
+                Ok(received) => received,
+                // spurious wakeup
+                Err(_) => continue,
+            };
+            match received {
+                Ok(Some(len)) => {
+                    let signal = match parse(&buf[..len]) {
+                        // the kernel announces a device once its attributes are in place
+                        Some(Uevent::Added(port)) => match sysfs.device(&port) {
+                            Ok(device) => Signal::DeviceAdded(device),
+                            Err(e) => Signal::Message(format!("Cannot read USB device {}\n", e)),
+                        },
+                        Some(Uevent::Removed(port)) => Signal::DeviceRemoved { port },
+                        None => continue,
+                    };
+                    tx.send(signal).await;
+                },
+                Ok(None) => {},
+                // the socket overflowed, some events are lost
+                Err(e) if e.raw_os_error() == Some(libc::ENOBUFS) => {
+                    tx.send(Signal::Message(String::from("Missed USB hotplug events, press F5 to refresh the device list\n"))).await;
+                },
+                Err(e) => {
+                    tx.send(Signal::Message(format!("USB hotplug monitoring stopped: {}\n", e))).await;
+                    return;
+                },
+            }
+        }
+    });
+    Ok(())
+}
+
+fn open() -> io::Result<OwnedFd> {
+    unsafe {
+        let fd = libc::socket(
+            libc::AF_NETLINK,
+            libc::SOCK_DGRAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+            libc::NETLINK_KOBJECT_UEVENT,
+        );
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let socket = OwnedFd::from_raw_fd(fd);
+
+        let mut address: libc::sockaddr_nl = mem::zeroed();
+        address.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        address.nl_groups = KERNEL_GROUP;
+        let bound = libc::bind(
+            fd,
+            &address as *const libc::sockaddr_nl as *const libc::sockaddr,
+            mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+        );
+        if bound < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(socket)
+    }
+}
+
+// One message, None when it did not come from the kernel
+fn receive(socket: &OwnedFd, buf: &mut [u8]) -> io::Result<Option<usize>> {
+    unsafe {
+        let mut sender: libc::sockaddr_nl = mem::zeroed();
+        let mut sender_len = mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t;
+        let len = libc::recvfrom(
+            socket.as_raw_fd(),
+            buf.as_mut_ptr() as *mut libc::c_void,
+            buf.len(),
+            0,
+            &mut sender as *mut libc::sockaddr_nl as *mut libc::sockaddr,
+            &mut sender_len,
+        );
+        if len < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // any process may send on the group, only trust the kernel
+        match sender.nl_pid {
+            0 => Ok(Some(len as usize)),
+            _ => Ok(None),
+        }
+    }
+}
+
+// "add@/devices/.../usb1/1-3\0ACTION=add\0DEVPATH=/devices/.../usb1/1-3\0SUBSYSTEM=usb\0...",
+// for whole NVIDIA USB devices only
+pub fn parse(message: &[u8]) -> Option<Uevent> {
+    let mut fields = message.split(|byte| *byte == 0).map(|field| String::from_utf8_lossy(field).into_owned());
+    // the "<action>@<devpath>" header
+    fields.next()?;
+    let env: HashMap<String, String> = fields
+                                        .filter_map(|field| field.split_once('=').map(|(key, value)| (key.to_string(), value.to_string())))
+                                        .collect();
+
+    // the interfaces of a device come with their own events
+    if env.get("SUBSYSTEM")? != "usb" || env.get("DEVTYPE")? != "usb_device" {
+        return None;
+    }
+
+    // PRODUCT=955/7323/101 : idVendor/idProduct/bcdDevice in hex, without the leading zeros
+    let vendor = env.get("PRODUCT")?.split('/').next()?;
+    if format!("{:04x}", u16::from_str_radix(vendor, 16).ok()?) != NVIDIA {
+        return None;
+    }
+
+    let port = env.get("DEVPATH")?.rsplit('/').next()?.to_string();
+    match env.get("ACTION")?.as_str() {
+        "add" => {
+            Some(Uevent::Added(port))
+        },
+        "remove" => {
+            Some(Uevent::Removed(port))
+        },
+        _ => {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // as `udevadm monitor --kernel --property` shows them for an Orin NX put in recovery mode
+    fn uevent(action: &str, devtype: &str, product: &str) -> Vec<u8> {
+        let devpath = "/devices/pci0000:00/0000:00:14.0/usb1/1-3";
+        [
+            format!("{}@{}", action, devpath),
+            format!("ACTION={}", action),
+            format!("DEVPATH={}", devpath),
+            String::from("SUBSYSTEM=usb"),
+            String::from("DEVNAME=/dev/bus/usb/001/012"),
+            format!("DEVTYPE={}", devtype),
+            format!("PRODUCT={}", product),
+            String::from("TYPE=0/0/0"),
+            String::from("BUSNUM=001"),
+            String::from("DEVNUM=012"),
+            String::from("SEQNUM=4791"),
+            String::from("MAJOR=189"),
+            String::from("MINOR=11"),
+        ].join("\0").into_bytes()
+    }
+
+    #[test]
+    fn parses_nvidia_usb_devices() {
+        assert_eq!(parse(&uevent("add", "usb_device", "955/7323/101")), Some(Uevent::Added(String::from("1-3"))));
+        assert_eq!(parse(&uevent("remove", "usb_device", "955/7323/101")), Some(Uevent::Removed(String::from("1-3"))));
+
+        // its interfaces, other vendors and other actions are not boards coming or going
+        assert_eq!(parse(&uevent("add", "usb_interface", "955/7323/101")), None);
+        assert_eq!(parse(&uevent("add", "usb_device", "46d/c52b/1211")), None);
+        assert_eq!(parse(&uevent("bind", "usb_device", "955/7323/101")), None);
+        assert_eq!(parse(b"libudev\0\xfe\xed\xca\xfe"), None);
+    }
+}
@@ -1,35 +1,65 @@
-use std::{sync::mpsc::Sender, time::SystemTime};
+use std::{fmt, sync::mpsc::Sender, time::SystemTime};
 
 use crate::logger::Logger;
-
-pub enum JetsonModuleType {
-    OrinNX16GB,
-    OrinNX8GB,
-    OrinNano8GB,
-    OrinNano4GB,
-    XavierNX,
-    Unknown,
-    None,
-}
+use crate::module_catalog::Module;
+use crate::test::{job::Job, setup_error::SetupStep, setup_progress::SetupProgress};
+use crate::usb::{self, Hub, UsbDevice};
 
 #[derive(PartialEq)]
 pub enum Signal {
     Message(String),
     FlashFail,
     FlashSuccess,
+    FlashCancelled,
     FlashPass,
     Flashing,
     EnvironmentInstalling(SystemTime),
     EnvironmentPass,
     EnvironmentInstalled,
+    EnvironmentFailed { step: SetupStep, reason: String },
+    EnvironmentCancelled { step: SetupStep },
+    SetupProgress(SetupProgress),
+    // a single device flash (by USB instance) has ended
+    DeviceFlashStatus { instance: String, status: FlashStatus },
+    // the chip ECID of the device on the USB instance, read from its flash log
+    DeviceEcid { instance: String, ecid: String },
+    // an NVIDIA USB device was plugged in, or unplugged from the port
+    DeviceAdded(UsbDevice),
+    DeviceRemoved { port: String },
+    // SIGINT, SIGTERM or SIGHUP reached the program
+    Terminate,
 }
 
-#[derive(PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum FlashStatus {
     Wait,
     Flashing,
     Finished,
     Failed,
+    Cancelled,
+}
+
+// Where a board is, by what answers on its USB port
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stage {
+    Recovery,
+    // the initrd of l4t_initrd_flash.sh, writing the storage
+    Initrd,
+    Booted,
+    // nothing on the port any more
+    Disconnected,
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Stage::Recovery => "recovery mode",
+            Stage::Initrd => "flashing initrd",
+            Stage::Booted => "L4T booted",
+            Stage::Disconnected => "disconnected",
+        };
+        write!(f, "{}", name)
+    }
 }
 
 pub struct Jetson {
@@ -38,51 +68,49 @@ pub struct Jetson {
     pub vendor_number: String,
     pub module_number: String,
     pub module_name: String,
-    pub module_type: JetsonModuleType,
+    // None for a product id the catalog does not know
+    pub module: Option<Module>,
+    // the USB port path, which identifies the board through all its stages
     pub instance_number: String,
+    pub stage: Stage,
+    // USB serial number, the booted L4T gadget's is the board's serial
+    pub serial: Option<String>,
+    // Tegra chip ECID (BR_CID), known once the board was flashed
+    pub ecid: Option<String>,
+    // USB link speed in Mbit/s, empty when not known
+    pub speed: String,
+    pub hubs: Vec<Hub>,
     pub ip_v4: Option<String>,
     pub logger: Option<Logger>,
     pub status: FlashStatus,
+    // set while this device is flashed on its own
+    pub flash_job: Option<Job>,
 }
 
-pub const XAVIER_NX_DTB: &'static str = "tegra194-p3668-0001-p3509-0000.dtb";
-pub const XAVIER_NX_DTS: &'static str = "tegra194-p3668-0001-p3509-0000.dts";
-pub const ORIN_NX_16GB_DTB: &'static str = "tegra234-p3767-0000-p3768-0000-a0.dtb";
-pub const ORIN_NX_8GB_DTB: &'static str = "tegra234-p3767-0001-p3768-0000-a0.dtb";
-pub const ORIN_NX_16GB_DTS: &'static str = "tegra234-p3767-0000-p3768-0000-a0.dts";
-pub const ORIN_NX_8GB_DTS: &'static str = "tegra234-p3767-0001-p3768-0000-a0.dts";
-
 impl Jetson {
-    pub fn new(bus: &str, dev: &str, vendor_number: &str, module_number: &str, instance_number: &str) -> Jetson {
+    pub fn new(bus: &str, dev: &str, vendor_number: &str, module_number: &str, instance_number: &str, module: Option<Module>) -> Jetson {
         let mut ret = Jetson {
             bus: bus.to_string(),
             dev: dev.to_string(),
             vendor_number: vendor_number.to_string(),
             module_number: module_number.to_string(),
-            module_name: String::new(),
-            module_type: JetsonModuleType::None,
+            module_name: match &module {
+                Some(module) => module.name.clone(),
+                None => String::from("Unknown USB Device"),
+            },
+            module,
             instance_number: instance_number.to_string(),
+            stage: Stage::Recovery,
+            serial: None,
+            ecid: None,
+            speed: String::new(),
+            hubs: vec![],
             ip_v4: None,
             logger: None,
             status: FlashStatus::Wait,
+            flash_job: None,
         };
 
-        if ret.module_number == "7323" {
-            ret.set_module_type(JetsonModuleType::OrinNX16GB);
-        } else if ret.module_number == "7423" {
-            ret.set_module_type(JetsonModuleType::OrinNX8GB);
-        } else if ret.module_number == "7e19" {
-            ret.set_module_type(JetsonModuleType::XavierNX);
-        } else if ret.module_number == "7523" {
-            ret.set_module_type(JetsonModuleType::OrinNano8GB);
-        } else if ret.module_number == "7623" {
-            ret.set_module_type(JetsonModuleType::OrinNano4GB);
-        } else if ["7023", "7223", "7019"].contains(&&ret.module_number[..]) {
-            ret.set_module_type(JetsonModuleType::Unknown);
-        } else {
-            ret.set_module_type(JetsonModuleType::None);
-        }
-        
         let mut logger = Logger::new(&ret.module_name);
         logger.init();
         ret.logger = Some(logger);
@@ -90,32 +118,64 @@ impl Jetson {
         ret
     }
 
-    pub fn set_module_type(&mut self, module_type: JetsonModuleType) {
-        self.module_type = module_type;
-        self.module_name = match self.module_type {
-            JetsonModuleType::OrinNX16GB => {
-                String::from("Jetson Orin NX 16GB")
-            },
-            JetsonModuleType::OrinNX8GB => {
-                String::from("Jetson Orin NX 8GB")
-            },
-            JetsonModuleType::OrinNano8GB => {
-                String::from("Jetson Orin Nano 8GB")
-            },
-            JetsonModuleType::OrinNano4GB => {
-                String::from("Jetson Orin Nano 4GB")
-            }
-            JetsonModuleType::XavierNX => {
-                String::from("Jetson Xavier NX")
-            },
-            _ => {
-                String::from("Unknown USB Device")
-            }
-        };
+    pub fn from_usb(device: &UsbDevice, module: Option<Module>) -> Jetson {
+        let mut jetson = Jetson::new(
+            device.bus.to_string().as_str(),
+            device.dev.to_string().as_str(),
+            &device.vendor,
+            &device.product,
+            &device.port,
+            module
+        );
+        jetson.update_usb(device);
+        jetson
     }
 
-    pub fn to_string(&self) -> String {
-        self.module_name.clone() + " (Bus " + &self.bus + " Device " + &self.dev + ": ID " + &self.vendor_number + ":" + &self.module_number + ")"
+    // the board re-enumerated on its port, e.g. as it reboots into the next stage
+    pub fn update_usb(&mut self, device: &UsbDevice) {
+        self.bus = device.bus.to_string();
+        self.dev = device.dev.to_string();
+        self.speed = device.speed.clone();
+        self.hubs = device.hubs.clone();
+        // not every stage has a serial number, keep the one known
+        if device.serial.is_some() {
+            self.serial = device.serial.clone();
+        }
+    }
+
+    // what ties the entry to a physical unit, e.g. "S/N 1421022001234, ECID 0x8001..."
+    pub fn identity(&self) -> Option<String> {
+        let ids: Vec<String> = [("S/N", &self.serial), ("ECID", &self.ecid)]
+                                .iter()
+                                .filter_map(|(label, id)| id.as_ref().map(|id| format!("{} {}", label, id)))
+                                .collect();
+        match ids.is_empty() {
+            true => None,
+            false => Some(ids.join(", ")),
+        }
+    }
+
+    // a job or the flash result still needs the entry
+    pub fn is_busy(&self) -> bool {
+        self.is_flashing() || self.is_flashed() || self.flash_job.is_some()
+    }
+
+    // enumerated below high speed, i.e. a bad cable or port
+    pub fn is_slow_link(&self) -> bool {
+        usb::mbps(&self.speed).is_some_and(|mbps| mbps < usb::HIGH_SPEED)
+    }
+
+    // the USB 2.0 hubs the board sits behind, where massflash drops boards
+    pub fn usb2_hubs(&self) -> Vec<&Hub> {
+        self.hubs.iter().filter(|hub| hub.is_usb2()).collect()
+    }
+
+    // what is wrong with the USB link, for the device list
+    pub fn link_badge(&self) -> Option<String> {
+        if self.is_slow_link() {
+            return Some(format!("[{} Mbit/s link]", self.speed));
+        }
+        self.usb2_hubs().first().map(|hub| format!("[USB 2.0 hub {}]", hub.port))
     }
 
     pub fn reset_flashing(&mut self) {
@@ -163,3 +223,9 @@ impl Jetson {
         self.logger.as_mut().unwrap().clear();
     }
 }
+
+impl fmt::Display for Jetson {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (Bus {} Device {}: ID {}:{})", self.module_name, self.bus, self.dev, self.vendor_number, self.module_number)
+    }
+}
@@ -0,0 +1,63 @@
+use crossterm::event::{KeyEvent, KeyCode};
+use tui::{
+    backend::Backend,
+    widgets::{Block, Borders, Paragraph, Wrap},
+    Frame, layout::{Margin, Alignment},
+};
+use crate::{App, app::CancelTarget, jetson::Signal};
+
+// open the dialog for whatever the cancel key is about, if anything is running
+pub fn open(app: &mut App) {
+    match app.cancel_target() {
+        Some(target) => {
+            app.cancel_target = Some(target);
+            app.index = 4;
+        },
+        None => {
+            app.tx.post(Signal::Message(String::from("Nothing to cancel.\n")));
+        }
+    }
+}
+
+pub fn cancel_job_ui<B: Backend>(f: &mut Frame<B>, app: &mut App) {
+    let title = Block::default()
+                            .title("Dialog")
+                            .borders(Borders::ALL);
+    let size = f.size();
+    let inner_size = f.size().inner(&Margin { vertical: size.height / 3, horizontal: size.width / 3 });
+    let text_size = inner_size.inner(&Margin { vertical: inner_size.height / 3, horizontal: 2, });
+    let text = match &app.cancel_target {
+        Some(CancelTarget::Install) => String::from("Cancel the environment setup? It can be resumed with F6. [y/N]"),
+        Some(CancelTarget::Massflash) => String::from("Cancel flashing on all ports? [y/N]"),
+        Some(CancelTarget::Device(instance)) => format!("Cancel flashing on port {}? [y/N]", instance),
+        None => String::new(),
+    };
+    let paragraph = Paragraph::new(text)
+                                            .alignment(Alignment::Center)
+                                            .wrap(Wrap { trim: false });
+
+    f.render_widget(paragraph, text_size);
+    f.render_widget(title, inner_size);
+}
+
+pub fn control(app: &mut App, key: KeyEvent) {
+    match key.code {
+        KeyCode::Char('y') | KeyCode::Char('Y') => {
+            if let Some(target) = app.cancel_target.take() {
+                app.cancel(&target);
+                let line = match &target {
+                    CancelTarget::Install => String::from("Cancelling environment setup\n"),
+                    CancelTarget::Massflash => String::from("Cancelling flash on all ports\n"),
+                    CancelTarget::Device(instance) => String::from("Cancelling flash on port ") + instance + "\n",
+                };
+                app.tx.post(Signal::Message(line));
+            }
+            app.index = 0;
+        },
+        KeyCode::Char('n') | KeyCode::Char('N') | KeyCode::Esc | KeyCode::Enter => {
+            app.cancel_target = None;
+            app.index = 0;
+        },
+        _ => {},
+    }
+}
@@ -0,0 +1,97 @@
+use std::fs;
+
+use crossterm::event::{KeyEvent, KeyCode};
+use tui::{
+    backend::Backend,
+    layout::{Constraint, Direction, Layout, Margin},
+    style::{Style, Color},
+    text::{Span, Spans},
+    widgets::{Block, Borders, Paragraph},
+    Frame,
+};
+use crate::{App, test::env_setup::DTB_REPORT};
+
+// load the report written by the last environment setup and show it
+pub fn open(app: &mut App) {
+    let report = match app.current_release() {
+        Some(release) => release.dir(".") + "/" + DTB_REPORT,
+        None => String::from(DTB_REPORT),
+    };
+    app.dtb_report = match fs::read_to_string(report) {
+        Ok(report) => report.lines().map(|line| line.to_string()).collect(),
+        Err(_) => vec![String::from("No device tree report yet. Install the environment for flashing first.")],
+    };
+    app.dtb_report_scroll = 0;
+    app.index = 3;
+}
+
+pub fn dtb_diff_ui<B: Backend>(f: &mut Frame<B>, app: &mut App) {
+    let chunks = Layout::default()
+                    .direction(Direction::Vertical)
+                    .margin(0)
+                    .constraints(
+                        [
+                            Constraint::Min(3),
+                            Constraint::Length(1),
+                        ].as_ref()
+                    )
+                    .split(f.size());
+
+    let block = Block::default()
+                    .title(match app.current_release() {
+                        Some(release) => format!("Device tree changes ({}/{})", release.version, DTB_REPORT),
+                        None => format!("Device tree changes ({})", DTB_REPORT),
+                    })
+                    .borders(Borders::ALL);
+
+    let lines: Vec<Spans> = app.dtb_report.iter().map(|line| {
+        let style = match line.chars().next() {
+            Some('+') => Style::default().fg(Color::Green),
+            Some('-') => Style::default().fg(Color::Red),
+            Some('~') => Style::default().fg(Color::Yellow),
+            Some('=') => Style::default().fg(Color::Black).bg(Color::White),
+            _ => Style::default(),
+        };
+        Spans::from(Span::styled(line.clone(), style))
+    }).collect();
+
+    let inner_size = block.inner(chunks[0]).inner(&Margin { vertical: 0, horizontal: 1 });
+    let max_scroll = (app.dtb_report.len() as u16).saturating_sub(inner_size.height);
+    app.dtb_report_scroll = app.dtb_report_scroll.min(max_scroll);
+
+    let paragraph = Paragraph::new(lines).scroll((app.dtb_report_scroll, 0));
+
+    f.render_widget(paragraph, inner_size);
+    f.render_widget(block, chunks[0]);
+
+    let key_style = Style::default().bg(Color::White).fg(Color::Black);
+    let help = Spans::from(vec![
+        Span::styled("↑ ↓ PgUp PgDn", key_style),
+        Span::raw(" Scroll "),
+        Span::styled("Q", key_style),
+        Span::raw(" Return to device list"),
+    ]);
+    f.render_widget(Paragraph::new(help), chunks[1]);
+}
+
+pub fn control(app: &mut App, key: KeyEvent) -> Option<()> {
+    match key.code {
+        KeyCode::Up => {
+            app.dtb_report_scroll = app.dtb_report_scroll.saturating_sub(1);
+        },
+        KeyCode::Down => {
+            app.dtb_report_scroll = app.dtb_report_scroll.saturating_add(1);
+        },
+        KeyCode::PageUp => {
+            app.dtb_report_scroll = app.dtb_report_scroll.saturating_sub(20);
+        },
+        KeyCode::PageDown => {
+            app.dtb_report_scroll = app.dtb_report_scroll.saturating_add(20);
+        },
+        KeyCode::Char('q') | KeyCode::Char('Q') | KeyCode::Esc => {
+            app.index = 0;
+        },
+        _ => {},
+    }
+    None
+}
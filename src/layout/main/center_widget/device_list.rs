@@ -10,6 +10,7 @@ use tui::{
 use crate::{App, UISelectionModel, UISelection, test::env_setup::{check_env, setup_workspace}, app::InstallStatus, jetson::FlashStatus};
 use crate::jetson::Signal;
 use crate::module_detect::refresh_devlist;
+use crate::layout::{cancel_job, dtb_diff};
 
 pub fn devices_ui<B: Backend>(f: &mut Frame<B>, size: Rect, app: &mut App) {
     let block = Block::default()
@@ -43,14 +44,14 @@ pub fn control(app: &mut App, key: KeyEvent) {
     match key.code {
         KeyCode::Up => {
             let index = app.previous_device();
-            if index.is_some() {
-                app.select(UISelectionModel { focused: UISelection::DeviceList(None), current: UISelection::DeviceList(Some(index.unwrap())) });
+            if let Some(index) = index {
+                app.select(UISelectionModel { focused: UISelection::DeviceList(None), current: UISelection::DeviceList(Some(index)) });
             }
         },
         KeyCode::Down => {
             let index = app.next_device();
-            if index.is_some() {
-                app.select(UISelectionModel { focused: UISelection::DeviceList(None), current: UISelection::DeviceList(Some(index.unwrap())) });
+            if let Some(index) = index {
+                app.select(UISelectionModel { focused: UISelection::DeviceList(None), current: UISelection::DeviceList(Some(index)) });
             }
         },
         KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('Q') => {
@@ -59,21 +60,18 @@ pub fn control(app: &mut App, key: KeyEvent) {
         },
         KeyCode::Enter => {
             if let InstallStatus::Installing(_) = app.install_status {
-                app.tx.send(Signal::Message(String::from("Please wait for environment setup finished.\n"))).unwrap();
+                app.tx.post(Signal::Message(String::from("Please wait for environment setup finished.\n")));
             } else {
                 match app.flash_status {
-                    FlashStatus::Wait => {
+                    FlashStatus::Flashing => {
+                        app.tx.post(Signal::Message(String::from("Flashing in progress.\n")));
+                    },
+                    // after a massflash single devices may still be re-flashed
+                    FlashStatus::Wait | FlashStatus::Finished | FlashStatus::Failed | FlashStatus::Cancelled => {
                         if let UISelection::DeviceList(_) = app.selection.current {
                             app.index = 1;
                         }
-                    },
-                    FlashStatus::Flashing => {
-                        app.tx.send(Signal::Message(String::from("Flashing in progress.\n"))).unwrap();
-                    },
-                    FlashStatus::Finished => {
-                        app.tx.send(Signal::Message(String::from("Please restart program.\n"))).unwrap();
                     }
-                    _ => {}
                 }
             }
         },
@@ -81,11 +79,34 @@ pub fn control(app: &mut App, key: KeyEvent) {
             refresh_devlist(app);
         },
         KeyCode::F(6) => {
-            if app.installer.is_none() && !check_env(app.tx.clone()) && app.install_status == InstallStatus::NotInstalled {
-                let tx = app.create_new_publisher();
-                tx.send(Signal::EnvironmentInstalling(SystemTime::now())).unwrap();
-                app.installer = Some(setup_workspace(tx, "."));
+            let can_install = matches!(app.install_status, InstallStatus::NotInstalled | InstallStatus::Failed { .. } | InstallStatus::Cancelled { .. });
+            if let Some(release) = app.current_release().cloned() {
+                if app.installer.is_none() && !check_env(app.tx.clone(), &release) && can_install {
+                    let tx = app.create_new_publisher();
+                    tx.post(Signal::EnvironmentInstalling(SystemTime::now()));
+                    app.installer = Some(setup_workspace(tx, ".", release, false));
+                }
             }
+        },
+        KeyCode::Char('c') | KeyCode::Char('C') => {
+            cancel_job::open(app);
+        },
+        KeyCode::F(8) => {
+            // throw the workspace away and start over, e.g. after NVIDIA files were modified by hand
+            let busy = matches!(app.install_status, InstallStatus::Installing(_)) || matches!(app.flash_status, FlashStatus::Flashing) || app.has_flashing_device();
+            if let Some(release) = app.current_release().cloned() {
+                if app.installer.is_none() && !busy {
+                    let tx = app.create_new_publisher();
+                    tx.post(Signal::EnvironmentInstalling(SystemTime::now()));
+                    app.installer = Some(setup_workspace(tx, ".", release, true));
+                }
+            }
+        },
+        KeyCode::F(7) => {
+            dtb_diff::open(app);
+        },
+        KeyCode::F(9) => {
+            app.next_release();
         }
         _ => {},
     }
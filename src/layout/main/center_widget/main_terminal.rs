@@ -30,8 +30,5 @@ fn main_terminal_inner_ui<B: Backend>(f: &mut Frame<B>, size: Rect, app: &mut Ap
     f.render_widget(paragraph, size.inner(&Margin { vertical: 0, horizontal: 1, }));
 }
 
-pub fn control(_app: &mut App, key: KeyEvent) {
-    match key.code {
-        _ => {},
-    }
+pub fn control(_app: &mut App, _key: KeyEvent) {
 }
\ No newline at end of file
@@ -7,11 +7,33 @@ use crate::App;
 
 pub mod device_list;
 pub mod main_terminal;
+pub mod setup_checklist;
 
 use device_list::*;
 use main_terminal::*;
+use setup_checklist::*;
 
 pub fn center_ui<B: Backend>(f: &mut Frame<B>, size: Rect, app: &mut App) {
+    // the setup checklist takes a column while there is an installer run to show
+    if app.setup_progress.is_some() {
+        let chunks = Layout::default()
+                            .direction(Direction::Horizontal)
+                            .margin(0)
+                            .constraints(
+                                [
+                                    Constraint::Percentage(30),
+                                    Constraint::Percentage(30),
+                                    Constraint::Percentage(40)
+                                ].as_ref()
+                            )
+                            .split(size);
+
+        devices_ui(f, chunks[0], app);
+        setup_checklist_ui(f, chunks[1], app);
+        main_terminal_ui(f, chunks[2], app);
+        return;
+    }
+
     let chunks = Layout::default()
                         .direction(Direction::Horizontal)
                         .margin(0)
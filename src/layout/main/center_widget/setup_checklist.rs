@@ -0,0 +1,42 @@
+use tui::{
+    backend::Backend,
+    layout::{Rect, Margin},
+    style::{Style, Color},
+    text::{Span, Spans},
+    widgets::{Block, Borders, List, ListItem},
+    Frame,
+};
+use crate::{App, app::InstallStatus, test::setup_error::SetupStep};
+
+// one line per setup step: done, running, failed or still to do
+pub fn setup_checklist_ui<B: Backend>(f: &mut Frame<B>, size: Rect, app: &mut App) {
+    let block = Block::default()
+                        .title("Setup")
+                        .borders(Borders::ALL);
+    let inner_size = block.inner(size).inner(&Margin { vertical: 0, horizontal: 1 });
+    f.render_widget(block, size);
+
+    let progress = match &app.setup_progress {
+        Some(progress) => progress,
+        None => return,
+    };
+    let failed = match &app.install_status {
+        InstallStatus::Failed { step, .. } => Some(*step),
+        _ => None,
+    };
+
+    let items: Vec<ListItem> = SetupStep::ALL.iter().map(|step| {
+        let (mark, style) = if progress.completed.contains(step) {
+            (String::from("[v]"), Style::default().fg(Color::Green))
+        } else if failed == Some(*step) {
+            (String::from("[x]"), Style::default().fg(Color::Red))
+        } else if progress.step == *step {
+            (format!("[{:>2}%]", (progress.fraction * 100.0) as u16), Style::default().fg(Color::Yellow))
+        } else {
+            (String::from("[ ]"), Style::default())
+        };
+        ListItem::new(Spans::from(Span::styled(format!("{} {}", mark, step), style)))
+    }).collect();
+
+    f.render_widget(List::new(items), inner_size);
+}
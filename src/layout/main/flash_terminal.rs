@@ -17,8 +17,6 @@ pub fn terminal_ui<B: Backend>(f: &mut Frame<B>, size: Rect, app: &mut App) {
 
             f.render_widget(border, size);
             f.render_widget(paragraph, text_size);
-
-            return;
         },
         UISelection::DeviceList(Some(index)) => {
             let terminal_name = app.devlist[index].logger.as_ref().unwrap().name.clone();
@@ -27,7 +25,7 @@ pub fn terminal_ui<B: Backend>(f: &mut Frame<B>, size: Rect, app: &mut App) {
             terminal_inner_ui(f,  block.inner(size), app);
             f.render_widget(block, size);
         }
-        _ => { return; }
+        _ => {}
     }
 }
 
@@ -9,9 +9,13 @@ pub fn help_ui<B: Backend>(f: &mut Frame<B>, size: Rect, _app: &mut App) {
     let keys = vec![
         ("F5", "Refresh device list"),
         ("F6", "Install environment for flashing"),
+        ("F7", "Device tree changes"),
+        ("F8", "Rebuild environment from scratch"),
+        ("F9", "Switch Jetson Linux release"),
         ("Q", "Quit"),
         ("↑ ↓ ", "Select device"),
         ("ENTER", "Flash device"),
+        ("C", "Cancel flash or setup"),
     ];
     let key_style = Style::default().bg(Color::White).fg(Color::Black);
     let description_style = Style::default();
@@ -1,27 +1,68 @@
 use tui::{
     backend::Backend,
-    layout::Rect,
-    Frame, widgets::Block, text::{Spans, Span}, style::{Style, Color},
+    layout::{Constraint, Direction, Layout, Rect},
+    Frame, widgets::{Block, Gauge}, text::{Spans, Span}, style::{Style, Color},
 };
-use crate::{App, app::InstallStatus};
+use crate::{App, app::InstallStatus, test::setup_error::SetupStep};
 
 pub fn indicator_ui<B: Backend>(f: &mut Frame<B>, size: Rect, app: &mut App) {
-    let line = match app.install_status {
+    let line = match &app.install_status {
         InstallStatus::NotInstalled => String::from("Environment not found. Press F6 to install"),
         InstallStatus::Installing(timestamp) => {
             String::from("Installing flash environment... ") + timestamp.elapsed().unwrap().as_secs().to_string().as_str() + " sec(s)"
         },
         InstallStatus::Installed => String::from("OK"),
+        InstallStatus::Failed { step, reason } => {
+            format!("Setup failed at [{}/{}] {}: {}. Press F6 to resume or F8 to start over", step.number(), SetupStep::ALL.len(), step, reason)
+        },
+        InstallStatus::Cancelled { step } => {
+            format!("Setup cancelled at [{}/{}] {}. Press F6 to resume or F8 to start over", step.number(), SetupStep::ALL.len(), step)
+        },
+    };
+
+    let line = match app.current_release() {
+        Some(release) => format!("[{}] {}", release.version, line),
+        None => String::from("No release descriptor found in releases/"),
     };
 
     let style = match app.install_status {
         InstallStatus::NotInstalled => Style::default().fg(Color::White).bg(Color::Red),
         InstallStatus::Installing(_) => Style::default().fg(Color::Black).bg(Color::Yellow),
         InstallStatus::Installed => Style::default().fg(Color::White).bg(Color::Green),
+        InstallStatus::Failed { .. } => Style::default().fg(Color::White).bg(Color::Red),
+        InstallStatus::Cancelled { .. } => Style::default().fg(Color::Black).bg(Color::Yellow),
     };
 
+    // while installing, the right half of the line is a progress bar for the whole setup
+    let (text_size, progress) = match (&app.install_status, &app.setup_progress) {
+        (InstallStatus::Installing(_), Some(progress)) => {
+            let chunks = Layout::default()
+                            .direction(Direction::Horizontal)
+                            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
+                            .split(size);
+            (chunks[0], Some((chunks[1], progress)))
+        },
+        _ => (size, None),
+    };
 
     let title = Spans::from(Span::styled(line, style));
     let block = Block::default().title(title);
-    f.render_widget(block, size);
+    f.render_widget(block, text_size);
+
+    if let Some((gauge_size, progress)) = progress {
+        let mut label = format!("[{}/{}] {}", progress.number(), progress.total, progress.name());
+        if !progress.detail.is_empty() {
+            label += &format!(": {}", progress.detail);
+        }
+        label += &format!(" {}%", (progress.overall() * 100.0) as u16);
+        if let Some(eta) = progress.eta() {
+            label += &format!(", about {} min left", eta.as_secs() / 60 + 1);
+        }
+
+        let gauge = Gauge::default()
+                        .gauge_style(Style::default().fg(Color::Yellow).bg(Color::DarkGray))
+                        .ratio(progress.overall())
+                        .label(label);
+        f.render_widget(gauge, gauge_size);
+    }
 }
\ No newline at end of file
@@ -69,7 +69,7 @@ pub fn control(app: &mut App, key: KeyEvent) -> Option<()> {
                     let now = SystemTime::now();
                     refresh_devlist(app);
                     let elapsed_time = now.elapsed().unwrap().as_millis().to_string();
-                    app.tx.send(Signal::Message(String::from("Refreshing device list takes ") + &elapsed_time + " milliseconds.\n")).unwrap();
+                    app.tx.post(Signal::Message(String::from("Refreshing device list takes ") + &elapsed_time + " milliseconds.\n"));
                 }
                 _ => {
                     center_widget::device_list::control(app, key);
@@ -1,3 +1,5 @@
 pub mod main;
 pub mod select_mode;
-pub mod quit;
\ No newline at end of file
+pub mod quit;
+pub mod dtb_diff;
+pub mod cancel_job;
\ No newline at end of file
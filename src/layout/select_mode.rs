@@ -1,19 +1,30 @@
+use std::time::SystemTime;
+
 use crossterm::event::{KeyEvent, KeyCode};
 use tui::{
     backend::Backend,
     widgets::{Block, Borders, Paragraph},
     Frame, layout::{Margin, Alignment},
 };
-use crate::{App, test::{flash::flash_device, env_setup::{check_env, setup_workspace}}};
+use crate::{App, jetson::{FlashStatus, Signal}, test::{flash::{flash_device, flash_single_device}, env_setup::{check_env, setup_workspace}}};
 
-pub fn select_mode_ui<B: Backend>(f: &mut Frame<B>, _app: &mut App) {
+pub fn select_mode_ui<B: Backend>(f: &mut Frame<B>, app: &mut App) {
     let title = Block::default()
                             .title("Dialog")
                             .borders(Borders::ALL);
     let size = f.size();
     let inner_size = f.size().inner(&Margin { vertical: size.height / 3, horizontal: size.width / 3 });
     let text_size = inner_size.inner(&Margin { vertical: inner_size.height / 3, horizontal: 1, });
-    let paragraph = Paragraph::new("Select flashing mode\n\n[1] Flashing for test\n[2] Flashing for release\n[Q] Return to device list")
+    let release = match app.current_release() {
+        Some(release) => release.version.clone(),
+        None => String::from("none"),
+    };
+    let port = match app.selected_device_index() {
+        Some(index) => app.devlist[index].instance_number.clone(),
+        None => String::from("-"),
+    };
+    let text = format!("Select flashing mode\n\nJetson Linux {} (← → to change)\n\n[1] Flashing for test\n[2] Flashing for release\n[3] Only port {} for test\n[4] Only port {} for release\n[Q] Return to device list", release, port, port);
+    let paragraph = Paragraph::new(text)
                                             .alignment(Alignment::Center);
 
     f.render_widget(paragraph, text_size);
@@ -25,20 +36,25 @@ pub fn control(app: &mut App, key: KeyEvent) -> Option<()> {
         KeyCode::Char('1') | KeyCode::Char('t') | KeyCode::Char('T') | KeyCode::Enter => {
             // test
             app.index = 0;
-            if check_env(app.tx.clone()) {
-                flash_device(app, true).unwrap();
-            } else {
-                setup_workspace(app.tx.clone(), ".");
-            }
+            flash_or_install(app, true, false);
         }
         KeyCode::Char('2') | KeyCode::Char('r') | KeyCode::Char('R') => {
             // release
             app.index = 0;
-            if check_env(app.tx.clone()) {
-                flash_device(app, false).unwrap();   
-            } else {
-                setup_workspace(app.tx.clone(), ".");
-            }
+            flash_or_install(app, false, false);
+        }
+        KeyCode::Char('3') => {
+            // test, selected device only
+            app.index = 0;
+            flash_or_install(app, true, true);
+        }
+        KeyCode::Char('4') => {
+            // release, selected device only
+            app.index = 0;
+            flash_or_install(app, false, true);
+        }
+        KeyCode::Left | KeyCode::Right => {
+            app.next_release();
         }
         KeyCode::Char('q') | KeyCode::Char('Q') | KeyCode::Esc => {
             app.index = 0;
@@ -46,4 +62,37 @@ pub fn control(app: &mut App, key: KeyEvent) -> Option<()> {
         _ => {},
     }
     None
-}
\ No newline at end of file
+}
+
+fn flash_or_install(app: &mut App, is_for_test: bool, single: bool) {
+    let release = match app.current_release() {
+        Some(release) => release.clone(),
+        None => {
+            app.tx.post(Signal::Message(String::from("No release descriptor found in releases/\n")));
+            return;
+        }
+    };
+
+    if !check_env(app.tx.clone(), &release) {
+        if app.installer.is_some() {
+            app.tx.post(Signal::Message(String::from("The environment is already being installed\n")));
+            return;
+        }
+        let tx = app.create_new_publisher();
+        tx.post(Signal::EnvironmentInstalling(SystemTime::now()));
+        app.installer = Some(setup_workspace(tx, ".", release, false));
+        return;
+    }
+
+    let result = match single {
+        true => flash_single_device(app, is_for_test),
+        false if app.flash_status == FlashStatus::Finished => {
+            app.tx.post(Signal::Message(String::from("Please restart program.\n")));
+            return;
+        },
+        false => flash_device(app, is_for_test),
+    };
+    if let Err(e) = result {
+        app.tx.post(Signal::Message(format!("Cannot flash: {}\n", e)));
+    }
+}
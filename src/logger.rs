@@ -48,15 +48,8 @@ impl Logger {
     pub fn output(&mut self) -> &str {
         let rx_as_ref = self.rx.as_ref().unwrap();
 
-        loop {
-            match rx_as_ref.try_recv() {
-                Ok(s) => {
-                    self.buffer += &s[..];
-                },
-                _ => {
-                    break;
-                }
-            }
+        while let Ok(s) = rx_as_ref.try_recv() {
+            self.buffer += &s[..];
         }
         while self.buffer.lines().count() > 60 {
             self.buffer.drain(..=self.buffer.find('\n').unwrap_or(0)+1);
@@ -6,19 +6,41 @@ pub mod app;
 pub mod test;
 pub mod devicetree;
 pub mod logger;
+pub mod event;
+pub mod hotplug;
 pub mod module_detect;
+pub mod module_catalog;
+pub mod usb;
 
 use ui_selection::{UISelection, UISelectionModel};
 use app::*;
+use test::{artifact_cache::seed_cache, release::Release};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     use term::{term_init, term_deinit};
 
+    // sg_test_host seed-cache <dir> : copy the L4T archives from e.g. a USB stick into the cache
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() > 1 {
+        return match (args[1].as_str(), args.get(2)) {
+            ("seed-cache", Some(source)) => {
+                let releases = Release::load_all(".")?;
+                let seeded = seed_cache(".", &releases, source)?;
+                for name in &seeded {
+                    println!("{}", name);
+                }
+                println!("{} archive(s) copied into the cache", seeded.len());
+                Ok(())
+            },
+            _ => Err(format!("usage: {} [seed-cache <dir>]", args[0]).into()),
+        };
+    }
+
     let mut terminal = term_init()?;
 
     let app: App<'static> = App::new();
-    let _ = run_app(&mut terminal, app).await?;
+    run_app(&mut terminal, app).await?;
 
     term_deinit(terminal)?;
 
@@ -0,0 +1,140 @@
+use std::fs;
+
+use serde::Deserialize;
+
+use crate::jetson::Stage;
+use crate::test::release::Release;
+use crate::test::setup_error::SetupError;
+
+// in the workspace, next to releases/
+pub const MODULES: &str = "modules.toml";
+
+// partition layout l4t_initrd_flash.sh uses for a rootfs on an external device
+const EXTERNAL_LAYOUT: &str = "tools/kernel_flash/flash_l4t_external.xml";
+
+// A Jetson module and how to flash it, see modules.toml
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Module {
+    // USB idProduct in recovery mode
+    pub product: String,
+    pub name: String,
+    pub board: String,
+    // "emmc", or the external device holding the rootfs, e.g. nvme0n1
+    pub storage: String,
+    pub dtb: String,
+    pub l4t: Vec<String>,
+    #[serde(default)]
+    pub options: Vec<String>,
+}
+
+// product ids of the USB gadgets a board shows after leaving recovery mode
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Gadgets {
+    pub initrd: Vec<String>,
+    pub booted: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Catalog {
+    #[serde(default)]
+    pub gadgets: Gadgets,
+    #[serde(rename = "module")]
+    pub modules: Vec<Module>,
+}
+
+impl Catalog {
+    pub fn load(workspace: &str) -> Result<Catalog, SetupError> {
+        let path = workspace.to_string() + "/" + MODULES;
+        let content = fs::read_to_string(&path).map_err(|error| SetupError::Io { path: path.clone(), error })?;
+        toml::from_str(&content).map_err(|e| SetupError::Config { path, reason: e.message().to_string() })
+    }
+
+    // the module answering with this product id in recovery mode
+    pub fn find(&self, product: &str) -> Option<&Module> {
+        self.modules.iter().find(|module| module.product.eq_ignore_ascii_case(product))
+    }
+
+    // A module the release's massflash package is built for. The modules sharing a board config
+    // share its storage and options, so any of them will do.
+    pub fn for_release(&self, release: &Release) -> Option<&Module> {
+        self.modules.iter().find(|module| module.board == release.board && module.supports(release))
+    }
+
+    // where in its lifecycle a board answering with this product id is, None for other devices
+    pub fn stage(&self, product: &str) -> Option<Stage> {
+        let listed = |products: &Vec<String>| products.iter().any(|listed| listed.eq_ignore_ascii_case(product));
+        if self.find(product).is_some() {
+            return Some(Stage::Recovery);
+        }
+        if listed(&self.gadgets.initrd) {
+            return Some(Stage::Initrd);
+        }
+        if listed(&self.gadgets.booted) {
+            return Some(Stage::Booted);
+        }
+        None
+    }
+}
+
+impl Module {
+    // "r35.3.1" belongs to the "r35" line
+    pub fn supports(&self, release: &Release) -> bool {
+        self.l4t.iter().any(|line| release.version == *line || release.version.starts_with(&(line.clone() + ".")))
+    }
+
+    pub fn is_external(&self) -> bool {
+        self.storage != "emmc"
+    }
+
+    // the storage and the module's own `<options>` for l4t_initrd_flash.sh
+    pub fn flash_options(&self) -> Vec<String> {
+        let mut args = vec![];
+        if self.is_external() {
+            args.extend([String::from("--external-device"), self.storage.clone(), String::from("-c"), String::from(EXTERNAL_LAYOUT)]);
+        }
+        args.extend(self.options.iter().cloned());
+        args
+    }
+
+    // the <rootdev> after the board: with an external rootfs the module boots from its own QSPI
+    pub fn rootdev(&self) -> &'static str {
+        match self.is_external() {
+            true => "internal",
+            false => "mmcblk0p1",
+        }
+    }
+
+    // `<options> <board> <rootdev>` for l4t_initrd_flash.sh
+    pub fn flash_args(&self) -> Vec<String> {
+        let mut args = self.flash_options();
+        args.push(self.board.clone());
+        args.push(String::from(self.rootdev()));
+        args
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn massflash_options_come_from_the_release_board() {
+        let workspace = env!("CARGO_MANIFEST_DIR");
+        let catalog = Catalog::load(workspace).unwrap();
+        let release = Release::load(&(workspace.to_string() + "/releases/r35.3.1.toml")).unwrap();
+
+        let module = catalog.for_release(&release).unwrap();
+        assert_eq!(module.flash_options(), [
+            "--external-device",
+            "nvme0n1",
+            "-c",
+            "tools/kernel_flash/flash_l4t_external.xml",
+            "-p",
+            "-c bootloader/t186ref/cfg/flash_t234_qspi.xml",
+        ]);
+        assert_eq!(module.rootdev(), "internal");
+    }
+}
@@ -1,79 +1,46 @@
-use std::process::{Stdio, Command};
-use std::io::Read;
-
 use crate::App;
 use crate::jetson::*;
+use crate::usb::NVIDIA;
 
+// Bring the device list in line with what is connected now, the way hotplug events would have
 pub fn refresh_devlist(app: &mut App) {
-    use regex::Regex;
-
-    app.clear_devlist();
-
-    let devices = Command::new("bash")
-                                .args([
-                                    "-c",
-                                    "grep 0955 /sys/bus/usb/devices/*/idVendor"
-                                ])
-                                .stdout(Stdio::piped())
-                                .spawn()
-                                .unwrap();
-
-    let mut buf = String::new();
-    devices.stdout.unwrap().read_to_string(&mut buf).unwrap();
-
-    for device in buf.lines() {
-        let re = Regex::new(r"\/sys\/bus\/usb\/devices\/(([\d]+)-([\d.]+))\/idVendor:0955").unwrap();
-        let captured = re.captures(device);
-
-        match captured {
-            Some(capture) => {
-                let path = &capture[1];
-                let path = String::from("/sys/bus/usb/devices/") + path + "/";
-                let bus = path.clone() + "busnum";
-                let dev = path.clone() + "devnum";
-                let product = path.clone() + "idProduct";
-                
-                let busnum = Command::new("cat")
-                                .arg(&bus)
-                                .output()
-                                .unwrap()
-                                .stdout;
-                let busnum = String::from_utf8(busnum).unwrap().trim().parse::<usize>().unwrap();
-                let devnum = Command::new("cat")
-                                .arg(&dev)
-                                .output()
-                                .unwrap()
-                                .stdout;
-                let devnum = String::from_utf8(devnum).unwrap().trim().parse::<usize>().unwrap();
-                let productnum = Command::new("cat")
-                                .arg(&product)
-                                .output()
-                                .unwrap()
-                                .stdout;
-                let productnum = String::from_utf8(productnum).unwrap().trim().to_string();
-
-                let jetson_detected = Jetson::new(
-                    busnum.to_string().as_str(),
-                    devnum.to_string().as_str(),
-                    "0955",
-                    &productnum,
-                    &capture[1]
-                );
-
-                // 현재 디바이스가 플래시 완료된 상태인지 확인
-                let flashed = !["7323", "7423", "7e19", "7523", "7623", "7023", "7223", "7019"].contains(&&jetson_detected.module_number[..]);
-                
-                if !flashed {
-                    app.devlist.push(jetson_detected);
+    let devices = match app.sysfs.devices() {
+        Ok(devices) => devices,
+        Err(e) => {
+            app.tx.post(Signal::Message(format!("Cannot list USB devices: {}\n", e)));
+            return;
+        }
+    };
+
+    let mut present = vec![];
+    for (port, device) in devices {
+        match device {
+            Ok(device) => {
+                if device.vendor != NVIDIA {
+                    continue;
                 }
+                present.push(device.port.clone());
+                app.add_device(device);
             },
-            _ => {
-                continue;
+            // e.g. unplugged while being read, the others are still listed. A board listed on the
+            // port stays, a read failing for a moment must not drop it in the middle of a flash.
+            Err(e) => {
+                present.push(port);
+                app.tx.post(Signal::Message(format!("Cannot read USB device {}\n", e)));
             }
         }
     }
 
-    if app.devlist.len() > 0 {
+    // 포트에서 사라진 디바이스 : 플래시 중이거나 완료된 디바이스는 disconnected 로 남음
+    let gone: Vec<String> = app.devlist.iter()
+                                .map(|jetson| jetson.instance_number.clone())
+                                .filter(|port| !present.contains(port))
+                                .collect();
+    for port in gone {
+        app.remove_device(&port);
+    }
+
+    if !app.devlist.is_empty() {
         use crate::UISelectionModel;
         use crate::UISelection;
         app.selection = UISelectionModel { focused: UISelection::DeviceList(None), current: UISelection::DeviceList(Some(0)) };
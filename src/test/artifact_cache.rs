@@ -0,0 +1,333 @@
+use std::fs;
+use std::path::Path;
+use std::process::Stdio;
+
+use serde::Deserialize;
+use tokio::process::Command;
+
+use crate::event::Publisher;
+use crate::jetson::Signal;
+use crate::test::checksum::Manifest;
+use crate::test::env_setup::finish;
+use crate::test::job::JobControl;
+use crate::test::release::{Artifact, Release};
+use crate::test::setup_error::SetupError;
+
+// optional, in the workspace
+pub const CACHE_CONFIG: &str = "cache.toml";
+
+// Where the L4T archives are kept (as <dir>/<version>/<name>) and fetched from, e.g.
+//
+//     dir = "/var/cache/sg_test_host"
+//     mirror = "http://192.168.0.10/l4t"
+//
+// `mirror` has the same layout as the cache and may also be a local directory or a file:// URL.
+// Without a config the archives are kept in the workspace and only downloaded from NVIDIA.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CacheConfig {
+    pub dir: Option<String>,
+    pub mirror: Option<String>,
+}
+
+impl CacheConfig {
+    pub fn load(workspace: &str) -> Result<CacheConfig, SetupError> {
+        let path = workspace.to_string() + "/" + CACHE_CONFIG;
+        if !Path::new(&path).exists() {
+            return Ok(CacheConfig::default());
+        }
+
+        let content = fs::read_to_string(&path).map_err(|error| SetupError::Io { path: path.clone(), error })?;
+        toml::from_str(&content).map_err(|e| SetupError::Config { path, reason: e.message().to_string() })
+    }
+}
+
+pub struct ArtifactCache {
+    dir: String,
+    mirror: Option<String>,
+}
+
+impl ArtifactCache {
+    pub fn new(workspace: &str, config: CacheConfig) -> ArtifactCache {
+        ArtifactCache {
+            dir: config.dir.unwrap_or_else(|| workspace.to_string()),
+            mirror: config.mirror.map(|mirror| mirror.trim_end_matches('/').to_string()),
+        }
+    }
+
+    pub fn path(&self, release: &Release, name: &str) -> String {
+        self.dir.clone() + "/" + &release.version + "/" + name
+    }
+
+    // Return the path of a verified copy of the artifact, trying the cache, then the mirror, then
+    // NVIDIA. Whatever gets downloaded stays in the cache for the next workspace.
+    pub async fn fetch(&self, tx: &Publisher, control: &JobControl, release: &Release, manifest: &Manifest, artifact: &Artifact) -> Result<String, SetupError> {
+        let name = &artifact.name[..];
+        let cached = self.path(release, name);
+
+        // fail before spending an hour on a download that could never be verified
+        manifest.expected(name)?;
+        if Path::new(&cached).exists() && checked(manifest, name, &cached).await.is_ok() {
+            tx.send(Signal::Message(name.to_string() + " found in " + &self.dir + "\n")).await;
+            return Ok(cached);
+        }
+        let dir = self.dir.clone() + "/" + &release.version;
+        tokio::fs::create_dir_all(&dir).await.map_err(|error| SetupError::Io { path: dir.clone(), error })?;
+
+        if let Some(mirror) = &self.mirror {
+            let source = mirror.clone() + "/" + &release.version + "/" + name;
+            tx.send(Signal::Message(String::from("Fetching ") + &source + "\n")).await;
+            let fetched = match source.strip_prefix("file://") {
+                Some(file) => copy_async(file, &cached).await,
+                None if source.contains("://") => download(tx, control, &source, &cached).await,
+                None => copy_async(&source, &cached).await,
+            };
+            let fetched = match fetched {
+                Ok(()) => verify(manifest, name, &cached).await,
+                Err(e) => Err(e),
+            };
+            match fetched {
+                Ok(()) => return Ok(cached),
+                Err(SetupError::Cancelled) => return Err(SetupError::Cancelled),
+                Err(e) => {
+                    tx.send(Signal::Message(format!("Mirror failed ({}), trying {}\n", e, artifact.url))).await;
+                }
+            }
+        }
+
+        tx.send(Signal::Message(String::from("Downloading ") + name + "...\n")).await;
+        download(tx, control, &artifact.url, &cached).await?;
+        verify(manifest, name, &cached).await?;
+
+        Ok(cached)
+    }
+}
+
+// Copy the archives of every release from `source` (e.g. a USB stick) into the cache, returning
+// what was copied as <version>/<name>. `source` may use the cache layout or hold the archives
+// side by side; as names like public_sources.tbz2 repeat between releases, a loose archive is
+// only taken by the release whose digest it matches.
+pub fn seed_cache(workspace: &str, releases: &[Release], source: &str) -> Result<Vec<String>, SetupError> {
+    let cache = ArtifactCache::new(workspace, CacheConfig::load(workspace)?);
+
+    let mut seeded = vec![];
+    for release in releases {
+        let manifest = release.manifest()?;
+        for artifact in release.artifacts.iter().filter(|a| a.sha256.is_some()) {
+            let name = &artifact.name;
+            let nested = source.to_string() + "/" + &release.version + "/" + name;
+            let loose = source.to_string() + "/" + name;
+
+            let from = if Path::new(&nested).exists() {
+                // check before copying, a bad archive must not replace a good one
+                manifest.verify(name, &nested)?;
+                nested
+            } else if Path::new(&loose).exists() && manifest.verify(name, &loose).is_ok() {
+                loose
+            } else {
+                continue;
+            };
+
+            let dir = cache.dir.clone() + "/" + &release.version;
+            fs::create_dir_all(&dir).map_err(|error| SetupError::Io { path: dir.clone(), error })?;
+            copy(&from, &cache.path(release, name))?;
+            seeded.push(release.version.clone() + "/" + name);
+        }
+    }
+
+    Ok(seeded)
+}
+
+fn copy(from: &str, to: &str) -> Result<(), SetupError> {
+    fs::copy(from, to).map(|_| ()).map_err(|error| SetupError::Io { path: from.to_string(), error })
+}
+
+async fn copy_async(from: &str, to: &str) -> Result<(), SetupError> {
+    tokio::fs::copy(from, to).await.map(|_| ()).map_err(|error| SetupError::Io { path: from.to_string(), error })
+}
+
+async fn download(tx: &Publisher, control: &JobControl, url: &str, file: &str) -> Result<(), SetupError> {
+    let mut wget = Command::new("wget");
+    wget.args(["--quiet", url, "-O", file])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+
+    let child = control.spawn(&mut wget).map_err(|error| SetupError::Spawn { command: String::from("wget"), error })?;
+    if let Err(e) = finish(control, child, String::from("wget ") + url).await {
+        // the partial download is of no use
+        let _ = tokio::fs::remove_file(file).await;
+        return Err(e);
+    }
+    tx.send(Signal::Message(String::from("Downloaded ") + url + "\n")).await;
+
+    Ok(())
+}
+
+async fn verify(manifest: &Manifest, name: &str, file: &str) -> Result<(), SetupError> {
+    if let Err(e) = checked(manifest, name, file).await {
+        // never leave a bad archive around for the next run to extract
+        let _ = tokio::fs::remove_file(file).await;
+        return Err(e);
+    }
+
+    Ok(())
+}
+
+// hashing a multi-gigabyte archive is blocking work
+async fn checked(manifest: &Manifest, name: &str, file: &str) -> Result<(), SetupError> {
+    let (manifest, name, file) = (manifest.clone(), name.to_string(), file.to_string());
+    tokio::task::spawn_blocking(move || manifest.verify(&name, &file)).await.unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    use sha2::{Digest, Sha256};
+
+    use crate::event::event_bus;
+
+    const ARCHIVE: &str = "tegra_linux_sample-root-filesystem_r35.3.1_aarch64.tbz2";
+    const CONTENT: &str = "the published archive";
+    // nothing listens there, a download fails at once
+    const UNREACHABLE: &str = "http://127.0.0.1:9/rootfs.tbz2";
+
+    struct Workspace(PathBuf);
+
+    impl Workspace {
+        fn new(name: &str) -> Workspace {
+            let dir = std::env::temp_dir().join(format!("artifact-cache-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            Workspace(dir)
+        }
+
+        fn path(&self, relative: &str) -> String {
+            self.0.join(relative).to_string_lossy().to_string()
+        }
+
+        fn write(&self, relative: &str, content: &str) {
+            let path = self.0.join(relative);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+
+        // a descriptor listing ARCHIVE with the digest of CONTENT
+        fn release(&self) -> Release {
+            let digest: String = Sha256::digest(CONTENT).iter().map(|b| format!("{:02x}", b)).collect();
+            self.write("r35.3.1.toml", &format!(
+                "version = \"r35.3.1\"\nboard = \"jetson-orin-nano-devkit\"\n\n[[artifact]]\nname = \"{}\"\nurl = \"{}\"\nsha256 = \"{}\"\nextract = \"rootfs\"\n",
+                ARCHIVE, UNREACHABLE, digest));
+            Release::load(&self.path("r35.3.1.toml")).unwrap()
+        }
+    }
+
+    impl Drop for Workspace {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    // fetch ARCHIVE, returning the result and the messages sent meanwhile
+    async fn fetch(cache: &ArtifactCache, release: &Release) -> (Result<String, SetupError>, Vec<String>) {
+        let (tx, mut rx) = event_bus();
+        let fetched = cache.fetch(&tx, &JobControl::default(), release, &release.manifest().unwrap(), &release.artifacts[0]).await;
+
+        let mut messages = vec![];
+        while let Ok(signal) = rx.try_recv() {
+            if let Signal::Message(message) = signal {
+                messages.push(message);
+            }
+        }
+        (fetched, messages)
+    }
+
+    #[tokio::test]
+    async fn a_cached_archive_with_the_right_digest_is_not_downloaded() {
+        let workspace = Workspace::new("cached");
+        let release = workspace.release();
+        let cache = ArtifactCache::new(&workspace.path(""), CacheConfig::default());
+        workspace.write(&("r35.3.1/".to_string() + ARCHIVE), CONTENT);
+
+        let (fetched, messages) = fetch(&cache, &release).await;
+        assert_eq!(fetched.unwrap(), cache.path(&release, ARCHIVE));
+        assert!(messages.iter().all(|m| !m.starts_with("Downloading")), "{:?}", messages);
+    }
+
+    #[tokio::test]
+    async fn a_cached_archive_with_another_digest_is_fetched_again() {
+        let workspace = Workspace::new("stale");
+        let release = workspace.release();
+        let cache = ArtifactCache::new(&workspace.path(""), CacheConfig::default());
+        workspace.write(&("r35.3.1/".to_string() + ARCHIVE), "a truncated download");
+
+        let (fetched, messages) = fetch(&cache, &release).await;
+        assert!(fetched.is_err());
+        assert!(messages.iter().any(|m| m.starts_with("Downloading")), "{:?}", messages);
+        // the failed download does not leave a file behind
+        assert!(!Path::new(&cache.path(&release, ARCHIVE)).exists());
+    }
+
+    fn with_mirror(workspace: &Workspace, mirror: String) -> ArtifactCache {
+        ArtifactCache::new(&workspace.path(""), CacheConfig { dir: Some(workspace.path("cache")), mirror: Some(mirror) })
+    }
+
+    #[tokio::test]
+    async fn fetches_from_a_local_mirror() {
+        let workspace = Workspace::new("mirror");
+        let release = workspace.release();
+        workspace.write(&("mirror/r35.3.1/".to_string() + ARCHIVE), CONTENT);
+
+        for mirror in [workspace.path("mirror"), String::from("file://") + &workspace.path("mirror/")] {
+            let cache = with_mirror(&workspace, mirror);
+            let _ = fs::remove_dir_all(workspace.path("cache"));
+
+            let (fetched, messages) = fetch(&cache, &release).await;
+            let cached = fetched.unwrap();
+            assert_eq!(cached, workspace.path(&("cache/r35.3.1/".to_string() + ARCHIVE)));
+            assert_eq!(fs::read_to_string(&cached).unwrap(), CONTENT);
+            assert!(messages.iter().all(|m| !m.starts_with("Downloading")), "{:?}", messages);
+        }
+    }
+
+    #[tokio::test]
+    async fn refuses_a_mirrored_archive_with_the_wrong_digest() {
+        let workspace = Workspace::new("bad-mirror");
+        let release = workspace.release();
+        workspace.write(&("mirror/r35.3.1/".to_string() + ARCHIVE), "tampered with");
+        let cache = with_mirror(&workspace, workspace.path("mirror"));
+
+        let (fetched, messages) = fetch(&cache, &release).await;
+        assert!(fetched.is_err());
+        assert!(messages.iter().any(|m| m.starts_with("Mirror failed") && m.contains("is corrupted")), "{:?}", messages);
+        assert!(!Path::new(&cache.path(&release, ARCHIVE)).exists());
+    }
+
+    #[test]
+    fn seeds_the_cache_from_a_stick() {
+        let workspace = Workspace::new("seed");
+        let release = workspace.release();
+        workspace.write(&("stick/".to_string() + ARCHIVE), CONTENT);
+
+        let seeded = seed_cache(&workspace.path(""), std::slice::from_ref(&release), &workspace.path("stick")).unwrap();
+        assert_eq!(seeded, [String::from("r35.3.1/") + ARCHIVE]);
+        assert_eq!(fs::read_to_string(workspace.path(&("r35.3.1/".to_string() + ARCHIVE))).unwrap(), CONTENT);
+    }
+
+    #[test]
+    fn seeding_refuses_an_archive_with_the_wrong_digest() {
+        let workspace = Workspace::new("seed-bad");
+        let release = workspace.release();
+
+        // a loose archive may belong to another release and is left alone
+        workspace.write(&("stick/".to_string() + ARCHIVE), "tampered with");
+        assert_eq!(seed_cache(&workspace.path(""), std::slice::from_ref(&release), &workspace.path("stick")).unwrap(), Vec::<String>::new());
+
+        // one filed under the release is not
+        workspace.write(&("stick/r35.3.1/".to_string() + ARCHIVE), "tampered with");
+        let seeded = seed_cache(&workspace.path(""), std::slice::from_ref(&release), &workspace.path("stick"));
+        assert!(matches!(seeded, Err(SetupError::Checksum { .. })));
+        assert!(!Path::new(&workspace.path(&("r35.3.1/".to_string() + ARCHIVE))).exists());
+    }
+}
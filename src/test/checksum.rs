@@ -0,0 +1,63 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read};
+
+use sha2::{Digest, Sha256};
+
+use crate::test::setup_error::SetupError;
+
+// Expected digests of the archives of one L4T release, by file name
+#[derive(Debug, Clone)]
+pub struct Manifest {
+    pub sha256: HashMap<String, String>,
+    // the release descriptor the digests come from, for error messages
+    source: String,
+}
+
+impl Manifest {
+    pub fn new(source: &str, mut sha256: HashMap<String, String>) -> Result<Manifest, SetupError> {
+        for (name, digest) in sha256.iter_mut() {
+            if digest.len() != 64 || !digest.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return Err(SetupError::Manifest { path: source.to_string(), reason: format!("{} is not a SHA-256 digest: \"{}\"", name, digest) });
+            }
+            *digest = digest.to_ascii_lowercase();
+        }
+
+        Ok(Manifest { sha256, source: source.to_string() })
+    }
+
+    pub fn expected(&self, name: &str) -> Result<&str, SetupError> {
+        match self.sha256.get(name) {
+            Some(digest) => Ok(digest),
+            None => Err(SetupError::Manifest { path: self.source.clone(), reason: format!("no SHA-256 listed for {}; add the `sha256` NVIDIA publishes on the release page", name) }),
+        }
+    }
+
+    // Err(SetupError::Checksum) unless the file is exactly the published archive
+    pub fn verify(&self, name: &str, file: &str) -> Result<(), SetupError> {
+        let expected = self.expected(name)?;
+        let actual = sha256_file(file).map_err(|error| SetupError::Io { path: file.to_string(), error })?;
+        if actual != expected {
+            return Err(SetupError::Checksum { file: file.to_string(), expected: expected.to_string(), actual });
+        }
+
+        Ok(())
+    }
+}
+
+// hex encoded SHA-256 of a file, read in chunks so multi-GB archives are not loaded at once
+pub fn sha256_file(path: &str) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 1 << 20];
+
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+
+    Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
@@ -1,735 +1,409 @@
-use std::sync::mpsc::Sender;
+use std::collections::HashMap;
 use std::fs::{self, OpenOptions};
-use std::io::{Write, BufReader, BufRead};
-use std::process::{Stdio, Command};
-use std::thread::{self, JoinHandle};
+use std::io::Write;
+use std::path::Path;
+use std::process::Stdio;
 
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::{Child, Command};
+
+use crate::event::Publisher;
 use crate::jetson::*;
+use crate::module_catalog::{Catalog, MODULES};
+use crate::test::artifact_cache::{ArtifactCache, CacheConfig};
+use crate::test::extract::{extract as extract_archive, ExtractProgress};
+use crate::test::job::{Job, JobControl};
+use crate::test::release::{DeviceTree, Extract, Release};
+use crate::test::setup_error::{SetupError, SetupStep};
+use crate::test::setup_progress::SetupProgress;
+use crate::test::setup_state::SetupState;
 use crate::devicetree::{
-    decompile::decompile_to_string,
-    compile::compile_to_file,
+    decompile::decompile,
+    compile::compile,
+    diff::{diff, DtbChange},
+    fdt::Fdt,
     node::DtbNode,
+    patch::DtbPatch,
+    validate::{validate, Diagnostic, SchemaSet, Severity},
 };
 
-pub fn check_env(tx: Sender<Signal>) -> bool {
+pub fn check_env(tx: Publisher, release: &Release) -> bool {
 
-    if check_test_env() && check_release_env() {
-        tx.send(Signal::EnvironmentInstalled).unwrap();
+    if check_test_env(release) && check_release_env(release) && SetupState::load(&release.dir(".")).is_finished() {
+        tx.post(Signal::EnvironmentInstalled);
         return true;
     }
 
     false
 }
 
-fn check_test_env() -> bool {
-    // check env
-    let ls_out = std::process::Command::new("ls")
-                            .args([
-                                "-d",
-                                "test/Linux_for_Tegra",
-                            ])
-                            .stdout(Stdio::piped())
-                            .stderr(Stdio::null())
-                            .spawn()
-                            .unwrap();
-
-    let out = std::process::Command::new("wc")
-                            .args([
-                                "-l"
-                            ])
-                            .stdin(ls_out.stdout.unwrap())
-                            .stderr(Stdio::null())
-                            .output()
-                            .unwrap()
-                            .stdout;
-                            
-
-    let out = String::from_utf8(out).unwrap().trim().parse::<usize>();
-
-    match out {
-        Ok(0) => {
-            return false;
-        },
-        Ok(1) => {
-            return true;
-        },
-        _ => {
-        panic!("Please check workspace");
-        }
-    }
+fn check_test_env(release: &Release) -> bool {
+    Path::new(&release.l4t(".", true)).is_dir()
 }
 
-fn check_release_env() -> bool {
-    // check env
-    let ls_out = std::process::Command::new("ls")
-                            .args([
-                                "-d",
-                                "release/Linux_for_Tegra",
-                            ])
-                            .stdout(Stdio::piped())
-                            .stderr(Stdio::null())
-                            .spawn()
-                            .unwrap();
-
-    let out = std::process::Command::new("wc")
-                            .args([
-                                "-l"
-                            ])
-                            .stdin(ls_out.stdout.unwrap())
-                            .stderr(Stdio::null())
-                            .output()
-                            .unwrap()
-                            .stdout;
-                            
-
-    let out = String::from_utf8(out).unwrap().trim().parse::<usize>();
-
-    match out {
-        Ok(0) => {
-            return false;
-        },
-        Ok(1) => {
-            return true;
-        },
-        _ => {
-        panic!("Please check workspace");
-        }
-    }
+fn check_release_env(release: &Release) -> bool {
+    Path::new(&release.l4t(".", false)).is_dir()
 }
 
-pub fn setup_workspace(tx: Sender<Signal>, path: &str) -> JoinHandle<()> {
+// Run the setup steps of `release` that have not finished yet. `clean` discards the recorded
+// progress and rebuilds the release's workspace from the first step.
+pub fn setup_workspace(tx: Publisher, path: &str, release: Release, clean: bool) -> Job {
     let path = path.to_string();
-    let handle = thread::spawn(move || {
+    Job::spawn(JobControl::default(), move |control| async move {
+        let control = &control;
         let path = &path[..];
-        tx.send(Signal::Message(String::from("[1/7] Download Jetson Linux...\n"))).unwrap();
-        let _ = download_jetson_linux(tx.clone(), &path);
-        tx.send(Signal::Message(String::from("[2/7] Patch device tree...\n"))).unwrap();
-        let _ = patch_device_tree(tx.clone(), &path);
-        tx.send(Signal::Message(String::from("[3/7] Apply binaries...\n"))).unwrap();
-        let _ = apply_binaries(tx.clone());
-        tx.send(Signal::Message(String::from("[4/7] Create default user...\n"))).unwrap();
-        let _ = create_default_user(tx.clone());
-        tx.send(Signal::Message(String::from("[5/7] Install startup programs...\n"))).unwrap();
-        let _ = install_test_client(tx.clone());
-        tx.send(Signal::Message(String::from("[6/7] Generating massflash package for test environment...\n"))).unwrap();
-        tx.send(Signal::Message(String::from("It may take a very long time.\n"))).unwrap();
-        let _  = generate_massflash_package(tx.clone());
-        tx.send(Signal::Message(String::from("Workspace setup has finished\n"))).unwrap();
-        tx.send(Signal::EnvironmentInstalled).unwrap();
-    });
-
-    handle
-}
+        let release = &release;
+        let release_dir = release.dir(path);
+        if let Err(error) = tokio::fs::create_dir_all(&release_dir).await {
+            let e = SetupError::Io { path: release_dir, error };
+            tx.send(Signal::EnvironmentFailed { step: SetupStep::Download, reason: e.to_string() }).await;
+            return;
+        }
 
-fn download_jetson_linux(tx: Sender<Signal>, path: &str) -> Result<(), Box<dyn std::error::Error>> {
-
-    let workspace = path.clone().to_string();
-    let jetson_linux = String::from(&workspace) + "/jetson_linux_r35.3.1_aarch64.tbz2";
-    let rootfs = String::from(&workspace) + "/tegra_linux_sample-root-filesystem_r35.3.1_aarch64.tbz2";
-    let test_rootfs_target= String::from(&workspace) + "/test/Linux_for_Tegra/rootfs/";
-    let sources = String::from(&workspace) + "/public_sources.tbz2";
-    let release_rootfs_target = String::from(&workspace) + "/release/Linux_for_Tegra/rootfs/";
-
-    let mut child = std::process::Command::new("rm")
-                        .args([
-                            "-rf",
-                            &jetson_linux,
-                            &sources,
-                            &rootfs,
-                            "wget.log",
-                            "Linux_for_Tegra",
-                            "test",
-                            "release",
-                        ])
-                        .stdout(Stdio::piped())
-                        .stderr(Stdio::null())
-                        .spawn()
-                        .unwrap();
-    let output = child.stdout.take().unwrap();
-    let reader = BufReader::new(output);
-    reader.lines().filter_map(|line| line.ok()).for_each(|line| { tx.send(Signal::Message(line + "\n")).unwrap(); } );
-    child.wait().unwrap();
-
-    tx.send(Signal::Message(String::from("Cleaning workspace...\n"))).unwrap();
-    let _ = std::process::Command::new("mkdir")
-                        .args([
-                            "test",
-                            "release",
-                        ])
-                        .output()
-                        .unwrap();
-
-    tx.send(Signal::Message(String::from("Downloading files from server...\n"))).unwrap();
-    let mut child = Command::new("wget")
-                    .args([
-                        "https://developer.nvidia.com/downloads/embedded/l4t/r35_release_v3.1/release/jetson_linux_r35.3.1_aarch64.tbz2/",
-                        "-O",
-                        &jetson_linux,
-                    ])
-                    .stdout(Stdio::piped())
-                    .stderr(Stdio::null())
-                    .spawn()
-                    .unwrap();
-    let output = child.stdout.take().unwrap();
-    let reader = BufReader::new(output);
-    reader.lines().filter_map(|line| line.ok()).for_each(|line| { tx.send(Signal::Message(line + "\n")).unwrap(); } );
-    child.wait().unwrap();
-
-    let mut child = Command::new("wget")
-                    .args([
-                        "https://developer.nvidia.com/downloads/embedded/l4t/r35_release_v3.1/release/tegra_linux_sample-root-filesystem_r35.3.1_aarch64.tbz2/",
-                        "-O",
-                        &rootfs,
-                    ])
-                    .stdout(Stdio::piped())
-                    .stderr(Stdio::null())
-                    .spawn()
-                    .unwrap();
-    let output = child.stdout.take().unwrap();
-    let reader = BufReader::new(output);
-    reader.lines().filter_map(|line| line.ok()).for_each(|line| { tx.send(Signal::Message(line + "\n")).unwrap(); } );
-    child.wait().unwrap();
-
-    let mut child = Command::new("wget")
-                    .args([
-                        "https://developer.nvidia.com/downloads/embedded/l4t/r35_release_v3.1/sources/public_sources.tbz2/",
-                        "-O",
-                        &sources,
-                    ])
-                    .stdout(Stdio::piped())
-                    .stderr(Stdio::null())
-                    .spawn()
-                    .unwrap();
-    let output = child.stdout.take().unwrap();
-    let reader = BufReader::new(output);
-    reader.lines().filter_map(|line| line.ok()).for_each(|line| { tx.send(Signal::Message(line + "\n")).unwrap(); } );
-    child.wait().unwrap();
-
-    let jetson_linux = String::from(&workspace) + "/jetson_linux_r35.3.1_aarch64.tbz2";
-    let rootfs = String::from(&workspace) + "/tegra_linux_sample-root-filesystem_r35.3.1_aarch64.tbz2";
-    let sources = String::from(&workspace) + "/public_sources.tbz2";
-
-    tx.send(Signal::Message(String::from("Extracting files for test environment...\n"))).unwrap();
-    let mut child = std::process::Command::new("tar")
-                    .args([
-                        "xf",
-                        &jetson_linux,
-                        "-C",
-                        "./test",
-                    ])
-                    .stdout(Stdio::piped())
-                    .stderr(Stdio::null())
-                    .spawn()
-                    .unwrap();
-    let output = child.stdout.take().unwrap();
-    let reader = BufReader::new(output);
-    reader.lines().filter_map(|line| line.ok()).for_each(|line| { tx.send(Signal::Message(line + "\n")).unwrap(); } );
-    child.wait().unwrap();
-
-    let mut child = std::process::Command::new("tar")
-                    .args([
-                        "xf",
-                        &sources,
-                        "-C",
-                        "./test",
-                    ])
-                    .stdout(Stdio::piped())
-                    .stderr(Stdio::null())
-                    .spawn()
-                    .unwrap();
-    let output = child.stdout.take().unwrap();
-    let reader = BufReader::new(output);
-    reader.lines().filter_map(|line| line.ok()).for_each(|line| { tx.send(Signal::Message(line + "\n")).unwrap(); } );
-    child.wait().unwrap();
-
-    let mut child = std::process::Command::new("tar")
-                    .args([
-                        "xpf",
-                        &rootfs,
-                        "-C",
-                        &test_rootfs_target,
-                    ])
-                    .stdout(Stdio::piped())
-                    .stderr(Stdio::null())
-                    .spawn()
-                    .unwrap();
-    let output = child.stdout.take().unwrap();
-    let reader = BufReader::new(output);
-    reader.lines().filter_map(|line| line.ok()).for_each(|line| { tx.send(Signal::Message(line + "\n")).unwrap(); } );
-    child.wait().unwrap();
-
-    tx.send(Signal::Message(String::from("Extracting files for release environment...\n"))).unwrap();
-    let mut child = std::process::Command::new("tar")
-                .args([
-                    "xf",
-                    &jetson_linux,
-                    "-C",
-                    "./release",
-                ])
-                .stdout(Stdio::piped())
-                    .stderr(Stdio::null())
-                    .spawn()
-                    .unwrap();
-    let output = child.stdout.take().unwrap();
-    let reader = BufReader::new(output);
-    reader.lines().filter_map(|line| line.ok()).for_each(|line| { tx.send(Signal::Message(line + "\n")).unwrap(); } );
-    child.wait().unwrap();
-
-    let mut child = std::process::Command::new("tar")
-                    .args([
-                        "xf",
-                        &sources,
-                        "-C",
-                        "./release",
-                    ])
-                    .stdout(Stdio::piped())
-                    .stderr(Stdio::null())
-                    .spawn()
-                    .unwrap();
-    let output = child.stdout.take().unwrap();
-    let reader = BufReader::new(output);
-    reader.lines().filter_map(|line| line.ok()).for_each(|line| { tx.send(Signal::Message(line + "\n")).unwrap(); } );
-    child.wait().unwrap();
-
-    let mut child = std::process::Command::new("tar")
-                    .args([
-                        "xpf",
-                        &rootfs,
-                        "-C",
-                        &release_rootfs_target,
-                    ])
-                    .stdout(Stdio::piped())
-                    .stderr(Stdio::null())
-                    .spawn()
-                    .unwrap();
-    let output = child.stdout.take().unwrap();
-    let reader = BufReader::new(output);
-    reader.lines().filter_map(|line| line.ok()).for_each(|line| { tx.send(Signal::Message(line + "\n")).unwrap(); } );
-    child.wait().unwrap();
-
-    let jetson_linux = String::from(&workspace) + "/jetson_linux_r35.3.1_aarch64.tbz2";
-    let rootfs = String::from(&workspace) + "/tegra_linux_sample-root-filesystem_r35.3.1_aarch64.tbz2";
-    let sources = String::from(&workspace) + "/public_sources.tbz2";
-    tx.send(Signal::Message(String::from("Removing archive files...\n"))).unwrap();
-    let mut child = std::process::Command::new("rm")
-                        .args([
-                            &jetson_linux,
-                            &sources,
-                            &rootfs,
-                        ])
-                        .stdout(Stdio::piped())
-                    .stderr(Stdio::null())
-                    .spawn()
-                    .unwrap();
-    let output = child.stdout.take().unwrap();
-    let reader = BufReader::new(output);
-    reader.lines().filter_map(|line| line.ok()).for_each(|line| { tx.send(Signal::Message(line + "\n")).unwrap(); } );
-    child.wait().unwrap();
+        let mut state = SetupState::load(&release_dir);
+        if clean {
+            tx.send(Signal::Message(String::from("Rebuilding ") + &release.version + " from scratch\n")).await;
+            if let Err(e) = state.reset() {
+                tx.send(Signal::EnvironmentFailed { step: SetupStep::Download, reason: e.to_string() }).await;
+                return;
+            }
+        }
 
-    Ok(())
+        for step in SetupStep::ALL {
+            if state.is_completed(step) {
+                tx.send(Signal::Message(format!("[{}/{}] {} already done, skipping\n", step.number(), SetupStep::ALL.len(), step))).await;
+                continue;
+            }
+
+            if control.is_cancelled() {
+                tx.send(Signal::EnvironmentCancelled { step }).await;
+                return;
+            }
+            tx.send(Signal::Message(format!("[{}/{}] {}...\n", step.number(), SetupStep::ALL.len(), step))).await;
+            let mut progress = SetupProgress::start(step, &state);
+            tx.send(Signal::SetupProgress(progress.clone())).await;
+
+            let result = match step {
+                SetupStep::Download => download_jetson_linux(tx.clone(), control, path, release, &mut progress).await,
+                SetupStep::PatchDeviceTree => {
+                    let (tx, path, release, mut progress) = (tx.clone(), path.to_string(), release.clone(), progress.clone());
+                    blocking(move || patch_device_tree(tx, &path, &release, &mut progress)).await
+                },
+                SetupStep::ApplyBinaries => apply_binaries(tx.clone(), control, path, release).await,
+                SetupStep::CreateDefaultUser => create_default_user(tx.clone(), control, path, release).await,
+                SetupStep::InstallTestClient => install_test_client(tx.clone(), path, release).await,
+                SetupStep::GenerateTestPackage => generate_massflash_package(tx.clone(), control, path, release, true).await,
+                SetupStep::GenerateReleasePackage => generate_massflash_package(tx.clone(), control, path, release, false).await,
+            };
+
+            // later steps build on this one, so stop here
+            let took = progress.started.elapsed().unwrap_or_default();
+            if let Err(SetupError::Cancelled) = result {
+                tx.send(Signal::Message(format!("{} cancelled\n", step))).await;
+                tx.send(Signal::EnvironmentCancelled { step }).await;
+                return;
+            }
+            if let Err(e) = result.and_then(|_| state.complete(step, took)) {
+                tx.send(Signal::Message(format!("{} failed: {}\n", step, e))).await;
+                tx.send(Signal::EnvironmentFailed { step, reason: e.to_string() }).await;
+                return;
+            }
+        }
+
+        tx.send(Signal::Message(String::from("Workspace setup for ") + &release.version + " has finished\n")).await;
+        tx.send(Signal::EnvironmentInstalled).await;
+    })
 }
 
-fn patch_device_tree(tx: Sender<Signal>, path: &str) -> Result<(), Box<dyn std::error::Error>> {
-    tx.send(Signal::Message(String::from("Patching") + XAVIER_NX_DTB + "\n")).unwrap();
-    patch_device_tree_xavier_nx(tx.clone(), path)?;
-    tx.send(Signal::Message(String::from("Patching") + ORIN_NX_16GB_DTB + "\n")).unwrap();
-    patch_device_tree_orin_nx_16gb(tx.clone(), path)?;
-    tx.send(Signal::Message(String::from("Patching") + ORIN_NX_8GB_DTB + "\n")).unwrap();
-    patch_device_tree_orin_nx_8gb(tx.clone(), path)?;
-    Ok(())
+// run blocking work (extraction, device tree patching) off the runtime's workers
+async fn blocking<T: Send + 'static>(f: impl FnOnce() -> Result<T, SetupError> + Send + 'static) -> Result<T, SetupError> {
+    tokio::task::spawn_blocking(f).await.map_err(|e| SetupError::Task { reason: e.to_string() })?
 }
 
-fn patch_device_tree_xavier_nx(tx: Sender<Signal>, path: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let dtb = path.to_string() + "/test/Linux_for_Tegra/kernel/dtb/" + XAVIER_NX_DTB;
-    let dts = path.to_string() + "/test/Linux_for_Tegra/kernel/dtb/" + XAVIER_NX_DTS;
+async fn download_jetson_linux(tx: Publisher, control: &JobControl, path: &str, release: &Release, progress: &mut SetupProgress) -> Result<(), SetupError> {
 
-    let mut root_node = DtbNode::new(decompile_to_string(tx.clone(), &dtb)?);
+    let manifest = release.manifest()?;
+    let cache = ArtifactCache::new(path, CacheConfig::load(path)?);
+    let test = release.dir(path) + "/test";
+    let release_env = release.dir(path) + "/release";
 
-    // sdcard slot
-    match root_node.find_childnode("sdhci@3440000") {
-        Some(sdhci) => {
-            let status = sdhci.find_property("status").unwrap();
-            status.value = Some("\"okay\"".to_string());
-        },
-        None => {
-            panic!("device tree corrupted");
-        }
+    // the extracted trees may be half written, the archives are checked against the manifest instead
+    tx.send(Signal::Message(String::from("Cleaning workspace...\n"))).await;
+    run(&tx, control, Command::new("rm")
+                .args([
+                    "-rf",
+                    &test,
+                    &release_env,
+                ])).await?;
+
+    run(&tx, control, Command::new("mkdir")
+                .args([
+                    &test,
+                    &release_env,
+                ])).await?;
+
+    // every fetch and every extraction counts as one part of the step
+    let parts = (release.artifacts.len() * 3) as f64;
+    let mut done = 0.0;
+
+    tx.send(Signal::Message(String::from("Downloading files from server...\n"))).await;
+    let mut archives = vec![];
+    for artifact in &release.artifacts {
+        progress.update(done / parts, &(String::from("fetching ") + &artifact.name));
+        tx.send(Signal::SetupProgress(progress.clone())).await;
+        archives.push((cache.fetch(&tx, control, release, &manifest, artifact).await?, artifact.extract));
+        done += 1.0;
     }
 
-    // camera
-    let cam_i2c0 = root_node
-                                                .find_childnode("cam_i2cmux").unwrap()
-                                                .find_childnode("i2c@0").unwrap();
-
-    let rbpcv3_imx477_a_1a = cam_i2c0
-                                    .find_childnode("rbpcv3_imx477_a@1a").unwrap();
-    
-    rbpcv3_imx477_a_1a.find_property("status").unwrap()
-                        .value = Some("\"okay\"".to_string());
-
-    // rbpcv3_imx477_a_1a.find_childnode("mode0").unwrap()
-    //                     .find_property("tegra_sinterface").unwrap()
-    //                     .value = Some("\"serial_a\"".to_string());
-
-    // rbpcv3_imx477_a_1a.find_childnode("mode1").unwrap()
-    //                     .find_property("tegra_sinterface").unwrap()
-    //                     .value = Some("\"serial_a\"".to_string());
-    
-    // rbpcv3_imx477_a_1a.find_childnode("ports").unwrap()
-    //                     .find_childnode("port@0").unwrap()
-    //                     .find_childnode("endpoint").unwrap()
-    //                     .find_property("port-index").unwrap()
-    //                     .value = Some("<0x00>".to_string());
-
-    // let rbpcv2_imx219_a_10 = cam_i2c0.find_childnode("rbpcv2_imx219_a@10").unwrap();
-
-    // rbpcv2_imx219_a_10.find_childnode("mode0").unwrap()
-    //                 .find_property("tegra_sinterface").unwrap()
-    //                 .value = Some("\"serial_a\"".to_string());
-
-    // rbpcv2_imx219_a_10.find_childnode("mode1").unwrap()
-    //                 .find_property("tegra_sinterface").unwrap()
-    //                 .value = Some("\"serial_a\"".to_string());
-
-    // rbpcv2_imx219_a_10.find_childnode("mode2").unwrap()
-    //                 .find_property("tegra_sinterface").unwrap()
-    //                 .value = Some("\"serial_a\"".to_string());
-
-    // rbpcv2_imx219_a_10.find_childnode("mode3").unwrap()
-    //                 .find_property("tegra_sinterface").unwrap()
-    //                 .value = Some("\"serial_a\"".to_string());
-
-    // rbpcv2_imx219_a_10.find_childnode("mode4").unwrap()
-    //                 .find_property("tegra_sinterface").unwrap()
-    //                 .value = Some("\"serial_a\"".to_string());
-
-    // rbpcv2_imx219_a_10.find_childnode("ports").unwrap()
-    //                 .find_childnode("port@0").unwrap()
-    //                 .find_childnode("endpoint").unwrap()
-    //                 .find_property("port-index").unwrap()
-    //                 .value = Some("<0x00>".to_string());
-
-    let cam_i2c1 = root_node
-                    .find_childnode("cam_i2cmux").unwrap()
-                    .find_childnode("i2c@1").unwrap();
-
-    let rbpcv3_imx477_c_1a = cam_i2c1
-                    .find_childnode("rbpcv3_imx477_c@1a").unwrap();
-
-    rbpcv3_imx477_c_1a.find_property("status").unwrap()
-                    .value = Some("\"okay\"".to_string());
-
-    let patched_string = root_node.stringify(0);
-    let mut patched_dts = OpenOptions::new()
-                                    .write(true)
-                                    .truncate(true)
-                                    .create(true)
-                                    .open(&dts)?;
-    patched_dts.write_all(patched_string.as_bytes())?;
-    compile_to_file(&dts, &dtb)?;
+    for (environment, is_for_test) in [(&test, true), (&release_env, false)] {
+        tx.send(Signal::Message(String::from("Extracting files to ") + environment + "...\n")).await;
+        let rootfs = release.l4t(path, is_for_test) + "/rootfs/";
+        for (archive, extract) in &archives {
+            let (target, preserve) = match extract {
+                Extract::L4t => (environment.clone(), false),
+                Extract::Rootfs => (rootfs.clone(), true),
+            };
+            let (tx, control, archive, mut extracting) = (tx.clone(), control.clone(), archive.clone(), progress.clone());
+            *progress = blocking(move || {
+                let mut on_progress = |extracted: &ExtractProgress| {
+                    let detail = format!("unpacking {} ({} files)", extracted.archive, extracted.entries);
+                    extracting.update((done + extracted.percent() as f64 / 100.0) / parts, &detail);
+                    tx.blocking_send(Signal::SetupProgress(extracting.clone()));
+                };
+                let extracted = extract_archive(&archive, &target, preserve, &control, &mut on_progress)?;
+                if extracted.skipped > 0 {
+                    tx.blocking_send(Signal::Message(format!("{}: {} device nodes left out, the rootfs is incomplete unless setup runs as root\n", extracted.archive, extracted.skipped)));
+                }
+                Ok(extracting)
+            }).await?;
+            done += 1.0;
+        }
+    }
 
     Ok(())
 }
 
-fn patch_device_tree_orin_nx_16gb(tx: Sender<Signal>, path: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let dtb = path.to_string() + "/test/Linux_for_Tegra/kernel/dtb/" + ORIN_NX_16GB_DTB;
-    let dts = path.to_string() + "/test/Linux_for_Tegra/kernel/dtb/" + ORIN_NX_16GB_DTS;
-
-    let mut root_node = DtbNode::new(decompile_to_string(tx.clone(), &dtb)?);
+// what the patches changed in every DTB, for QA to check before a release flash (per release)
+pub const DTB_REPORT: &str = "device_tree_report.txt";
+// optional extra rules for the patched nodes
+const DTB_SCHEMA: &str = "patches/schema.toml";
 
-    // camera
-    let cam_i2c0 = root_node
-                                                .find_childnode("cam_i2cmux").unwrap()
-                                                .find_childnode("i2c@0").unwrap();
+fn patch_device_tree(tx: Publisher, path: &str, release: &Release, progress: &mut SetupProgress) -> Result<(), SetupError> {
+    let mut report = String::from("# Device tree changes made by the patch files to ") + &release.version + "\n";
+    // overlay file -> the DTB it was made for
+    let mut overlays = HashMap::new();
 
-    let rbpcv3_imx477_a_1a = cam_i2c0
-                                    .find_childnode("rbpcv3_imx477_a@1a").unwrap();
-    
-    rbpcv3_imx477_a_1a.find_property("status").unwrap()
-                        .value = Some("\"okay\"".to_string());
+    for (index, DeviceTree { dtb, patch }) in release.device_trees.iter().enumerate() {
+        progress.update(index as f64 / release.device_trees.len() as f64, dtb);
+        tx.blocking_send(Signal::SetupProgress(progress.clone()));
 
-    rbpcv3_imx477_a_1a.find_childnode("mode0").unwrap()
-                        .find_property("tegra_sinterface").unwrap()
-                        .value = Some("\"serial_a\"".to_string());
-
-    rbpcv3_imx477_a_1a.find_childnode("mode1").unwrap()
-                        .find_property("tegra_sinterface").unwrap()
-                        .value = Some("\"serial_a\"".to_string());
-    
-    rbpcv3_imx477_a_1a.find_childnode("ports").unwrap()
-                        .find_childnode("port@0").unwrap()
-                        .find_childnode("endpoint").unwrap()
-                        .find_property("port-index").unwrap()
-                        .value = Some("<0x00>".to_string());
-
-    let rbpcv2_imx219_a_10 = cam_i2c0.find_childnode("rbpcv2_imx219_a@10").unwrap();
-
-    rbpcv2_imx219_a_10.find_childnode("mode0").unwrap()
-                    .find_property("tegra_sinterface").unwrap()
-                    .value = Some("\"serial_a\"".to_string());
-
-    rbpcv2_imx219_a_10.find_childnode("mode1").unwrap()
-                    .find_property("tegra_sinterface").unwrap()
-                    .value = Some("\"serial_a\"".to_string());
+        let patch_file = path.to_string() + "/" + patch;
+        if !Path::new(&patch_file).exists() {
+            tx.blocking_send(Signal::Message(String::from("Skipping ") + dtb + ": " + patch + " not found\n"));
+            continue;
+        }
+        tx.blocking_send(Signal::Message(String::from("Patching ") + dtb + " with " + patch + "\n"));
+        let changes = apply_device_tree_patch(&tx, path, &release.l4t(path, true), dtb, &patch_file, &mut overlays)
+                            .map_err(|e| SetupError::DeviceTree { dtb: dtb.to_string(), reason: e.to_string() })?;
 
-    rbpcv2_imx219_a_10.find_childnode("mode2").unwrap()
-                    .find_property("tegra_sinterface").unwrap()
-                    .value = Some("\"serial_a\"".to_string());
+        report.push_str(&format!("\n== {} ({})\n", dtb, patch));
+        if changes.is_empty() {
+            report.push_str("(no changes)\n");
+        }
+        for change in &changes {
+            report.push_str(&change.to_string());
+            report.push('\n');
+        }
+    }
 
-    rbpcv2_imx219_a_10.find_childnode("mode3").unwrap()
-                    .find_property("tegra_sinterface").unwrap()
-                    .value = Some("\"serial_a\"".to_string());
+    let report_file = release.dir(path) + "/" + DTB_REPORT;
+    fs::write(&report_file, report).map_err(|error| SetupError::Io { path: report_file.clone(), error })?;
+    tx.blocking_send(Signal::Message(String::from("Device tree changes written to ") + &report_file + "\n"));
 
-    rbpcv2_imx219_a_10.find_childnode("mode4").unwrap()
-                    .find_property("tegra_sinterface").unwrap()
-                    .value = Some("\"serial_a\"".to_string());
+    Ok(())
+}
 
-    rbpcv2_imx219_a_10.find_childnode("ports").unwrap()
-                    .find_childnode("port@0").unwrap()
-                    .find_childnode("endpoint").unwrap()
-                    .find_property("port-index").unwrap()
-                    .value = Some("<0x00>".to_string());
+fn apply_device_tree_patch(tx: &Publisher, path: &str, l4t: &str, dtb_name: &str, patch_file: &str, overlays: &mut HashMap<String, String>) -> Result<Vec<DtbChange>, Box<dyn std::error::Error>> {
+    let l4t = l4t.to_string() + "/";
+    let dtb = l4t.clone() + "kernel/dtb/" + dtb_name;
+    // the decompiled source is kept next to the DTB
+    let dts = dtb.trim_end_matches(".dtb").to_string() + ".dts";
+
+    let patch = DtbPatch::load(patch_file)?;
+    let schema_file = path.to_string() + "/" + DTB_SCHEMA;
+    let schemas = match Path::new(&schema_file).exists() {
+        true => Some(SchemaSet::load(&schema_file)?),
+        false => None,
+    };
+    let mut fdt = decompile(&dtb)?;
+
+    // keep NVIDIA's DTB untouched and let flash.sh pick the overlay up through OVERLAY_DTB_FILE
+    if let Some(overlay) = &patch.overlay {
+        // two patches writing the same file would silently leave only the last one
+        if let Some(other) = overlays.insert(overlay.name.clone(), dtb_name.to_string()) {
+            return Err(format!("overlay {} is already written by the patch for {}", overlay.name, other).into());
+        }
+        let dtbo = Fdt::new(patch.to_overlay(&fdt.root)?);
 
-    let cam_i2c1 = root_node
-                    .find_childnode("cam_i2cmux").unwrap()
-                    .find_childnode("i2c@1").unwrap();
+        // make sure the overlay applies before shipping it
+        let mut patched = fdt.root.clone();
+        patched.apply_overlay(&dtbo.root)?;
+        check_device_tree(tx, &fdt.root, &patched, &schemas)?;
 
-    let rbpcv3_imx477_c_1a = cam_i2c1
-                    .find_childnode("rbpcv3_imx477_c@1a").unwrap();
+        dtbo.write_to_file(&(l4t.clone() + "kernel/dtb/" + &overlay.name))?;
+        for board_config in &overlay.board_configs {
+            add_overlay_to_board_config(&(l4t.clone() + board_config), &overlay.name)?;
+        }
+        return Ok(diff(&fdt.root, &patched));
+    }
 
-    rbpcv3_imx477_c_1a.find_property("status").unwrap()
-                    .value = Some("\"okay\"".to_string());
+    let stock = fdt.root.clone();
+    patch.apply(&mut fdt.root)?;
+    check_device_tree(tx, &stock, &fdt.root, &schemas)?;
 
-    let patched_string = root_node.stringify(0);
+    let patched_string = fdt.stringify();
     let mut patched_dts = OpenOptions::new()
                                     .write(true)
                                     .truncate(true)
                                     .create(true)
                                     .open(&dts)?;
     patched_dts.write_all(patched_string.as_bytes())?;
-    compile_to_file(&dts, &dtb)?;
+    compile(&fdt, &dtb)?;
 
-    Ok(())
+    Ok(diff(&stock, &fdt.root))
 }
 
-fn patch_device_tree_orin_nx_8gb(tx: Sender<Signal>, path: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let dtb = path.to_string() + "/test/Linux_for_Tegra/kernel/dtb/" + ORIN_NX_8GB_DTB;
-    let dts = path.to_string() + "/test/Linux_for_Tegra/kernel/dtb/" + ORIN_NX_8GB_DTS;
-
-    let dts_content = decompile_to_string(tx.clone(), &dtb)?;
-
-    let mut root_node = DtbNode::new(dts_content);
-
-    // camera
-    let cam_i2c0 = root_node
-                                                .find_childnode("cam_i2cmux").unwrap()
-                                                .find_childnode("i2c@0").unwrap();
-
-    let rbpcv3_imx477_a_1a = cam_i2c0
-                                    .find_childnode("rbpcv3_imx477_a@1a").unwrap();
-    
-    rbpcv3_imx477_a_1a.find_property("status").unwrap()
-                        .value = Some("\"okay\"".to_string());
-
-    rbpcv3_imx477_a_1a.find_childnode("mode0").unwrap()
-                        .find_property("tegra_sinterface").unwrap()
-                        .value = Some("\"serial_a\"".to_string());
-
-    rbpcv3_imx477_a_1a.find_childnode("mode1").unwrap()
-                        .find_property("tegra_sinterface").unwrap()
-                        .value = Some("\"serial_a\"".to_string());
-    
-    rbpcv3_imx477_a_1a.find_childnode("ports").unwrap()
-                        .find_childnode("port@0").unwrap()
-                        .find_childnode("endpoint").unwrap()
-                        .find_property("port-index").unwrap()
-                        .value = Some("<0x00>".to_string());
-
-    let rbpcv2_imx219_a_10 = cam_i2c0.find_childnode("rbpcv2_imx219_a@10").unwrap();
-
-    rbpcv2_imx219_a_10.find_childnode("mode0").unwrap()
-                    .find_property("tegra_sinterface").unwrap()
-                    .value = Some("\"serial_a\"".to_string());
-
-    rbpcv2_imx219_a_10.find_childnode("mode1").unwrap()
-                    .find_property("tegra_sinterface").unwrap()
-                    .value = Some("\"serial_a\"".to_string());
-
-    rbpcv2_imx219_a_10.find_childnode("mode2").unwrap()
-                    .find_property("tegra_sinterface").unwrap()
-                    .value = Some("\"serial_a\"".to_string());
-
-    rbpcv2_imx219_a_10.find_childnode("mode3").unwrap()
-                    .find_property("tegra_sinterface").unwrap()
-                    .value = Some("\"serial_a\"".to_string());
+// Report what the patch broke; problems NVIDIA's DTB already has are only counted
+fn check_device_tree(tx: &Publisher, stock: &DtbNode, patched: &DtbNode, schemas: &Option<SchemaSet>) -> Result<(), Box<dyn std::error::Error>> {
+    let diagnostics = |root: &DtbNode| {
+        let mut diagnostics = validate(root);
+        if let Some(schemas) = schemas {
+            diagnostics.extend(schemas.check(root));
+        }
+        diagnostics
+    };
 
-    rbpcv2_imx219_a_10.find_childnode("mode4").unwrap()
-                    .find_property("tegra_sinterface").unwrap()
-                    .value = Some("\"serial_a\"".to_string());
+    let known = diagnostics(stock);
+    let (existing, introduced): (Vec<Diagnostic>, Vec<Diagnostic>) = diagnostics(patched).into_iter().partition(|d| known.contains(d));
 
-    rbpcv2_imx219_a_10.find_childnode("ports").unwrap()
-                    .find_childnode("port@0").unwrap()
-                    .find_childnode("endpoint").unwrap()
-                    .find_property("port-index").unwrap()
-                    .value = Some("<0x00>".to_string());
+    for diagnostic in &introduced {
+        tx.blocking_send(Signal::Message(diagnostic.to_string() + "\n"));
+    }
+    if !existing.is_empty() {
+        tx.blocking_send(Signal::Message(format!("{} issues already present in the stock DTB\n", existing.len())));
+    }
 
-    let cam_i2c1 = root_node
-                    .find_childnode("cam_i2cmux").unwrap()
-                    .find_childnode("i2c@1").unwrap();
+    let errors = introduced.iter().filter(|d| d.severity == Severity::Error).count();
+    if errors > 0 {
+        return Err(format!("device tree validation failed with {} errors", errors).into());
+    }
 
-    let rbpcv3_imx477_c_1a = cam_i2c1
-                    .find_childnode("rbpcv3_imx477_c@1a").unwrap();
+    Ok(())
+}
 
-    rbpcv3_imx477_c_1a.find_property("status").unwrap()
-                    .value = Some("\"okay\"".to_string());
+fn add_overlay_to_board_config(board_config: &str, overlay: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let line = format!("OVERLAY_DTB_FILE=\"${{OVERLAY_DTB_FILE}},{}\";", overlay);
+    if fs::read_to_string(board_config)?.lines().any(|l| l.trim() == line) {
+        return Ok(());
+    }
 
-    let patched_string = root_node.stringify(0);
-    let mut patched_dts = OpenOptions::new()
-                                    .write(true)
-                                    .truncate(true)
-                                    .create(true)
-                                    .open(&dts)?;
-    patched_dts.write_all(patched_string.as_bytes())?;
-    compile_to_file(&dts, &dtb)?;
+    let mut conf = OpenOptions::new()
+                        .append(true)
+                        .open(board_config)?;
+    conf.write_all(format!("\n# device tree patch from sg_test_host\n{}\n", line).as_bytes())?;
 
     Ok(())
 }
 
-fn apply_binaries(tx: Sender<Signal>) -> Result<(), Box<dyn std::error::Error>> {
-    let mut child = std::process::Command::new("./test/Linux_for_Tegra/apply_binaries.sh")
-                                    .stdout(Stdio::piped())
-                                    .stderr(Stdio::null())
-                                    .spawn()
-                                    .unwrap();
-
-    let output = child.stdout.take().unwrap();
-    let reader = BufReader::new(output);
+async fn apply_binaries(tx: Publisher, control: &JobControl, path: &str, release: &Release) -> Result<(), SetupError> {
+    run(&tx, control, &mut Command::new(release.l4t(path, true) + "/apply_binaries.sh")).await?;
+    run(&tx, control, &mut Command::new(release.l4t(path, false) + "/apply_binaries.sh")).await?;
 
-    reader.lines()
-        .filter_map(|line| line.ok())
-        .for_each(|line| { tx.send(Signal::Message(line + "\n")).unwrap(); });
+    Ok(())
+}
 
-    child.wait().unwrap();
+async fn create_default_user(tx: Publisher, control: &JobControl, path: &str, release: &Release) -> Result<(), SetupError> {
+    run(&tx, control, Command::new(release.l4t(path, true) + "/tools/l4t_create_default_user.sh")
+                .args([
+                    "-u",
+                    "jetson",
+                    "-p",
+                    "jetson",
+                    "-a",
+                    "--accept-license",
+                ])).await?;
 
-    let mut child = std::process::Command::new("./release/Linux_for_Tegra/apply_binaries.sh")
-                                    .stdout(Stdio::piped())
-                                    .stderr(Stdio::null())
-                                    .spawn()
-                                    .unwrap();
+    Ok(())
+}
 
-    let output = child.stdout.take().unwrap();
-    let reader = BufReader::new(output);
+async fn install_test_client(_tx: Publisher, path: &str, release: &Release) -> Result<(), SetupError> {
+    let copy = |name: &str| {
+        let from = path.to_string() + "/client/" + name;
+        let to = release.l4t(path, true) + "/rootfs/" + name;
+        async move {
+            tokio::fs::copy(&from, to).await.map(|_| ()).map_err(|error| SetupError::Io { path: from, error })
+        }
+    };
 
-    reader.lines()
-        .filter_map(|line| line.ok())
-        .for_each(|line| { tx.send(Signal::Message(line + "\n")).unwrap(); });
+    // copy script to launch test process
+    copy("launch_test.sh").await?;
 
-    child.wait().unwrap();
+    // copy binary
+    copy("sg_test_client").await?;
 
     Ok(())
 }
 
-fn create_default_user(tx: Sender<Signal>) -> Result<(), Box<dyn std::error::Error>> {
-    let mut child = Command::new("./test/Linux_for_Tegra/tools/l4t_create_default_user.sh")
-                                        .args([
-                                            "-u",
-                                            "jetson",
-                                            "-p",
-                                            "jetson",
-                                            "-a",
-                                            "--accept-license",
-                                        ])
-                                        .stdout(Stdio::piped())
-                                        .stderr(Stdio::null())
-                                        .spawn()
-                                        .unwrap();
-
-    let output = child.stdout.take().unwrap();
-    let reader = BufReader::new(output);
-    
-    reader.lines()
-        .filter_map(|line| line.ok())
-        .for_each(|line| { tx.send(Signal::Message(line + "\n")).unwrap() });
-
-    child.wait().unwrap();
-
-    Ok(())
+async fn generate_massflash_package(tx: Publisher, control: &JobControl, path: &str, release: &Release, is_for_test: bool) -> Result<(), SetupError> {
+    // storage and flash configuration of the modules flashed with the release's board config
+    let catalog = Catalog::load(path)?;
+    let module = catalog.for_release(release).ok_or(SetupError::Config {
+        path: path.to_string() + "/" + MODULES,
+        reason: format!("no module flashed with {} supports {}", release.board, release.version),
+    })?;
+
+    tx.send(Signal::Message(String::from("It may take a very long time.\n"))).await;
+
+    // the script is too chatty for the main terminal, only its exit code matters
+    let mut command = Command::new("./tools/kernel_flash/l4t_initrd_flash.sh");
+    command
+        .current_dir(release.l4t(path, is_for_test))
+        .arg("--no-flash")
+        .args(module.flash_options())
+        .args(["--network", "usb0", "--showlogs", "--massflash", &release.board, module.rootdev()])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+
+    let name = command.as_std().get_program().to_string_lossy().to_string();
+    let child = control.spawn(&mut command).map_err(|error| SetupError::Spawn { command: name.clone(), error })?;
+    finish(control, child, name).await
 }
 
-fn install_test_client(_tx: Sender<Signal>) -> Result<(), Box<dyn std::error::Error>> {
-    // copy script to launch test process
-    let _ = fs::copy("./client/launch_test.sh", "./test/Linux_for_Tegra/rootfs/launch_test.sh");
+// Run a command to completion, forwarding its stdout to the main terminal
+async fn run(tx: &Publisher, control: &JobControl, command: &mut Command) -> Result<(), SetupError> {
+    let name = command.as_std().get_program().to_string_lossy().to_string();
+    let mut child = control.spawn(command
+                        .stdout(Stdio::piped())
+                        .stderr(Stdio::null()))
+                        .map_err(|error| SetupError::Spawn { command: name.clone(), error })?;
 
-    // copy binary
-    let _ = fs::copy("./client/sg_test_client", "./test/Linux_for_Tegra/rootfs/sg_test_client");
-    
-    Ok(())
+    let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        tx.send(Signal::Message(line + "\n")).await;
+    }
+
+    finish(control, child, name).await
 }
 
-fn generate_massflash_package(tx: Sender<Signal>) -> Result<(), Box<dyn std::error::Error>> {
-    let mut child = Command::new("./tools/kernel_flash/l4t_initrd_flash.sh")
-                                        .current_dir("./test/Linux_for_Tegra/")
-                                        .args([
-                                            "--no-flash",
-                                            "--external-device",
-                                            "nvme0n1",
-                                            "-c",
-                                            "tools/kernel_flash/flash_l4t_external.xml",
-                                            "-p",
-                                            "-c bootloader/t186ref/cfg/flash_t234_qspi.xml",
-                                            "--network",
-                                            "usb0",
-                                            "--showlogs",
-                                            "--massflash",
-                                            "jetson-orin-nano-devkit",
-                                            "internal",
-                                        ])
-                                        .stdout(Stdio::null())
-                                        .stderr(Stdio::null())
-                                        .spawn()
-                                        .unwrap();
-
-    // let output = child.stdout.take().unwrap();
-    // let reader = BufReader::new(output);
-    
-    // reader.lines()
-    //     .filter_map(|line| line.ok())
-    //     .for_each(|line| { tx.send(Signal::Message(line + "\n")).unwrap() });
-
-    child.wait().unwrap();
-
-    tx.send(Signal::Message(String::from("[7/7] Generating massflash package for release environment...\n"))).unwrap();
-    tx.send(Signal::Message(String::from("It may take a very long time.\n"))).unwrap();
-    let mut child = Command::new("./tools/kernel_flash/l4t_initrd_flash.sh")
-                                        .current_dir("./release/Linux_for_Tegra")
-                                        .args([
-                                            "--no-flash",
-                                            "--external-device",
-                                            "nvme0n1",
-                                            "-c",
-                                            "tools/kernel_flash/flash_l4t_external.xml",
-                                            "-p",
-                                            "-c bootloader/t186ref/cfg/flash_t234_qspi.xml",
-                                            "--network",
-                                            "usb0",
-                                            "--showlogs",
-                                            "--massflash",
-                                            "jetson-orin-nano-devkit",
-                                            "internal",
-                                        ])
-                                        .stdout(Stdio::null())
-                                        .stderr(Stdio::null())
-                                        .spawn()
-                                        .unwrap();
-
-    // let output = child.stdout.take().unwrap();
-    // let reader = BufReader::new(output);
-    
-    // reader.lines()
-    //     .filter_map(|line| line.ok())
-    //     .for_each(|line| { tx.send(Signal::Message(line + "\n")).unwrap() });
-
-    child.wait().unwrap();
+// Wait for a command started through `control`; a failure after a cancel is the cancel
+pub async fn finish(control: &JobControl, mut child: Child, name: String) -> Result<(), SetupError> {
+    let status = control.wait(&mut child).await.map_err(|error| SetupError::Spawn { command: name.clone(), error })?;
+    if !status.success() {
+        return match control.is_cancelled() {
+            true => Err(SetupError::Cancelled),
+            false => Err(SetupError::Failed { command: name, status }),
+        };
+    }
 
     Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,310 @@
+use std::cell::Cell;
+use std::ffi::CString;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+
+use bzip2::read::MultiBzDecoder;
+use tar::{Archive, Entry, EntryType};
+
+use crate::test::job::JobControl;
+use crate::test::setup_error::SetupError;
+
+// How far the extraction of an archive has got
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExtractProgress {
+    pub archive: String,
+    // compressed bytes read so far, out of the archive size
+    pub bytes: u64,
+    pub total: u64,
+    pub entries: u64,
+    // device nodes left out as only root may create them
+    pub skipped: u64,
+}
+
+impl ExtractProgress {
+    pub fn percent(&self) -> u16 {
+        match self.total {
+            0 => 100,
+            total => (self.bytes * 100 / total).min(100) as u16,
+        }
+    }
+}
+
+// counts what the decoder pulled out of the archive file
+struct CountingReader<R> {
+    inner: R,
+    read: Rc<Cell<u64>>,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.read.set(self.read.get() + read as u64);
+        Ok(read)
+    }
+}
+
+// Unpack a .tbz2 into `target`. With `preserve` (the rootfs) permissions are kept, and when
+// running as root also ownership, xattrs and device nodes, as `tar xpf` under sudo would.
+// `on_progress` is called about once per percent. A cancel stops between two entries.
+// Returns the final progress.
+pub fn extract(archive: &str, target: &str, preserve: bool, control: &JobControl, on_progress: &mut dyn FnMut(&ExtractProgress)) -> Result<ExtractProgress, SetupError> {
+    let io_error = |path: String| move |error| SetupError::Io { path, error };
+
+    let file = File::open(archive).map_err(io_error(archive.to_string()))?;
+    let total = file.metadata().map_err(io_error(archive.to_string()))?.len();
+    let read = Rc::new(Cell::new(0));
+    let reader = CountingReader { inner: file, read: read.clone() };
+
+    // pbzip2 archives are several bzip2 streams back to back
+    let mut tar = Archive::new(MultiBzDecoder::new(reader));
+    let root = preserve && unsafe { libc::geteuid() } == 0;
+    tar.set_preserve_permissions(preserve);
+    tar.set_preserve_ownerships(root);
+    tar.set_unpack_xattrs(root);
+    tar.set_preserve_mtime(true);
+    tar.set_overwrite(true);
+
+    let mut progress = ExtractProgress {
+        archive: Path::new(archive).file_name().unwrap().to_string_lossy().to_string(),
+        bytes: 0,
+        total,
+        entries: 0,
+        skipped: 0,
+    };
+    let mut reported = None;
+
+    for entry in tar.entries().map_err(io_error(archive.to_string()))? {
+        if control.is_cancelled() {
+            return Err(SetupError::Cancelled);
+        }
+        let mut entry = entry.map_err(io_error(archive.to_string()))?;
+        let name = archive.to_string() + ": " + &entry.path().map(|p| p.display().to_string()).unwrap_or_default();
+
+        match entry.header().entry_type() {
+            EntryType::Char | EntryType::Block | EntryType::Fifo => {
+                if !make_node(&entry, target, root).map_err(io_error(name))? {
+                    progress.skipped += 1;
+                }
+            },
+            _ => {
+                entry.unpack_in(target).map_err(io_error(name))?;
+            }
+        }
+
+        progress.entries += 1;
+        progress.bytes = read.get();
+        if reported != Some(progress.percent()) {
+            reported = Some(progress.percent());
+            on_progress(&progress);
+        }
+    }
+
+    progress.bytes = total;
+    on_progress(&progress);
+
+    Ok(progress)
+}
+
+// tar writes device nodes and FIFOs out as empty regular files, so create them here.
+// Returns false for a device node left out.
+fn make_node<R: Read>(entry: &Entry<R>, target: &str, root: bool) -> io::Result<bool> {
+    let header = entry.header();
+    let kind = match header.entry_type() {
+        EntryType::Char => libc::S_IFCHR,
+        EntryType::Block => libc::S_IFBLK,
+        _ => libc::S_IFIFO,
+    };
+    // only root may create devices, an unprivileged extraction leaves them out
+    if kind != libc::S_IFIFO && !root {
+        return Ok(false);
+    }
+
+    let path = contained(target, &entry.path()?)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let _ = fs::remove_file(&path);
+
+    let mode = header.mode()? & 0o7777;
+    let device = libc::makedev(header.device_major()?.unwrap_or(0), header.device_minor()?.unwrap_or(0));
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    if unsafe { libc::mknod(c_path.as_ptr(), kind | mode, device) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // mknod applies the umask
+    fs::set_permissions(&path, fs::Permissions::from_mode(mode))?;
+    if root {
+        std::os::unix::fs::chown(&path, Some(header.uid()? as u32), Some(header.gid()? as u32))?;
+    }
+
+    Ok(true)
+}
+
+// the entry's path under `target`, refusing anything that would land outside it
+fn contained(target: &str, path: &Path) -> io::Result<PathBuf> {
+    let mut contained = PathBuf::from(target);
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => contained.push(part),
+            Component::CurDir | Component::RootDir => {},
+            _ => {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} leaves the target directory", path.display())));
+            }
+        }
+    }
+
+    Ok(contained)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    use bzip2::{write::BzEncoder, Compression};
+    use tar::{Builder, Header};
+
+    struct Workdir(PathBuf);
+
+    impl Workdir {
+        fn new(name: &str) -> Workdir {
+            let dir = std::env::temp_dir().join(format!("extract-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(dir.join("target")).unwrap();
+            Workdir(dir)
+        }
+
+        fn target(&self) -> PathBuf {
+            self.0.join("target")
+        }
+
+        fn target_str(&self) -> String {
+            self.target().to_string_lossy().to_string()
+        }
+    }
+
+    impl Drop for Workdir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    // the name goes into the header as is, tar::Builder refuses `..` and absolute paths
+    fn header(name: &str, kind: EntryType, mode: u32, size: u64) -> Header {
+        let mut header = Header::new_gnu();
+        header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+        header.set_entry_type(kind);
+        header.set_mode(mode);
+        header.set_size(size);
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_mtime(1681922820);
+        // /dev/null
+        let (major, minor) = match kind {
+            EntryType::Char => (1, 3),
+            _ => (0, 0),
+        };
+        header.set_device_major(major).unwrap();
+        header.set_device_minor(minor).unwrap();
+        header.set_cksum();
+        header
+    }
+
+    // a .tbz2 of `entries` as (name, type, mode, content)
+    fn archive(dir: &Workdir, entries: &[(&str, EntryType, u32, &str)]) -> String {
+        let path = dir.0.join("archive.tbz2");
+        let mut builder = Builder::new(BzEncoder::new(File::create(&path).unwrap(), Compression::fast()));
+        for (name, kind, mode, content) in entries {
+            let header = header(name, *kind, *mode, content.len() as u64);
+            builder.append(&header, content.as_bytes()).unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap().flush().unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    fn unpack(dir: &Workdir, archive: &str, preserve: bool) -> Result<ExtractProgress, SetupError> {
+        let mut reports = 0;
+        let extracted = extract(archive, &dir.target_str(), preserve, &JobControl::default(), &mut |_| reports += 1);
+        assert!(extracted.is_err() || reports > 0);
+        extracted
+    }
+
+    #[test]
+    fn unpacks_files_and_directories() {
+        let dir = Workdir::new("files");
+        let archive = archive(&dir, &[
+            ("Linux_for_Tegra/", EntryType::Directory, 0o755, ""),
+            ("Linux_for_Tegra/flash.sh", EntryType::Regular, 0o755, "#!/bin/bash\n"),
+        ]);
+
+        let extracted = unpack(&dir, &archive, false).unwrap();
+        assert_eq!((extracted.entries, extracted.skipped, extracted.percent()), (2, 0, 100));
+        assert_eq!(fs::read_to_string(dir.target().join("Linux_for_Tegra/flash.sh")).unwrap(), "#!/bin/bash\n");
+    }
+
+    #[test]
+    fn preserve_keeps_the_permissions() {
+        let entries = [("usr/bin/sudo", EntryType::Regular, 0o4755, "")];
+        let mode = |preserve| {
+            let dir = Workdir::new(&format!("preserve-{}", preserve));
+            let archive = archive(&dir, &entries);
+            unpack(&dir, &archive, preserve).unwrap();
+            fs::metadata(dir.target().join("usr/bin/sudo")).unwrap().permissions().mode() & 0o7777
+        };
+
+        assert_eq!(mode(true), 0o4755);
+        assert_eq!(mode(false) & 0o4000, 0);
+    }
+
+    #[test]
+    fn creates_fifos_and_leaves_devices_to_root() {
+        let dir = Workdir::new("nodes");
+        let archive = archive(&dir, &[
+            ("run/initctl", EntryType::Fifo, 0o600, ""),
+            ("dev/null", EntryType::Char, 0o666, ""),
+        ]);
+
+        // without preserve, as for the BSP, never as root
+        let extracted = unpack(&dir, &archive, false).unwrap();
+        assert_eq!(extracted.skipped, 1);
+        assert!(!dir.target().join("dev/null").exists());
+        let fifo = fs::symlink_metadata(dir.target().join("run/initctl")).unwrap();
+        assert!(std::os::unix::fs::FileTypeExt::is_fifo(&fifo.file_type()));
+        assert_eq!(fifo.permissions().mode() & 0o7777, 0o600);
+    }
+
+    #[test]
+    fn keeps_entries_inside_the_target() {
+        let dir = Workdir::new("escape");
+        let archive = archive(&dir, &[
+            ("../escaped", EntryType::Regular, 0o644, "outside"),
+            ("/etc/hostname", EntryType::Regular, 0o644, "jetson"),
+        ]);
+
+        unpack(&dir, &archive, false).unwrap();
+        assert!(!dir.0.join("escaped").exists());
+        assert_eq!(fs::read_to_string(dir.target().join("etc/hostname")).unwrap(), "jetson");
+
+        // a node is placed by `contained`
+        let archive = self::archive(&dir, &[("../escaped-fifo", EntryType::Fifo, 0o600, "")]);
+        assert!(matches!(unpack(&dir, &archive, false), Err(SetupError::Io { .. })));
+        assert!(!dir.0.join("escaped-fifo").exists());
+    }
+
+    #[test]
+    fn contained_refuses_parent_directories() {
+        assert_eq!(contained("/ws/rootfs", Path::new("dev/null")).unwrap(), PathBuf::from("/ws/rootfs/dev/null"));
+        assert_eq!(contained("/ws/rootfs", Path::new("./dev/./null")).unwrap(), PathBuf::from("/ws/rootfs/dev/null"));
+        // absolute entries are relative to the target, as with tar
+        assert_eq!(contained("/ws/rootfs", Path::new("/dev/null")).unwrap(), PathBuf::from("/ws/rootfs/dev/null"));
+
+        assert!(contained("/ws/rootfs", Path::new("../dev/null")).is_err());
+        assert!(contained("/ws/rootfs", Path::new("dev/../../null")).is_err());
+    }
+}
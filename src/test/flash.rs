@@ -1,55 +1,203 @@
 use std::{
-    process::{Command, Stdio},
-    thread,
+    collections::HashMap,
+    fmt,
+    io,
+    path::Path,
+    process::Stdio,
+    sync::mpsc::Sender,
 };
 
+use tokio::process::Command;
+
 use crate::{
     app::App,
-    jetson::{JetsonModuleType, FlashStatus}
+    jetson::{FlashStatus, Jetson, Signal, Stage},
+    module_catalog::Module,
+    test::{flash_output::monitor, job::{Job, JobControl}, release::Release},
 };
 
 const FLASH_SCRIPT: &str = "./tools/kernel_flash/l4t_initrd_flash.sh";
 
-pub fn flash_device(app: &mut App, is_for_test: bool) -> Result<(), ()> {
-    let index = app.selected_device_index().unwrap();
+#[derive(Debug)]
+pub enum FlashError {
+    NoRelease,
+    NoDevice,
+    // another flash holds the USB ports
+    Busy(String),
+    Unsupported(String),
+    // the module cannot take this release or this package
+    Incompatible(String),
+    NotInRecovery(String),
+    // enumerated below USB high speed
+    SlowLink { device: String, speed: String },
+    Spawn(io::Error),
+}
+
+impl fmt::Display for FlashError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FlashError::NoRelease => write!(f, "no release descriptor found in releases/"),
+            FlashError::NoDevice => write!(f, "no device selected"),
+            FlashError::Busy(reason) => write!(f, "{}", reason),
+            FlashError::Unsupported(module) => write!(f, "{} is not in the module catalog", module),
+            FlashError::Incompatible(reason) => write!(f, "{}", reason),
+            FlashError::NotInRecovery(reason) => write!(f, "{}", reason),
+            FlashError::SlowLink { device, speed } => write!(f, "{} is connected at {} Mbit/s, below USB high speed; check its cable and port", device, speed),
+            FlashError::Spawn(error) => write!(f, "cannot run {}: {}", FLASH_SCRIPT, error),
+        }
+    }
+}
+
+impl std::error::Error for FlashError {}
+
+// The catalog entry of the device, once it is sure `release` has what the module needs
+fn flashable<'a>(jetson: &'a Jetson, release: &Release, l4t: &str) -> Result<&'a Module, FlashError> {
+    let module = jetson.module.as_ref().ok_or_else(|| FlashError::Unsupported(jetson.to_string()))?;
+    if jetson.is_slow_link() {
+        return Err(FlashError::SlowLink { device: format!("{} on port {}", module.name, jetson.instance_number), speed: jetson.speed.clone() });
+    }
+    if !module.supports(release) {
+        return Err(FlashError::Incompatible(format!("{} is not supported by Jetson Linux {}", module.name, release.version)));
+    }
+    if !Path::new(&(l4t.to_string() + "/kernel/dtb/" + &module.dtb)).exists() {
+        return Err(FlashError::Incompatible(format!("{} has no {} for {}", release.version, module.dtb, module.name)));
+    }
+
+    Ok(module)
+}
+
+// A board behind a USB 2.0 hub can still be flashed, but tell which one to move if it fails
+fn warn_link(jetson: &Jetson, console: &Sender<String>) {
+    let hubs: Vec<&str> = jetson.usb2_hubs().iter().map(|hub| hub.port.as_str()).collect();
+    if hubs.is_empty() {
+        return;
+    }
+    let line = format!("Warning: {} on port {} is behind the USB 2.0 hub {}, plug it into a USB 3 port if flashing fails\n", jetson.module_name, jetson.instance_number, hubs.join(", "));
+    console.send(line).unwrap();
+}
+
+// Flash every connected device at once with the massflash package. The package is built for
+// the release's board, so every connected module has to use that board.
+pub fn flash_device(app: &mut App, is_for_test: bool) -> Result<(), FlashError> {
+    let release = app.current_release().cloned().ok_or(FlashError::NoRelease)?;
+    let l4t = release.l4t(".", is_for_test);
+    if app.has_flashing_device() {
+        return Err(FlashError::Busy(String::from("a single device is being flashed, wait for it to finish")));
+    }
+    let index = app.selected_device_index().ok_or(FlashError::NoDevice)?;
     let jetson = &mut app.devlist[index];
-    let tx = jetson.create_new_publisher();
 
     if jetson.is_flashed() || jetson.is_flashing() {
         // TODO send flash skipped signal
         return Ok(());
     }
-    
-    match jetson.module_type {
-        JetsonModuleType::OrinNX16GB => {
-            app.flash_status = FlashStatus::Flashing;
-            app.flash_handle = Some(thread::spawn(move || {
-                let mut child = Command::new(FLASH_SCRIPT)
-                                            .current_dir(match is_for_test { true => "./test/Linux_for_Tegra", false => "./release/Linux_for_Tegra"})
-                                            .args([
-                                                "--flash-only",
-                                                "--network",
-                                                "usb0",
-                                                "--massflash",
-                                                "--showlogs",
-                                            ])
-                                            .stdout(Stdio::piped())
-                                            .stderr(Stdio::null())
-                                            .spawn()
-                                            .unwrap();
-                // let stdout = child.stdout.take().unwrap();
-                // let reader = BufReader::new(stdout);
-
-                // reader
-                //     .lines()
-                //     .filter_map(|line| line.ok())
-                //     .for_each(|line| { tx.send(line + "\n").unwrap(); });
-
-                child.wait().unwrap();
-            }));
-        },
-        _ => { }
+
+    // massflash takes every device in recovery mode, a module needing another board must not be around
+    let recovery: Vec<&Jetson> = app.devlist.iter().filter(|jetson| jetson.stage == Stage::Recovery).collect();
+    if recovery.is_empty() {
+        return Err(FlashError::NotInRecovery(String::from("no board is in recovery mode")));
+    }
+    for jetson in recovery {
+        let module = flashable(jetson, &release, &l4t)?;
+        if module.board != release.board {
+            return Err(FlashError::Incompatible(format!(
+                "{} on port {} needs {}, the massflash package is for {}; flash it on its own or unplug it",
+                module.name, jetson.instance_number, module.board, release.board
+            )));
+        }
     }
 
+    let control = JobControl::default();
+    let child = control.spawn(Command::new(FLASH_SCRIPT)
+                        .current_dir(&l4t)
+                        .args([
+                            "--flash-only",
+                            "--network",
+                            "usb0",
+                            "--massflash",
+                            "--showlogs",
+                        ])
+                        .stdout(Stdio::piped())
+                        .stderr(Stdio::piped()))
+                        .map_err(FlashError::Spawn)?;
+
+    // every board in recovery mode takes part in a massflash, each log goes to its own device;
+    // the others keep their state and their logs
+    let mut devices = HashMap::new();
+    for jetson in app.devlist.iter_mut().filter(|jetson| jetson.stage == Stage::Recovery) {
+        jetson.clear_logger_buffer();
+        devices.insert(jetson.instance_number.clone(), jetson.create_new_publisher());
+    }
+    let console = app.main_terminal.create_new_publisher();
+    for jetson in app.devlist.iter().filter(|jetson| jetson.stage == Stage::Recovery) {
+        warn_link(jetson, &console);
+    }
+    let tx = app.create_new_publisher();
+
+    app.flash_status = FlashStatus::Flashing;
+    app.flash_job = Some(Job::spawn(control, move |control| async move {
+        let signal = match monitor(child, &l4t, devices, None, console, tx.clone(), &control).await {
+            FlashStatus::Finished => Signal::FlashSuccess,
+            FlashStatus::Cancelled => Signal::FlashCancelled,
+            _ => Signal::FlashFail,
+        };
+        tx.send(signal).await;
+    }));
+
+    Ok(())
+}
+
+// Flash only the selected device, through its USB port, while the others keep their state.
+// A module of the release's board reuses the massflash package, --usb-instance restricting it to
+// the one port. Any other module is flashed from its own catalog entry, which builds its images
+// in Linux_for_Tegra first, so nothing else may be flashing meanwhile.
+pub fn flash_single_device(app: &mut App, is_for_test: bool) -> Result<(), FlashError> {
+    let release = app.current_release().cloned().ok_or(FlashError::NoRelease)?;
+    let l4t = release.l4t(".", is_for_test);
+    if app.flash_status == FlashStatus::Flashing {
+        return Err(FlashError::Busy(String::from("massflash is running on all ports")));
+    }
+    let index = app.selected_device_index().ok_or(FlashError::NoDevice)?;
+    let tx = app.create_new_publisher();
+    let console = app.main_terminal.create_new_publisher();
+    let busy = app.has_flashing_device();
+    let jetson = &mut app.devlist[index];
+
+    if jetson.is_flashing() {
+        return Err(FlashError::Busy(jetson.to_string() + " is already being flashed"));
+    }
+    if jetson.stage != Stage::Recovery {
+        return Err(FlashError::NotInRecovery(format!("{} on port {} is not in recovery mode ({})", jetson.module_name, jetson.instance_number, jetson.stage)));
+    }
+    let module = flashable(jetson, &release, &l4t)?;
+
+    let mut args = vec![String::from("--usb-instance"), jetson.instance_number.clone(), String::from("--network"), String::from("usb0"), String::from("--showlogs")];
+    if module.board == release.board {
+        args.extend([String::from("--flash-only"), String::from("--massflash")]);
+    } else if busy {
+        return Err(FlashError::Busy(format!("{} needs its own images, wait for the other devices to finish", module.name)));
+    } else {
+        args.extend(module.flash_args());
+    }
+
+    let control = JobControl::default();
+    let child = control.spawn(Command::new(FLASH_SCRIPT)
+                        .current_dir(&l4t)
+                        .args(&args)
+                        .stdout(Stdio::piped())
+                        .stderr(Stdio::piped()))
+                        .map_err(FlashError::Spawn)?;
+
+    warn_link(jetson, &console);
+    let log = jetson.create_new_publisher();
+    let devices = HashMap::from([(jetson.instance_number.clone(), log.clone())]);
+    let single = Some(jetson.instance_number.clone());
+
+    jetson.clear_logger_buffer();
+    jetson.set_flashing();
+    jetson.flash_job = Some(Job::spawn(control, move |control| async move {
+        monitor(child, &l4t, devices, single, log, tx, &control).await;
+    }));
+
     Ok(())
-}
\ No newline at end of file
+}
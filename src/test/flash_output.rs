@@ -0,0 +1,337 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::Sender,
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use regex::Regex;
+use tokio::{
+    fs::File,
+    io::{AsyncBufReadExt, BufReader},
+    process::Child,
+};
+
+use crate::event::Publisher;
+use crate::jetson::{FlashStatus, Signal};
+use crate::test::job::JobControl;
+
+// What l4t_initrd_flash.sh says about a device on its own output
+#[derive(Debug, Clone, PartialEq)]
+pub enum FlashEvent {
+    // a flash process was started for the device on this USB port
+    Started { instance: String },
+    // where that process writes its log, as printed by the script
+    LogFile { instance: String, path: String },
+}
+
+// The patterns follow the r35 massflash scripts:
+//   Start flashing device: 1-3, rcm instance: 0, PID: 4242
+//   Log will be saved to Linux_for_Tegra/initrdlog/flash_1-3_0_20230419-164700.log
+// and the per-device log ends with "Flash is successful" or "Flash failure". Early in the log
+// tegrarcm_v2 prints the chip's ECID as it opens the RCM session:
+//   [   0.0146 ] BR_CID: 0x80012344705DD2C40400000012028180
+pub struct FlashOutput {
+    started: Regex,
+    log_file: Regex,
+    success: Regex,
+    failure: Regex,
+    ecid: Regex,
+}
+
+impl Default for FlashOutput {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FlashOutput {
+    pub fn new() -> FlashOutput {
+        FlashOutput {
+            started: Regex::new(r"Start flashing device: ([\d]+-[\d.]+)").unwrap(),
+            log_file: Regex::new(r"Log (?:will be|is) saved to (\S*flash_([\d]+-[\d.]+)_\d+_\S*\.log)").unwrap(),
+            success: Regex::new(r"^Flash is successful").unwrap(),
+            failure: Regex::new(r"^(Flash failure|Failed flashing)").unwrap(),
+            ecid: Regex::new(r"BR_CID:\s*(0x[0-9A-Fa-f]+)").unwrap(),
+        }
+    }
+
+    pub fn parse(&self, line: &str) -> Option<FlashEvent> {
+        if let Some(capture) = self.started.captures(line) {
+            return Some(FlashEvent::Started { instance: capture[1].to_string() });
+        }
+        if let Some(capture) = self.log_file.captures(line) {
+            return Some(FlashEvent::LogFile { instance: capture[2].to_string(), path: capture[1].to_string() });
+        }
+        None
+    }
+
+    pub fn ecid(&self, line: &str) -> Option<String> {
+        self.ecid.captures(line).map(|capture| capture[1].to_string())
+    }
+
+    // the outcome a line of a per-device log announces, if any
+    pub fn verdict(&self, line: &str) -> Option<FlashStatus> {
+        let line = line.trim();
+        if self.success.is_match(line) {
+            Some(FlashStatus::Finished)
+        } else if self.failure.is_match(line) {
+            Some(FlashStatus::Failed)
+        } else {
+            None
+        }
+    }
+}
+
+// Follow a running flash: the script's stdout goes to `console`, each device's log file to
+// that device's logger in `devices` (by USB instance). Every state change is sent on `tx`
+// as Signal::DeviceFlashStatus, the ECID of each chip as Signal::DeviceEcid, read from the
+// device's own log, or from stdout when the script flashes only the `single` instance.
+// Once the script exits its exit code has the last word:
+// success means every device is flashed, a failure fails those that did not report success,
+// or cancels them when the job was cancelled. Returns the outcome of the whole run.
+pub async fn monitor(mut child: Child, l4t: &str, devices: HashMap<String, Sender<String>>, single: Option<String>, console: Sender<String>, tx: Publisher, control: &JobControl) -> FlashStatus {
+    let parser = Arc::new(FlashOutput::new());
+    let verdicts = Arc::new(Mutex::new(HashMap::<String, FlashStatus>::new()));
+    let done = Arc::new(AtomicBool::new(false));
+    let mut tails = vec![];
+
+    // stderr would otherwise block the script once the pipe is full
+    let stderr = child.stderr.take().map(|stderr| {
+        let console = console.clone();
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(stderr).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                let _ = console.send(line + "\n");
+            }
+        })
+    });
+
+    if let Some(stdout) = child.stdout.take() {
+        let mut lines = BufReader::new(stdout).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            match parser.parse(&line) {
+                Some(FlashEvent::Started { instance }) if devices.contains_key(&instance) => {
+                    tx.send(Signal::DeviceFlashStatus { instance, status: FlashStatus::Flashing }).await;
+                },
+                Some(FlashEvent::LogFile { instance, path }) => {
+                    if let Some(log) = devices.get(&instance) {
+                        let path = log_path(l4t, &path);
+                        let (log, tx, parser, verdicts, done) = (log.clone(), tx.clone(), parser.clone(), verdicts.clone(), done.clone());
+                        tails.push(tokio::spawn(async move {
+                            let mut tail = Tail::new(path, done);
+                            while let Some(line) = tail.next_line().await {
+                                if let Some(ecid) = parser.ecid(&line) {
+                                    tx.send(Signal::DeviceEcid { instance: instance.clone(), ecid }).await;
+                                }
+                                if let Some(status) = parser.verdict(&line) {
+                                    verdicts.lock().unwrap().insert(instance.clone(), status);
+                                    tx.send(Signal::DeviceFlashStatus { instance: instance.clone(), status }).await;
+                                }
+                                let _ = log.send(line);
+                            }
+                        }));
+                    }
+                },
+                _ => {
+                    // without --massflash the script logs to stdout only
+                    if let (Some(ecid), Some(instance)) = (parser.ecid(&line), &single) {
+                        tx.send(Signal::DeviceEcid { instance: instance.clone(), ecid }).await;
+                    }
+                }
+            }
+            let _ = console.send(line + "\n");
+        }
+    }
+
+    let success = matches!(control.wait(&mut child).await, Ok(status) if status.success());
+    let outcome = match (success, control.is_cancelled()) {
+        (true, _) => FlashStatus::Finished,
+        (false, true) => FlashStatus::Cancelled,
+        (false, false) => FlashStatus::Failed,
+    };
+    done.store(true, Ordering::Relaxed);
+    for tail in tails {
+        let _ = tail.await;
+    }
+    if let Some(stderr) = stderr {
+        let _ = stderr.await;
+    }
+
+    let verdicts = verdicts.lock().unwrap().clone();
+    for instance in devices.keys() {
+        let reported = verdicts.get(instance).copied();
+        let status = match reported {
+            Some(FlashStatus::Finished) => FlashStatus::Finished,
+            _ => outcome,
+        };
+        if reported != Some(status) {
+            tx.send(Signal::DeviceFlashStatus { instance: instance.clone(), status }).await;
+        }
+    }
+
+    outcome
+}
+
+// the script prints the log path relative to the directory holding Linux_for_Tegra
+fn log_path(l4t: &str, printed: &str) -> PathBuf {
+    let printed = Path::new(printed);
+    if printed.is_absolute() {
+        return printed.to_path_buf();
+    }
+    match printed.strip_prefix("Linux_for_Tegra") {
+        Ok(inside) => Path::new(l4t).join(inside),
+        Err(_) => Path::new(l4t).join(printed),
+    }
+}
+
+// `tail -f` of a log file the script may not have created yet, ending once `done` is set
+// and everything written was read
+struct Tail {
+    path: PathBuf,
+    done: Arc<AtomicBool>,
+    reader: Option<BufReader<File>>,
+    line: String,
+}
+
+impl Tail {
+    fn new(path: PathBuf, done: Arc<AtomicBool>) -> Tail {
+        Tail { path, done, reader: None, line: String::new() }
+    }
+
+    async fn next_line(&mut self) -> Option<String> {
+        loop {
+            // checked before reading so the last read after the script exits sees everything
+            let finished = self.done.load(Ordering::Relaxed);
+            if self.reader.is_none() {
+                match File::open(&self.path).await {
+                    Ok(file) => self.reader = Some(BufReader::new(file)),
+                    Err(_) if !finished => {
+                        tokio::time::sleep(Duration::from_millis(200)).await;
+                        continue;
+                    },
+                    Err(_) => return None,
+                }
+            }
+
+            let reader = self.reader.as_mut().unwrap();
+            match reader.read_line(&mut self.line).await {
+                Ok(0) | Err(_) => {
+                    if finished {
+                        // a last line without its newline
+                        return match self.line.is_empty() {
+                            true => None,
+                            false => Some(std::mem::take(&mut self.line) + "\n"),
+                        };
+                    }
+                    tokio::time::sleep(Duration::from_millis(200)).await;
+                },
+                Ok(_) if self.line.ends_with('\n') => {
+                    return Some(std::mem::take(&mut self.line));
+                },
+                // a line still being written
+                Ok(_) => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Stdio;
+    use std::sync::mpsc;
+
+    use tokio::process::Command;
+
+    use crate::event::event_bus;
+
+    #[test]
+    fn parses_massflash_output() {
+        let parser = FlashOutput::new();
+
+        assert_eq!(parser.parse("Start flashing device: 1-3, rcm instance: 0, PID: 4242"), Some(FlashEvent::Started { instance: String::from("1-3") }));
+        assert_eq!(parser.parse("Start flashing device: 1-2.4, rcm instance: 1, PID: 4243"), Some(FlashEvent::Started { instance: String::from("1-2.4") }));
+        assert_eq!(parser.parse("Log will be saved to Linux_for_Tegra/initrdlog/flash_1-3_0_20230419-164700.log"), Some(FlashEvent::LogFile {
+            instance: String::from("1-3"),
+            path: String::from("Linux_for_Tegra/initrdlog/flash_1-3_0_20230419-164700.log"),
+        }));
+        assert_eq!(parser.parse("Waiting for target to boot-up..."), None);
+
+        assert_eq!(parser.ecid("[   0.0146 ] BR_CID: 0x80012344705DD2C40400000012028180"), Some(String::from("0x80012344705DD2C40400000012028180")));
+        assert_eq!(parser.ecid("[   0.0146 ] Boot Rom communication completed"), None);
+    }
+
+    #[test]
+    fn reads_the_verdict_of_a_device_log() {
+        let parser = FlashOutput::new();
+
+        assert_eq!(parser.verdict("Flash is successful\n"), Some(FlashStatus::Finished));
+        assert_eq!(parser.verdict("Flash failure\n"), Some(FlashStatus::Failed));
+        assert_eq!(parser.verdict("Failed flashing t186ref.\n"), Some(FlashStatus::Failed));
+        assert_eq!(parser.verdict("Writing partition APP"), None);
+    }
+
+    #[test]
+    fn finds_the_log_inside_linux_for_tegra() {
+        assert_eq!(log_path("/ws/r35.3.1/test/Linux_for_Tegra", "Linux_for_Tegra/initrdlog/flash_1-3_0_x.log"), PathBuf::from("/ws/r35.3.1/test/Linux_for_Tegra/initrdlog/flash_1-3_0_x.log"));
+        assert_eq!(log_path("/ws/r35.3.1/test/Linux_for_Tegra", "/tmp/flash_1-3_0_x.log"), PathBuf::from("/tmp/flash_1-3_0_x.log"));
+    }
+
+    // Run a stand-in for l4t_initrd_flash.sh flashing 1-3 and 1-4, 1-3 logging `log` before the
+    // script exits with `exit_code`. Returns the outcome and the last status of each device.
+    async fn flash(name: &str, log: &str, exit_code: i32) -> (FlashStatus, HashMap<String, FlashStatus>) {
+        let dir = std::env::temp_dir().join(format!("flash-output-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let log_file = dir.join("flash_1-3_0_20230419-164700.log");
+        std::fs::write(&log_file, log).unwrap();
+
+        let script = format!(
+            "echo 'Start flashing device: 1-3, rcm instance: 0, PID: 4242'; \
+             echo 'Start flashing device: 1-4, rcm instance: 1, PID: 4243'; \
+             echo 'Log will be saved to {}'; \
+             exit {}",
+            log_file.display(), exit_code);
+        let control = JobControl::default();
+        let child = control.spawn(Command::new("sh").args(["-c", &script]).stdout(Stdio::piped()).stderr(Stdio::piped())).unwrap();
+
+        let (console, _console) = mpsc::channel();
+        let devices = HashMap::from([
+            (String::from("1-3"), mpsc::channel().0),
+            (String::from("1-4"), mpsc::channel().0),
+        ]);
+        let (tx, mut rx) = event_bus();
+        let outcome = monitor(child, dir.to_str().unwrap(), devices, None, console, tx, &control).await;
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let mut statuses = HashMap::new();
+        while let Ok(signal) = rx.try_recv() {
+            if let Signal::DeviceFlashStatus { instance, status } = signal {
+                statuses.insert(instance, status);
+            }
+        }
+        (outcome, statuses)
+    }
+
+    #[tokio::test]
+    async fn a_failed_script_keeps_the_successes_its_logs_report() {
+        let (outcome, statuses) = flash("failed", "[   0.0146 ] BR_CID: 0x80012344705DD2C40400000012028180\nFlash is successful\n", 1).await;
+
+        assert_eq!(outcome, FlashStatus::Failed);
+        assert_eq!(statuses["1-3"], FlashStatus::Finished);
+        assert_eq!(statuses["1-4"], FlashStatus::Failed);
+    }
+
+    #[tokio::test]
+    async fn a_successful_script_flashed_every_device() {
+        let (outcome, statuses) = flash("success", "Flash failure\n", 0).await;
+
+        assert_eq!(outcome, FlashStatus::Finished);
+        assert_eq!(statuses["1-3"], FlashStatus::Finished);
+        assert_eq!(statuses["1-4"], FlashStatus::Finished);
+    }
+}
@@ -0,0 +1,134 @@
+use std::{
+    future::Future,
+    io,
+    process::ExitStatus,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use tokio::{
+    process::{Child, Command},
+    task::JoinHandle,
+};
+
+// Shared between a job's task and the UI: whether the job was cancelled, and the process
+// groups of the commands it is running
+#[derive(Clone, Default)]
+pub struct JobControl {
+    cancelled: Arc<AtomicBool>,
+    groups: Arc<Mutex<Vec<u32>>>,
+}
+
+impl JobControl {
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    // Start `command` in a process group of its own, so that cancelling reaches everything it
+    // starts (tar under a script, the per-device processes of a massflash...). Ctrl-C in the
+    // terminal no longer reaches the group, the job has to be cancelled instead.
+    pub fn spawn(&self, command: &mut Command) -> io::Result<Child> {
+        if self.is_cancelled() {
+            return Err(io::Error::new(io::ErrorKind::Interrupted, "cancelled"));
+        }
+        unsafe {
+            command.pre_exec(|| match libc::setpgid(0, 0) {
+                0 => Ok(()),
+                _ => Err(io::Error::last_os_error()),
+            });
+        }
+        let child = command.kill_on_drop(true).spawn()?;
+        let group = child.id().unwrap_or_default();
+        self.groups.lock().unwrap().push(group);
+
+        // cancelled while the command was starting
+        if self.is_cancelled() {
+            signal_group(group, libc::SIGTERM);
+        }
+        Ok(child)
+    }
+
+    // Wait for a command started by spawn, then forget its group as the id may be reused
+    pub async fn wait(&self, child: &mut Child) -> io::Result<ExitStatus> {
+        let group = child.id().unwrap_or_default();
+        let status = child.wait().await;
+        self.groups.lock().unwrap().retain(|g| *g != group);
+        status
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+        self.signal(libc::SIGTERM);
+    }
+
+    fn signal(&self, signal: libc::c_int) {
+        for group in self.groups.lock().unwrap().iter() {
+            signal_group(*group, signal);
+        }
+    }
+}
+
+fn signal_group(group: u32, signal: libc::c_int) {
+    if group == 0 {
+        return;
+    }
+    unsafe {
+        libc::killpg(group as libc::pid_t, signal);
+    }
+}
+
+// A task on the runtime together with the processes it runs
+pub struct Job {
+    handle: JoinHandle<()>,
+    control: JobControl,
+}
+
+impl Job {
+    pub fn spawn<F, Fut>(control: JobControl, f: F) -> Job
+    where
+        F: FnOnce(JobControl) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let handle = tokio::spawn(f(control.clone()));
+        Job { handle, control }
+    }
+
+    pub fn control(&self) -> &JobControl {
+        &self.control
+    }
+
+    pub fn cancel(&self) {
+        self.control.cancel();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.control.is_cancelled()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.handle.is_finished()
+    }
+
+    // Used on quit: cancel, give the processes `grace` to clean up, then kill what is left
+    pub async fn terminate(mut self, grace: Duration) {
+        self.cancel();
+        let _ = tokio::time::timeout(grace, &mut self.handle).await;
+        self.kill();
+    }
+
+    // the processes and the task, without waiting for them
+    pub fn kill(&self) {
+        self.control.signal(libc::SIGKILL);
+        self.handle.abort();
+    }
+}
+
+// Clear the job in `slot` once its task has ended, passing a panic in it on
+pub async fn reap(slot: &mut Option<Job>) {
+    if slot.as_ref().is_some_and(|job| job.is_finished()) {
+        slot.take().unwrap().handle.await.unwrap();
+    }
+}
@@ -1,2 +1,11 @@
+pub mod artifact_cache;
+pub mod checksum;
 pub mod env_setup;
-pub mod flash;
\ No newline at end of file
+pub mod extract;
+pub mod flash;
+pub mod flash_output;
+pub mod job;
+pub mod release;
+pub mod setup_error;
+pub mod setup_progress;
+pub mod setup_state;
\ No newline at end of file
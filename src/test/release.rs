@@ -0,0 +1,149 @@
+use std::collections::HashMap;
+use std::fs;
+
+use serde::Deserialize;
+
+use crate::test::checksum::Manifest;
+use crate::test::setup_error::SetupError;
+
+// one descriptor per Jetson Linux release, e.g. releases/r35.3.1.toml
+pub const RELEASES: &str = "releases";
+
+// Everything that differs between Jetson Linux releases. The workspace keeps one directory per
+// release, <workspace>/<version>/{test,release}/Linux_for_Tegra.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Release {
+    pub version: String,
+    // board config (<board>.conf) the massflash packages are generated for
+    pub board: String,
+    #[serde(rename = "artifact")]
+    pub artifacts: Vec<Artifact>,
+    #[serde(default, rename = "device-tree")]
+    pub device_trees: Vec<DeviceTree>,
+    #[serde(skip)]
+    path: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Artifact {
+    pub name: String,
+    pub url: String,
+    pub sha256: Option<String>,
+    pub extract: Extract,
+}
+
+// where an archive is unpacked inside test/ and release/
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Extract {
+    // next to Linux_for_Tegra, the BSP and the sources archives
+    L4t,
+    // into Linux_for_Tegra/rootfs, keeping permissions
+    Rootfs,
+}
+
+// a DTB of the release and the patch file (relative to the workspace) applied to it
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct DeviceTree {
+    pub dtb: String,
+    pub patch: String,
+}
+
+impl Release {
+    pub fn load(path: &str) -> Result<Release, SetupError> {
+        let content = fs::read_to_string(path).map_err(|error| SetupError::Io { path: path.to_string(), error })?;
+        let mut release: Release = toml::from_str(&content).map_err(|e| SetupError::Config { path: path.to_string(), reason: e.message().to_string() })?;
+        release.path = path.to_string();
+        // fail on a bad digest now rather than after the download
+        release.manifest()?;
+
+        Ok(release)
+    }
+
+    // every descriptor in <workspace>/releases, oldest first
+    pub fn load_all(workspace: &str) -> Result<Vec<Release>, SetupError> {
+        let dir = workspace.to_string() + "/" + RELEASES;
+        let entries = fs::read_dir(&dir).map_err(|error| SetupError::Io { path: dir.clone(), error })?;
+
+        let mut releases = vec![];
+        for entry in entries {
+            let path = entry.map_err(|error| SetupError::Io { path: dir.clone(), error })?.path();
+            if path.extension().map(|e| e == "toml").unwrap_or(false) {
+                releases.push(Release::load(&path.to_string_lossy())?);
+            }
+        }
+        releases.sort_by_key(|release| version_key(&release.version));
+
+        Ok(releases)
+    }
+
+    pub fn manifest(&self) -> Result<Manifest, SetupError> {
+        let sha256: HashMap<String, String> = self.artifacts.iter()
+                                                .filter_map(|a| a.sha256.clone().map(|digest| (a.name.clone(), digest)))
+                                                .collect();
+        Manifest::new(&self.path, sha256)
+    }
+
+    pub fn dir(&self, workspace: &str) -> String {
+        workspace.to_string() + "/" + &self.version
+    }
+
+    // Linux_for_Tegra of the test or the release environment
+    pub fn l4t(&self, workspace: &str, is_for_test: bool) -> String {
+        let environment = match is_for_test {
+            true => "test",
+            false => "release",
+        };
+        self.dir(workspace) + "/" + environment + "/Linux_for_Tegra"
+    }
+}
+
+// "r35.3.1" -> [35, 3, 1], so r35.10 sorts after r35.9
+fn version_key(version: &str) -> Vec<u64> {
+    version
+        .split(|c: char| !c.is_ascii_digit())
+        .filter_map(|part| part.parse().ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devicetree::patch::DtbPatch;
+
+    // setup refuses an archive without a digest, so a descriptor missing one cannot be installed
+    #[test]
+    fn every_artifact_has_a_digest() {
+        let releases = Release::load_all(env!("CARGO_MANIFEST_DIR")).unwrap();
+        assert!(!releases.is_empty());
+
+        for release in releases {
+            let manifest = release.manifest().unwrap();
+            let missing: Vec<&str> = release.artifacts.iter()
+                                        .filter(|artifact| manifest.expected(&artifact.name).is_err())
+                                        .map(|artifact| &artifact.name[..])
+                                        .collect();
+            assert!(missing.is_empty(), "{}: no sha256 for {:?}", release.version, missing);
+        }
+    }
+
+    // overlays land side by side in kernel/dtb, a shared name would keep only one of them
+    #[test]
+    fn every_overlay_has_its_own_name() {
+        let workspace = env!("CARGO_MANIFEST_DIR");
+        for release in Release::load_all(workspace).unwrap() {
+            let mut names = HashMap::new();
+            for device_tree in &release.device_trees {
+                let patch = DtbPatch::load(&(workspace.to_string() + "/" + &device_tree.patch)).unwrap();
+                if let Some(overlay) = patch.overlay {
+                    if let Some(other) = names.insert(overlay.name.clone(), device_tree.patch.clone()) {
+                        panic!("{}: {} and {} both write {}", release.version, other, device_tree.patch, overlay.name);
+                    }
+                }
+            }
+        }
+    }
+}
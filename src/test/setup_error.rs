@@ -0,0 +1,91 @@
+use std::fmt;
+use std::io;
+use std::process::ExitStatus;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SetupStep {
+    Download,
+    PatchDeviceTree,
+    ApplyBinaries,
+    CreateDefaultUser,
+    InstallTestClient,
+    GenerateTestPackage,
+    GenerateReleasePackage,
+}
+
+impl SetupStep {
+    pub const ALL: [SetupStep; 7] = [
+        SetupStep::Download,
+        SetupStep::PatchDeviceTree,
+        SetupStep::ApplyBinaries,
+        SetupStep::CreateDefaultUser,
+        SetupStep::InstallTestClient,
+        SetupStep::GenerateTestPackage,
+        SetupStep::GenerateReleasePackage,
+    ];
+
+    // 1-based position shown as [n/7]
+    pub fn number(&self) -> usize {
+        Self::ALL.iter().position(|step| step == self).unwrap() + 1
+    }
+}
+
+impl fmt::Display for SetupStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            SetupStep::Download => "Download Jetson Linux",
+            SetupStep::PatchDeviceTree => "Patch device tree",
+            SetupStep::ApplyBinaries => "Apply binaries",
+            SetupStep::CreateDefaultUser => "Create default user",
+            SetupStep::InstallTestClient => "Install startup programs",
+            SetupStep::GenerateTestPackage => "Generate massflash package for test environment",
+            SetupStep::GenerateReleasePackage => "Generate massflash package for release environment",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug)]
+pub enum SetupError {
+    // the command could not be started
+    Spawn { command: String, error: io::Error },
+    // the command ran and exited with a failure
+    Failed { command: String, status: ExitStatus },
+    Io { path: String, error: io::Error },
+    DeviceTree { dtb: String, reason: String },
+    Config { path: String, reason: String },
+    // the checksum manifest is missing, malformed or has no entry for an archive
+    Manifest { path: String, reason: String },
+    // a downloaded archive is not the one the manifest lists
+    Checksum { file: String, expected: String, actual: String },
+    // blocking work panicked or was dropped with the runtime
+    Task { reason: String },
+    // stopped from the UI
+    Cancelled,
+}
+
+impl fmt::Display for SetupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SetupError::Spawn { command, error } => write!(f, "cannot run {}: {}", command, error),
+            SetupError::Failed { command, status } => match status.code() {
+                Some(code) => write!(f, "{} exited with code {}", command, code),
+                None => write!(f, "{} was terminated by a signal", command),
+            },
+            SetupError::Io { path, error } => write!(f, "{}: {}", path, error),
+            SetupError::DeviceTree { dtb, reason } => write!(f, "{}: {}", dtb, reason),
+            SetupError::Config { path, reason } => write!(f, "{}: {}", path, reason),
+            SetupError::Manifest { path, reason } => write!(f, "{}: {}", path, reason),
+            SetupError::Checksum { file, expected, actual } => {
+                write!(f, "{} is corrupted or tampered with: SHA-256 is {}, expected {}", file, actual, expected)
+            },
+            SetupError::Task { reason } => write!(f, "setup step did not finish: {}", reason),
+            SetupError::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for SetupError {}
@@ -0,0 +1,70 @@
+use std::time::{Duration, SystemTime};
+
+use crate::test::setup_error::SetupStep;
+use crate::test::setup_state::SetupState;
+
+// Where setup_workspace is, sent as Signal::SetupProgress whenever a step starts or moves on
+#[derive(Debug, Clone, PartialEq)]
+pub struct SetupProgress {
+    pub step: SetupStep,
+    pub total: usize,
+    // finished in this or an earlier run
+    pub completed: Vec<SetupStep>,
+    // 0.0 ..= 1.0 within the current step, left at 0 by steps that cannot tell
+    pub fraction: f64,
+    // what the step is busy with, e.g. the archive being unpacked
+    pub detail: String,
+    pub started: SystemTime,
+    // how long the current step and the steps after it took last time, when known
+    pub expected: Option<Duration>,
+    pub remaining: Option<Duration>,
+}
+
+impl SetupProgress {
+    pub fn start(step: SetupStep, state: &SetupState) -> SetupProgress {
+        let later = SetupStep::ALL.iter().filter(|s| s.number() > step.number() && !state.is_completed(**s));
+        let remaining = later.map(|s| state.duration(*s)).sum::<Option<Duration>>();
+
+        SetupProgress {
+            step,
+            total: SetupStep::ALL.len(),
+            completed: state.completed.clone(),
+            fraction: 0.0,
+            detail: String::new(),
+            started: SystemTime::now(),
+            expected: state.duration(step),
+            remaining,
+        }
+    }
+
+    pub fn update(&mut self, fraction: f64, detail: &str) {
+        self.fraction = fraction.clamp(0.0, 1.0);
+        self.detail = detail.to_string();
+    }
+
+    pub fn number(&self) -> usize {
+        self.step.number()
+    }
+
+    pub fn name(&self) -> String {
+        self.step.to_string()
+    }
+
+    // share of the whole setup that is done
+    pub fn overall(&self) -> f64 {
+        ((self.completed.len() as f64 + self.fraction) / self.total as f64).min(1.0)
+    }
+
+    // Time left, from the durations of the previous run and, past those, from the step's own
+    // progress. None until there is something to go on.
+    pub fn eta(&self) -> Option<Duration> {
+        let elapsed = self.started.elapsed().unwrap_or_default();
+        let current = match self.expected {
+            Some(expected) if elapsed < expected => expected - elapsed,
+            _ if self.fraction >= 0.05 => elapsed.mul_f64((1.0 - self.fraction) / self.fraction),
+            _ => return None,
+        };
+
+        Some(current + self.remaining?)
+    }
+}
@@ -0,0 +1,71 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::test::setup_error::{SetupError, SetupStep};
+
+// kept in the release directory of the workspace, replaces the old .setup_finished marker
+pub const SETUP_STATE: &str = ".setup_state.toml";
+
+// Progress of setup_workspace, saved after every step so an interrupted setup resumes
+// from the first step that did not finish.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct SetupState {
+    #[serde(default)]
+    pub completed: Vec<SetupStep>,
+    // seconds every step took when it last succeeded, for the ETA of the next run
+    #[serde(default)]
+    pub durations: BTreeMap<SetupStep, u64>,
+    #[serde(skip)]
+    file: String,
+}
+
+impl SetupState {
+    // a missing or unreadable state file means nothing was done yet
+    pub fn load(workspace: &str) -> SetupState {
+        let file = workspace.to_string() + "/" + SETUP_STATE;
+        let mut state: SetupState = fs::read_to_string(&file)
+                                        .ok()
+                                        .and_then(|s| toml::from_str(&s).ok())
+                                        .unwrap_or_default();
+        state.file = file;
+        state
+    }
+
+    pub fn save(&self) -> Result<(), SetupError> {
+        let content = toml::to_string(self).unwrap();
+        // write aside and rename, so an interruption never leaves a truncated state file
+        let temp = self.file.clone() + ".tmp";
+        fs::write(&temp, content).map_err(|error| SetupError::Io { path: temp.clone(), error })?;
+        fs::rename(&temp, &self.file).map_err(|error| SetupError::Io { path: self.file.clone(), error })
+    }
+
+    pub fn is_completed(&self, step: SetupStep) -> bool {
+        self.completed.contains(&step)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        SetupStep::ALL.iter().all(|step| self.is_completed(*step))
+    }
+
+    pub fn duration(&self, step: SetupStep) -> Option<Duration> {
+        self.durations.get(&step).map(|secs| Duration::from_secs(*secs))
+    }
+
+    pub fn complete(&mut self, step: SetupStep, took: Duration) -> Result<(), SetupError> {
+        if !self.is_completed(step) {
+            self.completed.push(step);
+        }
+        self.durations.insert(step, took.as_secs());
+        self.save()
+    }
+
+    // the durations are kept, a rebuild takes about as long as the last build
+    pub fn reset(&mut self) -> Result<(), SetupError> {
+        self.completed.clear();
+        self.save()
+    }
+}
@@ -0,0 +1,235 @@
+use std::{fmt, fs, io};
+
+// where the kernel lists USB devices, one directory per device and interface
+pub const SYSFS_USB_DEVICES: &str = "/sys/bus/usb/devices";
+
+pub const NVIDIA: &str = "0955";
+
+// Mbit/s of USB 2.0 high speed, anything slower cannot flash a board
+pub const HIGH_SPEED: f64 = 480.0;
+
+// A USB device as sysfs describes it
+#[derive(Debug, Clone, PartialEq)]
+pub struct UsbDevice {
+    // port path, the name of the device's directory, e.g. "1-3.2"
+    pub port: String,
+    pub bus: u32,
+    pub dev: u32,
+    pub vendor: String,
+    pub product: String,
+    pub serial: Option<String>,
+    // in Mbit/s as the kernel writes it: "1.5", "12", "480", "5000"...
+    pub speed: String,
+    // bcdUSB, e.g. "2.00"
+    pub version: String,
+    pub manufacturer: Option<String>,
+    // the hubs between the root hub and the device, the one next to the root hub first
+    pub hubs: Vec<Hub>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hub {
+    pub port: String,
+    pub speed: String,
+    pub version: String,
+}
+
+impl Hub {
+    // A USB 3 hub reports bcdUSB 2.10 or more on the USB 2 bus. Below that it is a USB 2.0 only
+    // hub, every board behind it sharing its bandwidth.
+    pub fn is_usb2(&self) -> bool {
+        self.version.parse::<f64>().is_ok_and(|version| version < 2.1)
+    }
+}
+
+// "480" -> 480.0, None for anything else
+pub fn mbps(speed: &str) -> Option<f64> {
+    speed.parse().ok()
+}
+
+#[derive(Debug)]
+pub enum UsbError {
+    Io { path: String, error: io::Error },
+    // an attribute that does not hold what the kernel writes there
+    Parse { path: String, value: String },
+}
+
+impl fmt::Display for UsbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UsbError::Io { path, error } => write!(f, "{}: {}", path, error),
+            UsbError::Parse { path, value } => write!(f, "{}: unexpected value {:?}", path, value),
+        }
+    }
+}
+
+impl std::error::Error for UsbError {}
+
+pub type DeviceRead = Result<UsbDevice, UsbError>;
+
+// Reads USB devices from a sysfs tree, /sys/bus/usb/devices unless another root is given
+// (e.g. a copy of it)
+#[derive(Debug, Clone)]
+pub struct Sysfs {
+    root: String,
+}
+
+impl Default for Sysfs {
+    fn default() -> Self {
+        Sysfs::new(SYSFS_USB_DEVICES)
+    }
+}
+
+impl Sysfs {
+    pub fn new(root: &str) -> Sysfs {
+        Sysfs { root: root.to_string() }
+    }
+
+    // Every connected device, hubs included, with its port path. A device that cannot be read does
+    // not keep the others from being listed.
+    pub fn devices(&self) -> Result<Vec<(String, DeviceRead)>, UsbError> {
+        let entries = fs::read_dir(&self.root).map_err(|error| UsbError::Io { path: self.root.clone(), error })?;
+
+        let mut ports: Vec<String> = entries
+                                        .filter_map(|entry| entry.ok())
+                                        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                                        .filter(|name| is_port(name))
+                                        .collect();
+        ports.sort();
+
+        Ok(ports.into_iter().map(|port| (port.clone(), self.device(&port))).collect())
+    }
+
+    pub fn device(&self, port: &str) -> Result<UsbDevice, UsbError> {
+        let path = self.root.clone() + "/" + port + "/";
+        Ok(UsbDevice {
+            port: port.to_string(),
+            bus: number(&(path.clone() + "busnum"))?,
+            dev: number(&(path.clone() + "devnum"))?,
+            vendor: attribute(&(path.clone() + "idVendor"))?,
+            product: attribute(&(path.clone() + "idProduct"))?,
+            serial: optional(&(path.clone() + "serial"))?,
+            speed: attribute(&(path.clone() + "speed"))?,
+            version: attribute(&(path.clone() + "version"))?,
+            manufacturer: optional(&(path + "manufacturer"))?,
+            hubs: self.hubs(port)?,
+        })
+    }
+
+    fn hubs(&self, port: &str) -> Result<Vec<Hub>, UsbError> {
+        let mut hubs = vec![];
+        for port in parents(port) {
+            let path = self.root.clone() + "/" + &port + "/";
+            hubs.push(Hub {
+                speed: attribute(&(path.clone() + "speed"))?,
+                version: attribute(&(path + "version"))?,
+                port,
+            });
+        }
+        Ok(hubs)
+    }
+}
+
+// "1-3.2.1" is port 1 of the hub on port 2 of the hub on port 3 of root hub 1: "1-3", "1-3.2"
+fn parents(port: &str) -> Vec<String> {
+    let mut parents = vec![];
+    let mut parent = port;
+    while let Some((hub, _)) = parent.rsplit_once('.') {
+        parents.insert(0, hub.to_string());
+        parent = hub;
+    }
+    parents
+}
+
+// "1-3", "1-3.2" are devices; "usb1" is a root hub and "1-3:1.0" an interface
+fn is_port(name: &str) -> bool {
+    name.contains('-') && !name.contains(':')
+}
+
+fn attribute(path: &str) -> Result<String, UsbError> {
+    fs::read_to_string(path)
+        .map(|value| value.trim().to_string())
+        .map_err(|error| UsbError::Io { path: path.to_string(), error })
+}
+
+// an attribute only some devices have
+fn optional(path: &str) -> Result<Option<String>, UsbError> {
+    match attribute(path) {
+        Ok(value) => Ok(Some(value)),
+        Err(UsbError::Io { error, .. }) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn number(path: &str) -> Result<u32, UsbError> {
+    let value = attribute(path)?;
+    value.parse().map_err(|_| UsbError::Parse { path: path.to_string(), value })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::path::Path;
+
+    fn write_device(root: &Path, port: &str, attributes: &[(&str, &str)]) {
+        let dir = root.join(port);
+        fs::create_dir_all(&dir).unwrap();
+        for (name, value) in attributes {
+            fs::write(dir.join(name), format!("{}\n", value)).unwrap();
+        }
+    }
+
+    // a USB 2.0 hub on root port 1, a board in recovery mode behind it and a device whose
+    // busnum cannot be read, next to a root hub and an interface that are not devices
+    #[test]
+    fn reads_devices_and_reports_bad_ones() {
+        let root = std::env::temp_dir().join(format!("sysfs-usb-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        write_device(&root, "usb1", &[("busnum", "1"), ("devnum", "1")]);
+        write_device(&root, "1-1", &[
+            ("busnum", "1"), ("devnum", "2"), ("idVendor", "05e3"), ("idProduct", "0610"), ("speed", "480"), ("version", " 2.00"),
+        ]);
+        write_device(&root, "1-1:1.0", &[("bInterfaceClass", "09")]);
+        write_device(&root, "1-1.2", &[
+            ("busnum", "1"), ("devnum", "5"), ("idVendor", "0955"), ("idProduct", "7323"), ("serial", "1424621055424"),
+            ("speed", "480"), ("version", " 2.00"), ("manufacturer", "NVIDIA Corp."),
+        ]);
+        write_device(&root, "1-4", &[
+            ("busnum", "one"), ("devnum", "3"), ("idVendor", "046d"), ("idProduct", "c52b"), ("speed", "12"), ("version", " 2.00"),
+        ]);
+
+        let sysfs = Sysfs::new(&root.to_string_lossy());
+        let devices = sysfs.devices().unwrap();
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(devices.iter().map(|(port, _)| &port[..]).collect::<Vec<_>>(), ["1-1", "1-1.2", "1-4"]);
+        let hub = devices[0].1.as_ref().unwrap();
+        assert_eq!((&hub.port[..], hub.bus, hub.dev), ("1-1", 1, 2));
+        assert_eq!(hub.serial, None);
+        assert!(hub.hubs.is_empty());
+
+        let board = devices[1].1.as_ref().unwrap();
+        assert_eq!(board, &UsbDevice {
+            port: String::from("1-1.2"),
+            bus: 1,
+            dev: 5,
+            vendor: String::from(NVIDIA),
+            product: String::from("7323"),
+            serial: Some(String::from("1424621055424")),
+            speed: String::from("480"),
+            version: String::from("2.00"),
+            manufacturer: Some(String::from("NVIDIA Corp.")),
+            hubs: vec![Hub { port: String::from("1-1"), speed: String::from("480"), version: String::from("2.00") }],
+        });
+        assert!(board.hubs[0].is_usb2());
+
+        match &devices[2].1 {
+            Err(UsbError::Parse { path, value }) => {
+                assert!(path.ends_with("/1-4/busnum"));
+                assert_eq!(value, "one");
+            },
+            device => panic!("unexpected {:?}", device),
+        }
+    }
+}